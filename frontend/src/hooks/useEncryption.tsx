@@ -50,7 +50,7 @@ export const EncryptionProvider: React.FC<EncryptionProviderProps> = ({ children
   const [encryptionStatus, setEncryptionStatus] = useState<EncryptionStatus | null>(null);
   const [encryptionStrength, setEncryptionStrength] = useState<number>(0);
   const { user } = useAuth();
-  const { sendMessage } = useWebSocket(`ws://localhost:8080/ws${user?.id ? `?user_id=${user.id}` : ''}`);
+  const { sendMessage } = useWebSocket('ws://localhost:8080/ws');
 
   // Check encryption status on mount and when user changes
   useEffect(() => {
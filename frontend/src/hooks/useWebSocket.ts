@@ -4,7 +4,7 @@ import { useAuth } from './useAuth';
 type ConnectionStatus = 'Connecting' | 'Connected' | 'Disconnected';
 
 interface WebSocketMessage {
-  message_type: 'new_email' | 'email_read' | 'typing_indicator' | 'user_online' | 'user_offline' | 'encryption_status' | 'key_rotation' | 'error';
+  message_type: 'new_email' | 'email_read' | 'typing_indicator' | 'user_online' | 'user_offline' | 'encryption_status' | 'key_rotation' | 'authenticate' | 'authenticated' | 'error';
   sender_id?: string;
   recipient_id?: string;
   payload: any;
@@ -32,7 +32,9 @@ export const useWebSocket = (url: string) => {
 
     try {
       setConnectionStatus('Connecting');
-      const ws = new WebSocket(url);
+      // The server only accepts connections carrying a session token
+      const token = localStorage.getItem('authToken');
+      const ws = new WebSocket(token ? `${url}${url.includes('?') ? '&' : '?'}token=${encodeURIComponent(token)}` : url);
 
       ws.onopen = () => {
         console.log('WebSocket connection established');
@@ -0,0 +1,492 @@
+// src/api/attachments.rs
+use std::sync::Arc;
+
+use axum::body::{Bytes, StreamBody};
+use axum::extract::{BodyStream, Path, Query};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Extension, Json, Router};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::api::auth::AuthUser;
+use crate::api::emails::{find_email, find_wrapped_key};
+use crate::api::encryption::{content_suite, has_suite};
+use crate::database::models::{Email, EmailAttachment};
+use crate::quantum_encryption::decryption::DecryptionService;
+use crate::quantum_encryption::encryption::WrappedKey;
+use crate::quantum_encryption::kdf::{KeyPurpose, SHARED_CONTENT_KEY};
+use crate::quantum_encryption::secret::SecretKey;
+use crate::quantum_encryption::signing::EmailHeader;
+use crate::quantum_encryption::stream::{StreamDecryptor, StreamEncryptor, StreamHeader, CHUNK_SIZE};
+use crate::utils::error_handling::AppError;
+use crate::AppState;
+
+/// Routes for `/api/emails/:id/attachments`, merged into the email routes
+pub fn routes() -> Router {
+    Router::new()
+        .route("/:id/attachments", get(list_attachments).post(upload_attachment))
+        .route("/:id/attachments/:attachment_id", get(download_attachment))
+}
+
+/// Attachment metadata; the content is fetched through the download route
+#[derive(Debug, Serialize)]
+pub struct AttachmentInfo {
+    pub attachment_id: Uuid,
+    pub email_id: Uuid,
+    pub filename: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+}
+
+impl From<EmailAttachment> for AttachmentInfo {
+    fn from(attachment: EmailAttachment) -> Self {
+        Self {
+            attachment_id: attachment.attachment_id,
+            email_id: attachment.email_id,
+            filename: attachment.filename,
+            content_type: attachment.content_type,
+            size_bytes: attachment.size_bytes,
+        }
+    }
+}
+
+/// Query string of an upload; the raw file is the request body
+#[derive(Debug, Deserialize)]
+pub struct UploadAttachmentQuery {
+    pub filename: String,
+}
+
+/// Lists the attachments of an email the user sent or received
+async fn list_attachments(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(email_id): Path<Uuid>,
+) -> Result<Json<Vec<AttachmentInfo>>, AppError> {
+    find_email(&state.db_pool, user.user_id, email_id).await?
+        .ok_or_else(|| AppError::NotFoundError("Email not found".to_string()))?;
+
+    let attachments = sqlx::query_as::<_, EmailAttachment>(
+        "SELECT * FROM email_attachments WHERE email_id = $1 ORDER BY filename, attachment_id",
+    )
+        .bind(email_id)
+        .fetch_all(&state.db_pool)
+        .await?;
+    Ok(Json(attachments.into_iter().map(AttachmentInfo::from).collect()))
+}
+
+/// Encrypts an uploaded file chunk by chunk as it arrives and stores it with the email
+///
+/// Only the sender can attach files. The body is read as a stream, so the file is never
+/// held in memory as a whole, and uploads over `max_attachment_bytes` are rejected.
+async fn upload_attachment(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(email_id): Path<Uuid>,
+    Query(query): Query<UploadAttachmentQuery>,
+    headers: HeaderMap,
+    mut body: BodyStream,
+) -> Result<(StatusCode, Json<AttachmentInfo>), AppError> {
+    let max_bytes = state.config.server.max_attachment_bytes;
+    let declared_length = headers.get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if declared_length.is_some_and(|length| length > max_bytes) {
+        return Err(too_large(max_bytes));
+    }
+
+    let filename = query.filename.trim().to_string();
+    if filename.is_empty() || filename.len() > 255 {
+        return Err(AppError::ValidationError("Filename must be 1 to 255 bytes".to_string()));
+    }
+    let content_type = match headers.get(header::CONTENT_TYPE) {
+        Some(value) => value.to_str()
+            .map_err(|_| AppError::ValidationError("Content type is not valid".to_string()))?
+            .to_string(),
+        None => "application/octet-stream".to_string(),
+    };
+    if content_type.is_empty() || content_type.len() > 100 {
+        return Err(AppError::ValidationError("Content type must be 1 to 100 bytes".to_string()));
+    }
+
+    let email = find_email(&state.db_pool, user.user_id, email_id).await?
+        .ok_or_else(|| AppError::NotFoundError("Email not found".to_string()))?;
+    if email.sender_id != user.user_id {
+        return Err(AppError::AuthorizationError("Only the sender can attach files to an email".to_string()));
+    }
+
+    let key = attachment_key(&state, &email, user.user_id).await?;
+    let attachment_id = Uuid::new_v4();
+    let mut encryptor = StreamEncryptor::new(key.expose_secret(), &associated_data(email_id, attachment_id));
+
+    let mut tx = state.db_pool.begin().await?;
+    sqlx::query(
+        "INSERT INTO email_attachments (attachment_id, email_id, filename, content_type, encrypted_content, size_bytes)
+         VALUES ($1, $2, $3, $4, $5, 0)",
+    )
+        .bind(attachment_id)
+        .bind(email_id)
+        .bind(&filename)
+        .bind(&content_type)
+        .bind(encryptor.header().to_bytes())
+        .execute(&mut *tx)
+        .await?;
+
+    // Full chunks are sealed as soon as a byte past them arrives; the rest becomes the last chunk
+    let mut size: u64 = 0;
+    let mut chunk_index: i32 = 0;
+    let mut buffer = Vec::with_capacity(2 * CHUNK_SIZE);
+    while let Some(bytes) = body.next().await {
+        let bytes = bytes.map_err(|e| AppError::ValidationError(format!("Failed to read attachment: {}", e)))?;
+        size += bytes.len() as u64;
+        if size > max_bytes {
+            return Err(too_large(max_bytes));
+        }
+        buffer.extend_from_slice(&bytes);
+        while buffer.len() > CHUNK_SIZE {
+            let sealed = encryptor.encrypt_next(&buffer[..CHUNK_SIZE])
+                .map_err(|e| AppError::EncryptionError(e.to_string()))?;
+            buffer.drain(..CHUNK_SIZE);
+            insert_chunk(&mut tx, attachment_id, chunk_index, &sealed).await?;
+            chunk_index += 1;
+        }
+    }
+    let sealed = encryptor.encrypt_last(&buffer)
+        .map_err(|e| AppError::EncryptionError(e.to_string()))?;
+    insert_chunk(&mut tx, attachment_id, chunk_index, &sealed).await?;
+
+    sqlx::query("UPDATE email_attachments SET size_bytes = $1 WHERE attachment_id = $2")
+        .bind(size as i64)
+        .bind(attachment_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    info!("Stored attachment {} ({} bytes, {} chunks) on email {}", attachment_id, size, chunk_index + 1, email_id);
+    Ok((StatusCode::CREATED, Json(AttachmentInfo {
+        attachment_id,
+        email_id,
+        filename,
+        content_type,
+        size_bytes: size as i64,
+    })))
+}
+
+/// Streams an attachment back, decrypting one chunk at a time
+///
+/// The first chunk is opened before the response starts, so a wrong key is reported as an
+/// error. A chunk that fails to authenticate later aborts the transfer.
+async fn download_attachment(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path((email_id, attachment_id)): Path<(Uuid, Uuid)>,
+) -> Result<Response, AppError> {
+    let email = find_email(&state.db_pool, user.user_id, email_id).await?
+        .ok_or_else(|| AppError::NotFoundError("Email not found".to_string()))?;
+    let attachment = sqlx::query_as::<_, EmailAttachment>(
+        "SELECT * FROM email_attachments WHERE attachment_id = $1 AND email_id = $2",
+    )
+        .bind(attachment_id)
+        .bind(email_id)
+        .fetch_optional(&state.db_pool)
+        .await?
+        .ok_or_else(|| AppError::NotFoundError("Attachment not found".to_string()))?;
+
+    let stream_header = StreamHeader::parse(&attachment.encrypted_content)
+        .map_err(|e| AppError::DecryptionError(e.to_string()))?;
+    let key = attachment_key(&state, &email, user.user_id).await?;
+    let (chunk_count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM email_attachment_chunks WHERE attachment_id = $1")
+        .bind(attachment_id)
+        .fetch_one(&state.db_pool)
+        .await?;
+
+    let mut reader = ChunkReader {
+        pool: state.db_pool.clone(),
+        attachment_id,
+        next_index: 0,
+        chunk_count: chunk_count as i32,
+        decryptor: Some(StreamDecryptor::new(key.expose_secret(), stream_header, &associated_data(email_id, attachment_id))),
+    };
+    let first = reader.next_chunk().await?
+        .ok_or_else(|| AppError::DecryptionError("Attachment has no content".to_string()))?;
+
+    let rest = stream::unfold(reader, |mut reader| async move {
+        match reader.next_chunk().await {
+            Ok(Some(chunk)) => Some((Ok(Bytes::from(chunk)), reader)),
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Aborting download of attachment {}: {}", reader.attachment_id, e);
+                reader.next_index = reader.chunk_count;
+                Some((Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string())), reader))
+            }
+        }
+    });
+    let body = StreamBody::new(stream::once(async move { Ok(Bytes::from(first)) }).chain(rest));
+
+    debug!("Streaming attachment {} to user {}", attachment_id, user.user_id);
+    Ok((
+        [
+            (header::CONTENT_TYPE, attachment.content_type),
+            (header::CONTENT_LENGTH, attachment.size_bytes.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", header_safe(&attachment.filename))),
+        ],
+        body,
+    ).into_response())
+}
+
+/// Reads and opens the chunks of one attachment in order
+struct ChunkReader {
+    pool: Pool<Postgres>,
+    attachment_id: Uuid,
+    next_index: i32,
+    chunk_count: i32,
+    /// Taken when the last chunk is opened
+    decryptor: Option<StreamDecryptor>,
+}
+
+impl ChunkReader {
+    async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, AppError> {
+        if self.next_index >= self.chunk_count {
+            return Ok(None);
+        }
+        let (data,): (Vec<u8>,) = sqlx::query_as(
+            "SELECT data FROM email_attachment_chunks WHERE attachment_id = $1 AND chunk_index = $2",
+        )
+            .bind(self.attachment_id)
+            .bind(self.next_index)
+            .fetch_one(&self.pool)
+            .await?;
+        self.next_index += 1;
+
+        let plaintext = if self.next_index == self.chunk_count {
+            self.decryptor.take().map(|decryptor| decryptor.decrypt_last(&data))
+        } else {
+            self.decryptor.as_mut().map(|decryptor| decryptor.decrypt_next(&data))
+        };
+        plaintext
+            .ok_or_else(|| AppError::DecryptionError("Attachment stream already ended".to_string()))?
+            .map(Some)
+            .map_err(|e| AppError::DecryptionError(e.to_string()))
+    }
+}
+
+/// Derives the attachment key of an email from its encapsulated secret
+///
+/// Multi-recipient mail uses the content key wrapped for the user. Single-recipient mail
+/// uses the recipient's key of the body's suite that opens the body, so sender and
+/// recipient get the same key.
+async fn attachment_key(state: &AppState, email: &Email, user_id: Uuid) -> Result<SecretKey, AppError> {
+    let decryption_service = DecryptionService::new(&state.config.encryption);
+    let mut header = EmailHeader {
+        email_id: email.email_id,
+        sender_id: email.sender_id,
+        recipient_id: email.recipient_id,
+        subject: email.subject.clone(),
+    };
+
+    if email.encryption_method == SHARED_CONTENT_KEY {
+        let recipient = find_wrapped_key(&state.db_pool, user_id, email.email_id).await?
+            .ok_or_else(|| AppError::DecryptionError("No content key was wrapped for this user".to_string()))?;
+        let key_pair = state.key_store.key_pair_history(user_id).await?
+            .into_iter()
+            .find(|key_pair| key_pair.id == recipient.key_id)
+            .ok_or_else(|| AppError::DecryptionError("The key the content key was wrapped for is gone".to_string()))?;
+        let wrapped_key = WrappedKey {
+            recipient_id: recipient.recipient_id,
+            encryption_method: recipient.encryption_method,
+            kem_ciphertext: recipient.kem_ciphertext,
+            wrapped_key: recipient.wrapped_key,
+        };
+        header.recipient_id = Uuid::nil();
+        return decryption_service.shared_key(&wrapped_key, &key_pair, &header, KeyPurpose::Attachment)
+            .map_err(|e| AppError::DecryptionError(e.to_string()));
+    }
+
+    // As in decrypt, only keys of the suite the body was encrypted under are tried, and of
+    // those only keys that existed when the email was stored, newest first
+    let suite = content_suite(&email.encrypted_content, Some(&email.encryption_method))?;
+    let mut keys: Vec<_> = state.key_store.key_pair_history(email.recipient_id).await?
+        .into_iter()
+        .filter(|key_pair| suite.is_none_or(|suite| has_suite(key_pair, suite)) && key_pair.created_at <= email.timestamp)
+        .collect();
+    keys.sort_by_key(|key_pair| std::cmp::Reverse(key_pair.created_at));
+    for key_pair in keys {
+        let opens_body = decryption_service
+            .decrypt_email(&email.encrypted_content, &email.encrypted_shared_secret, &key_pair, &header, None, None)
+            .is_ok();
+        if opens_body {
+            return decryption_service
+                .message_key(&email.encrypted_content, &email.encrypted_shared_secret, &key_pair, &header, KeyPurpose::Attachment)
+                .map_err(|e| AppError::DecryptionError(e.to_string()));
+        }
+    }
+    Err(AppError::DecryptionError("No key of the recipient opens this email".to_string()))
+}
+
+async fn insert_chunk(
+    tx: &mut sqlx::Transaction<'_, Postgres>,
+    attachment_id: Uuid,
+    chunk_index: i32,
+    data: &[u8],
+) -> Result<(), AppError> {
+    sqlx::query("INSERT INTO email_attachment_chunks (attachment_id, chunk_index, data) VALUES ($1, $2, $3)")
+        .bind(attachment_id)
+        .bind(chunk_index)
+        .bind(data)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Binds every chunk to its email and attachment, so chunks cannot be moved between them
+fn associated_data(email_id: Uuid, attachment_id: Uuid) -> Vec<u8> {
+    [email_id.as_bytes().as_slice(), attachment_id.as_bytes()].concat()
+}
+
+/// Replaces anything that cannot appear in a quoted header parameter
+fn header_safe(filename: &str) -> String {
+    filename
+        .chars()
+        .map(|c| if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' { c } else { '_' })
+        .collect()
+}
+
+fn too_large(max_bytes: u64) -> AppError {
+    AppError::PayloadTooLarge(format!("Attachments are limited to {} bytes", max_bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_support::{app, insert_user, login, send_as, send_encrypted_email, test_state};
+    use axum::body::Body;
+    use axum::http::{Method, Request};
+    use tower::ServiceExt;
+
+    async fn upload(app: Router, token: &str, uri: &str, content: Vec<u8>) -> (StatusCode, serde_json::Value) {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .header(header::CONTENT_TYPE, "application/pdf")
+            .body(Body::from(content))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    async fn download(app: Router, token: &str, uri: &str) -> (StatusCode, HeaderMap, Bytes) {
+        let request = Request::builder()
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        (status, headers, hyper::body::to_bytes(response.into_body()).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_attachment_roundtrip() {
+        let state = test_state().await;
+        let sender = insert_user(&state).await;
+        let recipient = insert_user(&state).await;
+        let outsider = insert_user(&state).await;
+        let sender_token = login(&state, &sender).await;
+        let recipient_token = login(&state, &recipient).await;
+        let outsider_token = login(&state, &outsider).await;
+        for token in [&sender_token, &recipient_token] {
+            send_as(app(state.clone()), Some(token), Method::POST, "/encryption/generate-key-pair", None).await;
+        }
+
+        let email = send_encrypted_email(&state, &sender_token, &recipient).await;
+        let uri = format!("/emails/{}/attachments", email["email_id"].as_str().unwrap());
+
+        // Spans several chunks, the last one partial
+        let content: Vec<u8> = (0..150_000u32).map(|i| (i % 251) as u8).collect();
+        let (status, attachment) = upload(app(state.clone()), &sender_token, &format!("{}?filename=report.pdf", uri), content.clone()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(attachment["size_bytes"], 150_000);
+        let attachment_id: Uuid = attachment["attachment_id"].as_str().unwrap().parse().unwrap();
+
+        let (chunks,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM email_attachment_chunks WHERE attachment_id = $1")
+            .bind(attachment_id)
+            .fetch_one(&state.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(chunks, 3);
+
+        // Only the sender attaches files
+        let (status, _) = upload(app(state.clone()), &recipient_token, &format!("{}?filename=reply.pdf", uri), vec![1]).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, listed) = send_as(app(state.clone()), Some(&recipient_token), Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(listed.as_array().unwrap().len(), 1);
+        assert_eq!(listed[0]["filename"], "report.pdf");
+        assert!(listed[0].get("encrypted_content").is_none());
+
+        // A key the recipient generates later is not used for mail that predates it
+        send_as(app(state.clone()), Some(&recipient_token), Method::POST, "/encryption/generate-key-pair", None).await;
+        let download_uri = format!("{}/{}", uri, attachment_id);
+        for token in [&recipient_token, &sender_token] {
+            let (status, headers, body) = download(app(state.clone()), token, &download_uri).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(headers[header::CONTENT_TYPE], "application/pdf");
+            assert_eq!(headers[header::CONTENT_DISPOSITION], "attachment; filename=\"report.pdf\"");
+            assert_eq!(body.as_ref(), content.as_slice());
+        }
+
+        let (status, _, _) = download(app(state.clone()), &outsider_token, &download_uri).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // A tampered chunk is never served
+        sqlx::query("UPDATE email_attachment_chunks SET data = $1 WHERE attachment_id = $2 AND chunk_index = 0")
+            .bind(vec![0u8; CHUNK_SIZE + 16])
+            .bind(attachment_id)
+            .execute(&state.db_pool)
+            .await
+            .unwrap();
+        let (status, _, _) = download(app(state), &recipient_token, &download_uri).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_attachment_size_limit() {
+        let mut state = Arc::try_unwrap(test_state().await).ok().expect("state is not shared yet");
+        state.config.server.max_attachment_bytes = 1000;
+        let state = Arc::new(state);
+        let sender = insert_user(&state).await;
+        let recipient = insert_user(&state).await;
+        let sender_token = login(&state, &sender).await;
+        send_as(app(state.clone()), Some(&sender_token), Method::POST, "/encryption/generate-key-pair", None).await;
+        let recipient_token = login(&state, &recipient).await;
+        send_as(app(state.clone()), Some(&recipient_token), Method::POST, "/encryption/generate-key-pair", None).await;
+
+        let email = send_encrypted_email(&state, &sender_token, &recipient).await;
+        let email_id: Uuid = email["email_id"].as_str().unwrap().parse().unwrap();
+
+        let uri = format!("/emails/{}/attachments?filename=big.bin", email_id);
+        let (status, body) = upload(app(state.clone()), &sender_token, &uri, vec![0; 1001]).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body["error"], "PAYLOAD_TOO_LARGE");
+
+        // Nothing of the rejected upload is kept
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM email_attachments WHERE email_id = $1")
+            .bind(email_id)
+            .fetch_one(&state.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+
+        let (status, _) = upload(app(state), &sender_token, &uri, vec![0; 1000]).await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+}
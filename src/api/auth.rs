@@ -0,0 +1,341 @@
+// src/api/auth.rs
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use argon2::password_hash::rand_core::OsRng as PasswordRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::{Extension, Json, Router};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use tracing::{debug, info};
+
+use crate::database::models::{User, UserSession};
+use crate::utils::error_handling::AppError;
+use crate::utils::logging::log_security_event;
+use crate::AppState;
+
+/// How long a session token stays valid after login
+const SESSION_DURATION_HOURS: i64 = 24;
+
+/// Minimum accepted password length
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Hash checked for unknown emails, with the default parameters, so a failed login takes as
+/// long whether or not the account exists
+const DUMMY_PASSWORD_HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$dRliap4WCe7ZR54v6tYFKA$QKJcQMg99nGyfcPg7zAyYKifhmfRuBZQeFCZJL5wuRs";
+
+/// Routes for `/api/auth`
+pub fn routes() -> Router {
+    Router::new()
+        .route("/register", post(register))
+        .route("/login", post(login))
+}
+
+/// Request body for creating an account
+#[derive(Debug, Deserialize)]
+pub struct RegisterRequest {
+    pub username: String,
+    pub email: String,
+    pub password: String,
+}
+
+/// Request body for logging in
+#[derive(Debug, Deserialize)]
+pub struct LoginRequest {
+    pub email: String,
+    pub password: String,
+}
+
+/// Response returned by register and login, as expected by authService.ts
+#[derive(Debug, Serialize)]
+pub struct AuthResponse {
+    pub token: String,
+    pub expires_at: OffsetDateTime,
+    pub user: User,
+}
+
+/// Extractor resolving the `Authorization: Bearer <token>` header to the session's user
+///
+/// Rejects the request with `AppError::AuthenticationError` when the header is
+/// missing or the token does not belong to a live session.
+pub struct AuthUser(pub User);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Extension(app_state) = Extension::<Arc<AppState>>::from_request_parts(parts, state)
+            .await
+            .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+
+        let token = bearer_token(&parts.headers)
+            .ok_or_else(|| AppError::AuthenticationError("Missing bearer token".to_string()))?;
+
+        authenticate_session(&app_state.db_pool, token).await.map(AuthUser)
+    }
+}
+
+/// Reads the token of an `Authorization: Bearer <token>` header
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Resolves a session token to its user and refreshes the session's activity timestamp
+pub async fn authenticate_session(db_pool: &PgPool, token: &str) -> Result<User, AppError> {
+    let token_hash = hash_token(token);
+    let user = sqlx::query_as::<_, User>(
+        "SELECT u.* FROM user_sessions s JOIN users u ON u.user_id = s.user_id
+         WHERE s.token = $1 AND s.expires_at > $2",
+    )
+        .bind(&token_hash)
+        .bind(OffsetDateTime::now_utc())
+        .fetch_optional(db_pool)
+        .await?
+        .ok_or_else(|| AppError::AuthenticationError("Invalid or expired session token".to_string()))?;
+
+    sqlx::query("UPDATE user_sessions SET last_active_at = $1 WHERE token = $2")
+        .bind(OffsetDateTime::now_utc())
+        .bind(&token_hash)
+        .execute(db_pool)
+        .await?;
+
+    debug!("Authenticated session for user {}", user.user_id);
+    Ok(user)
+}
+
+/// Creates an account with a hashed password and opens a session for it
+async fn register(
+    Extension(state): Extension<Arc<AppState>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(request): Json<RegisterRequest>,
+) -> Result<(StatusCode, Json<AuthResponse>), AppError> {
+    let username = request.username.trim().to_string();
+    let email = request.email.trim().to_lowercase();
+    if username.is_empty() || username.len() > 255 {
+        return Err(AppError::ValidationError("Username must be 1 to 255 bytes".to_string()));
+    }
+    if !email.contains('@') || email.len() > 255 {
+        return Err(AppError::ValidationError("Invalid email address".to_string()));
+    }
+    if request.password.len() < MIN_PASSWORD_LENGTH {
+        return Err(AppError::ValidationError(format!(
+            "Password must be at least {} characters", MIN_PASSWORD_LENGTH
+        )));
+    }
+
+    let password_hash = hash_password(&request.password)?;
+    let user = User::new(username, email, Vec::new(), "password".to_string());
+
+    sqlx::query(
+        "INSERT INTO users (user_id, username, email, quantum_public_key, authentication_method, password_hash, created_at, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+    )
+        .bind(user.user_id)
+        .bind(&user.username)
+        .bind(&user.email)
+        .bind(&user.quantum_public_key)
+        .bind(&user.authentication_method)
+        .bind(&password_hash)
+        .bind(user.created_at)
+        .bind(user.updated_at)
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| match e.as_database_error().and_then(|db| db.code()) {
+            Some(code) if code == "23505" => {
+                AppError::ValidationError("Username or email is already registered".to_string())
+            }
+            _ => AppError::DatabaseError(e),
+        })?;
+
+    info!("Registered user {}", user.user_id);
+    let (token, session) = create_session(&state, user.user_id, connect_info, &headers).await?;
+    Ok((StatusCode::CREATED, Json(AuthResponse { token, expires_at: session.expires_at, user })))
+}
+
+/// Verifies an email/password pair and opens a new session
+async fn login(
+    Extension(state): Extension<Arc<AppState>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(request): Json<LoginRequest>,
+) -> Result<Json<AuthResponse>, AppError> {
+    let email = request.email.trim().to_lowercase();
+    let row: Option<(Option<String>,)> = sqlx::query_as("SELECT password_hash FROM users WHERE email = $1")
+        .bind(&email)
+        .fetch_optional(&state.db_pool)
+        .await?;
+
+    let (password_hash, exists) = match &row {
+        Some((Some(hash),)) => (hash.as_str(), true),
+        _ => (DUMMY_PASSWORD_HASH, false),
+    };
+    let verified = verify_password(&request.password, password_hash) && exists;
+    if !verified {
+        log_security_event("login_failed", None, &format!("Failed login for {}", email));
+        return Err(AppError::AuthenticationError("Invalid email or password".to_string()));
+    }
+
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
+        .bind(&email)
+        .fetch_one(&state.db_pool)
+        .await?;
+
+    info!("User {} logged in", user.user_id);
+    let (token, session) = create_session(&state, user.user_id, connect_info, &headers).await?;
+    Ok(Json(AuthResponse { token, expires_at: session.expires_at, user }))
+}
+
+/// Stores a new session and returns the plaintext token handed to the client
+///
+/// Only a SHA3-256 hash of the token is stored, so a database leak does not expose live sessions.
+pub(crate) async fn create_session(
+    state: &AppState,
+    user_id: uuid::Uuid,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: &HeaderMap,
+) -> Result<(String, UserSession), AppError> {
+    let mut token_bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut token_bytes);
+    let token = hex::encode(token_bytes);
+
+    let ip_address = connect_info
+        .map(|ConnectInfo(addr)| addr.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let user_agent: String = headers
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("unknown")
+        .chars()
+        .take(255)
+        .collect();
+
+    let session = UserSession::new(user_id, hash_token(&token), SESSION_DURATION_HOURS, ip_address, user_agent);
+    sqlx::query(
+        "INSERT INTO user_sessions (session_id, user_id, token, created_at, expires_at, last_active_at, ip_address, user_agent)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+    )
+        .bind(session.session_id)
+        .bind(session.user_id)
+        .bind(&session.token)
+        .bind(session.created_at)
+        .bind(session.expires_at)
+        .bind(session.last_active_at)
+        .bind(&session.ip_address)
+        .bind(&session.user_agent)
+        .execute(&state.db_pool)
+        .await?;
+
+    debug!("Created session {} for user {}", session.session_id, user_id);
+    Ok((token, session))
+}
+
+/// Hashes a session token for storage and lookup
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha3_256::digest(token.as_bytes()))
+}
+
+/// Hashes a password with Argon2id and a random salt, in PHC string format
+fn hash_password(password: &str) -> Result<String, AppError> {
+    let salt = SaltString::generate(&mut PasswordRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AppError::InternalServerError(format!("Failed to hash password: {}", e)))
+}
+
+/// Checks a password against a stored PHC hash string
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_support::{app, send, test_state};
+    use axum::http::Method;
+    use serde_json::json;
+
+    #[test]
+    fn test_dummy_hash_is_checked_in_full() {
+        let hash = PasswordHash::new(DUMMY_PASSWORD_HASH).unwrap();
+        let own = hash_password("correct horse battery").unwrap();
+        assert_eq!(hash.params, PasswordHash::new(&own).unwrap().params);
+        assert!(!verify_password("correct horse battery", DUMMY_PASSWORD_HASH));
+    }
+
+    #[test]
+    fn test_password_hashing() {
+        let hash = hash_password("correct horse battery").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse battery", &hash));
+        assert!(!verify_password("wrong password", &hash));
+        assert!(!verify_password("correct horse battery", "not a phc string"));
+    }
+
+    #[tokio::test]
+    async fn test_register_login_and_authenticate() {
+        let state = test_state().await;
+        let tag = uuid::Uuid::new_v4().simple().to_string();
+        let email = format!("{}@example.com", tag);
+
+        let (status, body) = send(app(state.clone()), Method::POST, "/auth/register", Some(json!({
+            "username": format!("user_{}", tag),
+            "email": email,
+            "password": "quantum-secret",
+        }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let user_id = body["user"]["user_id"].as_str().unwrap().to_string();
+        assert!(body["user"].get("password_hash").is_none());
+
+        let (status, body) = send(app(state.clone()), Method::POST, "/auth/login", Some(json!({
+            "email": email,
+            "password": "quantum-secret",
+        }))).await;
+        assert_eq!(status, StatusCode::OK);
+        let token = body["token"].as_str().unwrap().to_string();
+
+        let user = authenticate_session(&state.db_pool, &token).await.unwrap();
+        assert_eq!(user.user_id.to_string(), user_id);
+
+        let (status, body) = send(app(state.clone()), Method::POST, "/auth/login", Some(json!({
+            "email": email,
+            "password": "wrong-password",
+        }))).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "AUTHENTICATION_ERROR");
+
+        let (status, _) = send(app(state.clone()), Method::POST, "/auth/register", Some(json!({
+            "username": format!("other_{}", tag),
+            "email": email,
+            "password": "quantum-secret",
+        }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_token_rejected() {
+        let state = test_state().await;
+        let result = authenticate_session(&state.db_pool, "not-a-session-token").await;
+        assert!(matches!(result, Err(AppError::AuthenticationError(_))));
+    }
+}
@@ -0,0 +1,135 @@
+// src/api/backup.rs
+use std::sync::Arc;
+
+use axum::routing::post;
+use axum::{Extension, Json, Router};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::info;
+
+use crate::api::auth::AuthUser;
+use crate::quantum_encryption::key_backup::{KeyBackup, RestoreReport};
+use crate::utils::error_handling::AppError;
+use crate::AppState;
+
+/// Routes for `/api/encryption/backup`, merged into the encryption routes
+pub fn routes() -> Router {
+    Router::new()
+        .route("/backup/export", post(export_backup))
+        .route("/backup/import", post(import_backup))
+}
+
+/// Request body for exporting the user's keys
+#[derive(Debug, Deserialize)]
+pub struct ExportBackupRequest {
+    pub passphrase: String,
+}
+
+/// A sealed key backup, base64 encoded
+#[derive(Debug, Serialize)]
+pub struct ExportBackupResponse {
+    pub archive: String,
+    pub key_count: usize,
+    pub exported_at: OffsetDateTime,
+}
+
+/// Request body for restoring keys from a sealed backup
+#[derive(Debug, Deserialize)]
+pub struct ImportBackupRequest {
+    pub archive: String,
+    pub passphrase: String,
+}
+
+/// Seals all of the user's quantum keys under a passphrase
+async fn export_backup(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Json(request): Json<ExportBackupRequest>,
+) -> Result<Json<ExportBackupResponse>, AppError> {
+    let backup = KeyBackup::export(state.key_store.as_ref(), user.user_id).await?;
+    if backup.keys.is_empty() {
+        return Err(AppError::NotFoundError("No quantum keys to back up".to_string()));
+    }
+    // Deriving the sealing key from the passphrase is slow, so keep it off the async workers
+    let (backup, archive) = tokio::task::spawn_blocking(move || {
+        let archive = backup.seal(&request.passphrase);
+        (backup, archive)
+    })
+        .await
+        .map_err(|e| AppError::InternalServerError(e.to_string()))?;
+    let archive = archive.map_err(|e| AppError::ValidationError(e.to_string()))?;
+
+    info!("Exported {} quantum keys of user {}", backup.keys.len(), user.user_id);
+    Ok(Json(ExportBackupResponse {
+        archive: BASE64.encode(archive),
+        key_count: backup.keys.len(),
+        exported_at: backup.exported_at,
+    }))
+}
+
+/// Merges the keys of a backup into the user's keys without replacing any stored key
+async fn import_backup(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Json(request): Json<ImportBackupRequest>,
+) -> Result<Json<RestoreReport>, AppError> {
+    let archive = BASE64.decode(&request.archive)
+        .map_err(|e| AppError::ValidationError(format!("Field archive is not valid base64: {}", e)))?;
+    // The claimed owner is checked before the slow passphrase derivation, and again after
+    let owner = KeyBackup::owner(&archive).map_err(|e| AppError::ValidationError(e.to_string()))?;
+    if owner != user.user_id {
+        return Err(AppError::AuthorizationError("Key backup belongs to another user".to_string()));
+    }
+    let backup = tokio::task::spawn_blocking(move || KeyBackup::open(&archive, &request.passphrase))
+        .await
+        .map_err(|e| AppError::InternalServerError(e.to_string()))?
+        .map_err(|e| AppError::ValidationError(e.to_string()))?;
+    if backup.user_id != user.user_id {
+        return Err(AppError::AuthorizationError("Key backup belongs to another user".to_string()));
+    }
+
+    Ok(Json(backup.restore(state.key_store.as_ref()).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::test_support::{app, insert_user, login, send_as, test_state};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_export_and_import_backup() {
+        let state = test_state().await;
+        let alice = insert_user(&state).await;
+        let bob = insert_user(&state).await;
+        let alice_token = login(&state, &alice).await;
+        let bob_token = login(&state, &bob).await;
+        let passphrase = "a long backup passphrase";
+
+        let (status, _) = send_as(app(state.clone()), Some(&alice_token), Method::POST, "/encryption/backup/export", Some(json!({ "passphrase": passphrase }))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (_, key) = send_as(app(state.clone()), Some(&alice_token), Method::POST, "/encryption/generate-key-pair", None).await;
+
+        let (status, _) = send_as(app(state.clone()), Some(&alice_token), Method::POST, "/encryption/backup/export", Some(json!({ "passphrase": "short" }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, exported) = send_as(app(state.clone()), Some(&alice_token), Method::POST, "/encryption/backup/export", Some(json!({ "passphrase": passphrase }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(exported["key_count"], 1);
+
+        // Importing into the same account changes nothing
+        let import = json!({ "archive": exported["archive"], "passphrase": passphrase });
+        let (status, report) = send_as(app(state.clone()), Some(&alice_token), Method::POST, "/encryption/backup/import", Some(import.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["restored"], json!([]));
+        assert_eq!(report["skipped"], json!([key["key_id"]]));
+
+        let wrong = json!({ "archive": exported["archive"], "passphrase": "not the passphrase" });
+        let (status, body) = send_as(app(state.clone()), Some(&alice_token), Method::POST, "/encryption/backup/import", Some(wrong)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["message"].as_str().unwrap().contains("wrong passphrase"));
+        let (status, _) = send_as(app(state), Some(&bob_token), Method::POST, "/encryption/backup/import", Some(import)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,529 @@
+// src/api/contacts.rs
+use std::sync::Arc;
+
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Postgres};
+use time::OffsetDateTime;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::api::auth::AuthUser;
+use crate::api::directory::published_key;
+use crate::database::models::Contact;
+use crate::quantum_encryption::fingerprint::Fingerprint;
+use crate::quantum_encryption::key_exchange::KeyPair;
+use crate::utils::error_handling::AppError;
+use crate::AppState;
+
+/// Routes for `/api/contacts`
+pub fn routes() -> Router {
+    Router::new()
+        .route("/", get(list_contacts).post(create_contact))
+        .route("/:id", get(get_contact).patch(update_contact).delete(delete_contact))
+        .route("/:id/key", get(check_key))
+        .route("/:id/verify", post(verify_contact))
+}
+
+/// Request body for adding a contact; it is linked to the user registered under `email`, if any
+#[derive(Debug, Deserialize)]
+pub struct CreateContactRequest {
+    pub name: String,
+    pub email: String,
+}
+
+/// Request body for renaming a contact or changing its address
+#[derive(Debug, Deserialize)]
+pub struct UpdateContactRequest {
+    pub name: Option<String>,
+    pub email: Option<String>,
+}
+
+/// The fingerprint the user compared out of band, as shown by the contact
+#[derive(Debug, Deserialize)]
+pub struct VerifyContactRequest {
+    pub fingerprint: String,
+}
+
+/// A contact with the fingerprint of its pinned key
+#[derive(Debug, Serialize)]
+pub struct ContactResponse {
+    pub contact_id: Uuid,
+    pub contact_user_id: Option<Uuid>,
+    pub name: String,
+    pub email: String,
+    pub key_algorithm: Option<String>,
+    pub key_fingerprint: Option<String>,
+    pub key_pinned_at: Option<OffsetDateTime>,
+    pub key_verified: bool,
+    pub created_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+}
+
+impl From<Contact> for ContactResponse {
+    fn from(contact: Contact) -> Self {
+        let key_fingerprint = pinned_fingerprint(&contact).map(|fingerprint| fingerprint.to_string());
+        Self {
+            contact_id: contact.contact_id,
+            contact_user_id: contact.contact_user_id,
+            name: contact.name,
+            email: contact.email,
+            key_algorithm: contact.key_algorithm,
+            key_fingerprint,
+            key_pinned_at: contact.key_pinned_at,
+            key_verified: contact.key_verified,
+            created_at: contact.created_at,
+            updated_at: contact.updated_at,
+        }
+    }
+}
+
+/// How the contact's published key compares with the pinned one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyCheckStatus {
+    /// Nothing was pinned yet; the published key is now
+    Pinned,
+    Unchanged,
+    /// The published key differs from the pinned one and should not be trusted blindly
+    Changed,
+    /// The contact is not a registered user or has not published a key
+    Unavailable,
+}
+
+/// Result of checking a contact's key before composing to them
+#[derive(Debug, Serialize)]
+pub struct KeyCheckResponse {
+    pub status: KeyCheckStatus,
+    pub current_key_id: Option<Uuid>,
+    pub current_fingerprint: Option<String>,
+    pub contact: ContactResponse,
+}
+
+/// A recipient whose key no longer matches the one pinned for them as a contact
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyChangeWarning {
+    pub recipient_id: Uuid,
+    pub contact_id: Uuid,
+    pub pinned_fingerprint: String,
+    pub current_fingerprint: String,
+    /// Whether the pinned key had been verified out of band
+    pub was_verified: bool,
+}
+
+fn pinned_fingerprint(contact: &Contact) -> Option<Fingerprint> {
+    match (&contact.key_algorithm, &contact.public_key) {
+        (Some(algorithm), Some(public_key)) => Some(Fingerprint::of(algorithm, public_key)),
+        _ => None,
+    }
+}
+
+fn validate_contact(name: &str, email: &str) -> Result<(), AppError> {
+    if name.is_empty() || name.len() > 255 {
+        return Err(AppError::ValidationError("Name must be 1 to 255 bytes".to_string()));
+    }
+    if email.len() > 255 || !email.contains('@') {
+        return Err(AppError::ValidationError("A valid email address is required".to_string()));
+    }
+    Ok(())
+}
+
+async fn find_contact(pool: &Pool<Postgres>, user_id: Uuid, contact_id: Uuid) -> Result<Contact, AppError> {
+    sqlx::query_as::<_, Contact>("SELECT * FROM contacts WHERE contact_id = $1 AND user_id = $2")
+        .bind(contact_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| AppError::NotFoundError("Contact not found".to_string()))
+}
+
+async fn registered_user(pool: &Pool<Postgres>, email: &str) -> Result<Option<Uuid>, sqlx::Error> {
+    sqlx::query_scalar("SELECT user_id FROM users WHERE email = $1")
+        .bind(email)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Pins a key for a contact; a first-use pin never replaces one made in the meantime
+async fn pin_key(
+    pool: &Pool<Postgres>,
+    contact_id: Uuid,
+    algorithm: &str,
+    public_key: &[u8],
+    verified: bool,
+) -> Result<Option<Contact>, sqlx::Error> {
+    sqlx::query_as::<_, Contact>(
+        "UPDATE contacts SET public_key = $1, key_algorithm = $2, key_pinned_at = $3, key_verified = $4, updated_at = $3
+         WHERE contact_id = $5 AND ($4 OR public_key IS NULL)
+         RETURNING *",
+    )
+        .bind(public_key)
+        .bind(algorithm)
+        .bind(OffsetDateTime::now_utc())
+        .bind(verified)
+        .bind(contact_id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// The users who have `user_id` in their contacts
+pub(crate) async fn contact_owners(pool: &Pool<Postgres>, user_id: Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+    sqlx::query_scalar("SELECT DISTINCT user_id FROM contacts WHERE contact_user_id = $1 AND user_id <> $1")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+}
+
+/// Compares the keys recipients are about to be encrypted to with the keys pinned for them
+///
+/// Recipients the user has as contacts but never pinned a key for are pinned now, on first
+/// use. Recipients who are not contacts are not checked.
+pub(crate) async fn key_changes(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    recipients: &[(Uuid, &KeyPair)],
+) -> Result<Vec<KeyChangeWarning>, AppError> {
+    let recipient_ids: Vec<Uuid> = recipients.iter().map(|(recipient_id, _)| *recipient_id).collect();
+    let contacts = sqlx::query_as::<_, Contact>(
+        "SELECT * FROM contacts WHERE user_id = $1 AND contact_user_id = ANY($2)",
+    )
+        .bind(user_id)
+        .bind(&recipient_ids)
+        .fetch_all(pool)
+        .await?;
+
+    let mut warnings = Vec::new();
+    for contact in contacts {
+        let Some((recipient_id, key_pair)) = recipients.iter().find(|(id, _)| Some(*id) == contact.contact_user_id) else {
+            continue;
+        };
+        let current = Fingerprint::of(&key_pair.algorithm, &key_pair.public_key);
+        match pinned_fingerprint(&contact) {
+            None => {
+                pin_key(pool, contact.contact_id, &key_pair.algorithm, &key_pair.public_key, false).await?;
+            }
+            Some(pinned) if pinned != current => {
+                warn!("Key of contact {} changed since it was pinned by user {}", contact.contact_id, user_id);
+                warnings.push(KeyChangeWarning {
+                    recipient_id: *recipient_id,
+                    contact_id: contact.contact_id,
+                    pinned_fingerprint: pinned.to_string(),
+                    current_fingerprint: current.to_string(),
+                    was_verified: contact.key_verified,
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(warnings)
+}
+
+/// Lists the user's contacts by name
+async fn list_contacts(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+) -> Result<Json<Vec<ContactResponse>>, AppError> {
+    let contacts = sqlx::query_as::<_, Contact>("SELECT * FROM contacts WHERE user_id = $1 ORDER BY name, email")
+        .bind(user.user_id)
+        .fetch_all(&state.db_pool)
+        .await?;
+    Ok(Json(contacts.into_iter().map(ContactResponse::from).collect()))
+}
+
+/// Adds a contact, pinning their published key if they have one
+async fn create_contact(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Json(request): Json<CreateContactRequest>,
+) -> Result<(StatusCode, Json<ContactResponse>), AppError> {
+    let name = request.name.trim().to_string();
+    let email = request.email.trim().to_lowercase();
+    validate_contact(&name, &email)?;
+
+    let contact_user_id = registered_user(&state.db_pool, &email).await?;
+    let mut contact = Contact::new(user.user_id, name, email, contact_user_id);
+    sqlx::query(
+        "INSERT INTO contacts (contact_id, user_id, contact_user_id, name, email, created_at, updated_at)
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+        .bind(contact.contact_id)
+        .bind(contact.user_id)
+        .bind(contact.contact_user_id)
+        .bind(&contact.name)
+        .bind(&contact.email)
+        .bind(contact.created_at)
+        .bind(contact.updated_at)
+        .execute(&state.db_pool)
+        .await
+        .map_err(|e| match e.as_database_error().and_then(|db| db.code()) {
+            Some(code) if code == "23505" => {
+                AppError::ValidationError(format!("{} is already a contact", contact.email))
+            }
+            _ => AppError::DatabaseError(e),
+        })?;
+
+    if let Some(contact_user_id) = contact.contact_user_id {
+        if let Some(key) = published_key(&state.db_pool, contact_user_id).await? {
+            if let Some(pinned) = pin_key(&state.db_pool, contact.contact_id, &key.encryption_method, &key.public_key, false).await? {
+                contact = pinned;
+            }
+        }
+    }
+
+    info!("User {} added contact {}", user.user_id, contact.contact_id);
+    Ok((StatusCode::CREATED, Json(contact.into())))
+}
+
+async fn get_contact(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(contact_id): Path<Uuid>,
+) -> Result<Json<ContactResponse>, AppError> {
+    Ok(Json(find_contact(&state.db_pool, user.user_id, contact_id).await?.into()))
+}
+
+/// Renames a contact or changes its address; a new address drops the pinned key
+async fn update_contact(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(contact_id): Path<Uuid>,
+    Json(request): Json<UpdateContactRequest>,
+) -> Result<Json<ContactResponse>, AppError> {
+    let mut contact = find_contact(&state.db_pool, user.user_id, contact_id).await?;
+    if let Some(name) = request.name {
+        contact.name = name.trim().to_string();
+    }
+    if let Some(email) = request.email.map(|email| email.trim().to_lowercase()) {
+        if email != contact.email {
+            contact.contact_user_id = registered_user(&state.db_pool, &email).await?;
+            contact.email = email;
+            contact.public_key = None;
+            contact.key_algorithm = None;
+            contact.key_pinned_at = None;
+            contact.key_verified = false;
+        }
+    }
+    validate_contact(&contact.name, &contact.email)?;
+
+    let contact = sqlx::query_as::<_, Contact>(
+        "UPDATE contacts SET name = $1, email = $2, contact_user_id = $3, public_key = $4, key_algorithm = $5,
+                             key_pinned_at = $6, key_verified = $7, updated_at = $8
+         WHERE contact_id = $9 AND user_id = $10
+         RETURNING *",
+    )
+        .bind(&contact.name)
+        .bind(&contact.email)
+        .bind(contact.contact_user_id)
+        .bind(&contact.public_key)
+        .bind(&contact.key_algorithm)
+        .bind(contact.key_pinned_at)
+        .bind(contact.key_verified)
+        .bind(OffsetDateTime::now_utc())
+        .bind(contact_id)
+        .bind(user.user_id)
+        .fetch_optional(&state.db_pool)
+        .await
+        .map_err(|e| match e.as_database_error().and_then(|db| db.code()) {
+            Some(code) if code == "23505" => {
+                AppError::ValidationError(format!("{} is already a contact", contact.email))
+            }
+            _ => AppError::DatabaseError(e),
+        })?
+        .ok_or_else(|| AppError::NotFoundError("Contact not found".to_string()))?;
+    Ok(Json(contact.into()))
+}
+
+async fn delete_contact(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(contact_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let deleted = sqlx::query("DELETE FROM contacts WHERE contact_id = $1 AND user_id = $2")
+        .bind(contact_id)
+        .bind(user.user_id)
+        .execute(&state.db_pool)
+        .await?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(AppError::NotFoundError("Contact not found".to_string()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Compares the contact's published key with the pinned one, pinning it on first use
+async fn check_key(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(contact_id): Path<Uuid>,
+) -> Result<Json<KeyCheckResponse>, AppError> {
+    let contact = find_contact(&state.db_pool, user.user_id, contact_id).await?;
+    let current = match contact.contact_user_id {
+        Some(contact_user_id) => published_key(&state.db_pool, contact_user_id).await?,
+        None => None,
+    };
+    let Some(current) = current else {
+        return Ok(Json(KeyCheckResponse {
+            status: KeyCheckStatus::Unavailable,
+            current_key_id: None,
+            current_fingerprint: None,
+            contact: contact.into(),
+        }));
+    };
+
+    let (status, contact) = match pinned_fingerprint(&contact) {
+        None => match pin_key(&state.db_pool, contact.contact_id, &current.encryption_method, &current.public_key, false).await? {
+            Some(pinned) => (KeyCheckStatus::Pinned, pinned),
+            None => (KeyCheckStatus::Unavailable, contact),
+        },
+        Some(pinned) if pinned == current.fingerprint() => (KeyCheckStatus::Unchanged, contact),
+        Some(_) => (KeyCheckStatus::Changed, contact),
+    };
+    Ok(Json(KeyCheckResponse {
+        status,
+        current_key_id: Some(current.key_id),
+        current_fingerprint: Some(current.fingerprint().to_string()),
+        contact: contact.into(),
+    }))
+}
+
+/// Marks the contact's current key verified after the user compared its fingerprint
+///
+/// The published key is pinned as verified, which is also how a changed key is accepted.
+async fn verify_contact(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(contact_id): Path<Uuid>,
+    Json(request): Json<VerifyContactRequest>,
+) -> Result<Json<ContactResponse>, AppError> {
+    let contact = find_contact(&state.db_pool, user.user_id, contact_id).await?;
+    let current = match contact.contact_user_id {
+        Some(contact_user_id) => published_key(&state.db_pool, contact_user_id).await?,
+        None => None,
+    }
+        .ok_or_else(|| AppError::NotFoundError("Contact has not published a key".to_string()))?;
+    if !current.fingerprint().matches(&request.fingerprint) {
+        return Err(AppError::ValidationError("Fingerprint does not match the contact's current key".to_string()));
+    }
+
+    let contact = pin_key(&state.db_pool, contact.contact_id, &current.encryption_method, &current.public_key, true).await?
+        .ok_or_else(|| AppError::NotFoundError("Contact not found".to_string()))?;
+    info!("User {} verified the key of contact {}", user.user_id, contact.contact_id);
+    Ok(Json(contact.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_support::{app, insert_user, login, send_as, test_state};
+    use axum::http::Method;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_contact_crud() {
+        let state = test_state().await;
+        let owner = insert_user(&state).await;
+        let other = insert_user(&state).await;
+        let token = login(&state, &owner).await;
+        let other_token = login(&state, &other).await;
+
+        let (status, contact) = send_as(app(state.clone()), Some(&token), Method::POST, "/contacts", Some(json!({
+            "name": "Outside Friend",
+            "email": " Friend@Example.org ",
+        }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(contact["email"], "friend@example.org");
+        assert!(contact["contact_user_id"].is_null());
+        assert!(contact["key_fingerprint"].is_null());
+        let uri = format!("/contacts/{}", contact["contact_id"].as_str().unwrap());
+
+        let (status, _) = send_as(app(state.clone()), Some(&token), Method::POST, "/contacts", Some(json!({
+            "name": "Again",
+            "email": "friend@example.org",
+        }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // Pointing the contact at a registered user links it
+        let (status, updated) = send_as(app(state.clone()), Some(&token), Method::PATCH, &uri, Some(json!({
+            "name": "Colleague",
+            "email": other.email,
+        }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(updated["name"], "Colleague");
+        assert_eq!(updated["contact_user_id"], other.user_id.to_string());
+
+        let (_, listed) = send_as(app(state.clone()), Some(&token), Method::GET, "/contacts", None).await;
+        assert_eq!(listed.as_array().unwrap().len(), 1);
+
+        // Contacts are private to their owner
+        let (status, _) = send_as(app(state.clone()), Some(&other_token), Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send_as(app(state.clone()), Some(&other_token), Method::DELETE, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = send_as(app(state.clone()), Some(&token), Method::DELETE, &uri, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send_as(app(state), Some(&token), Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_key_pinning_and_verification() {
+        let state = test_state().await;
+        let owner = insert_user(&state).await;
+        let friend = insert_user(&state).await;
+        let token = login(&state, &owner).await;
+        let friend_token = login(&state, &friend).await;
+        send_as(app(state.clone()), Some(&token), Method::POST, "/encryption/generate-key-pair", None).await;
+        send_as(app(state.clone()), Some(&friend_token), Method::POST, "/encryption/generate-key-pair", None).await;
+
+        // The published key is pinned when the contact is added
+        let (_, contact) = send_as(app(state.clone()), Some(&token), Method::POST, "/contacts", Some(json!({
+            "name": "Friend",
+            "email": friend.email,
+        }))).await;
+        let (_, status) = send_as(app(state.clone()), Some(&friend_token), Method::GET, "/encryption/status", None).await;
+        assert_eq!(contact["key_fingerprint"], status["fingerprint"]);
+        assert_eq!(contact["key_verified"], false);
+        let uri = format!("/contacts/{}", contact["contact_id"].as_str().unwrap());
+
+        let (_, check) = send_as(app(state.clone()), Some(&token), Method::GET, &format!("{}/key", uri), None).await;
+        assert_eq!(check["status"], "unchanged");
+
+        let compose = json!({ "recipient_id": friend.user_id, "content": "Hello" });
+        let (_, encrypted) = send_as(app(state.clone()), Some(&token), Method::POST, "/encryption/encrypt", Some(compose.clone())).await;
+        assert!(encrypted.get("key_warnings").is_none());
+
+        // A new key is reported as a change, when checked and when composing
+        send_as(app(state.clone()), Some(&friend_token), Method::POST, "/encryption/generate-key-pair", None).await;
+        let (_, new_status) = send_as(app(state.clone()), Some(&friend_token), Method::GET, "/encryption/status", None).await;
+        let (_, check) = send_as(app(state.clone()), Some(&token), Method::GET, &format!("{}/key", uri), None).await;
+        assert_eq!(check["status"], "changed");
+        assert_eq!(check["current_fingerprint"], new_status["fingerprint"]);
+        assert_eq!(check["contact"]["key_fingerprint"], status["fingerprint"]);
+
+        let (_, encrypted) = send_as(app(state.clone()), Some(&token), Method::POST, "/encryption/encrypt", Some(compose.clone())).await;
+        assert_eq!(encrypted["key_warnings"][0]["recipient_id"], friend.user_id.to_string());
+        assert_eq!(encrypted["key_warnings"][0]["pinned_fingerprint"], status["fingerprint"]);
+        assert_eq!(encrypted["key_warnings"][0]["current_fingerprint"], new_status["fingerprint"]);
+
+        // Verifying needs the current fingerprint and accepts the new key
+        let verify = format!("{}/verify", uri);
+        let (code, _) = send_as(app(state.clone()), Some(&token), Method::POST, &verify, Some(json!({
+            "fingerprint": status["fingerprint"],
+        }))).await;
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+        let typed = new_status["fingerprint"].as_str().unwrap().to_lowercase();
+        let (code, verified) = send_as(app(state.clone()), Some(&token), Method::POST, &verify, Some(json!({
+            "fingerprint": typed,
+        }))).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(verified["key_verified"], true);
+        assert_eq!(verified["key_fingerprint"], new_status["fingerprint"]);
+
+        let (_, encrypted) = send_as(app(state), Some(&token), Method::POST, "/encryption/encrypt", Some(compose)).await;
+        assert!(encrypted.get("key_warnings").is_none());
+    }
+}
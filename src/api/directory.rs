@@ -0,0 +1,258 @@
+// src/api/directory.rs
+use std::sync::Arc;
+
+use axum::extract::{Path, Query};
+use axum::routing::get;
+use axum::{Extension, Json, Router};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Postgres};
+use time::OffsetDateTime;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::api::auth::AuthUser;
+use crate::database::models::{KeyRevocation, User};
+use crate::quantum_encryption::fingerprint::Fingerprint;
+use crate::utils::error_handling::AppError;
+use crate::AppState;
+
+/// Routes for `/api/directory`
+pub fn routes() -> Router {
+    Router::new()
+        .route("/", get(lookup))
+        .route("/:user_id", get(lookup_by_id))
+}
+
+/// Looks a user up by exactly one of `email` or `username`
+#[derive(Debug, Deserialize)]
+pub struct DirectoryQuery {
+    pub email: Option<String>,
+    pub username: Option<String>,
+}
+
+/// Whether a published key may be encrypted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyStatus {
+    Active,
+    Expired,
+    /// The user revoked their key and has not published a new one
+    Revoked,
+}
+
+/// A signed revocation of one of a user's keys, base64 encoded
+#[derive(Debug, Clone, Serialize)]
+pub struct RevocationInfo {
+    pub key_id: Uuid,
+    pub user_id: Uuid,
+    pub reason: String,
+    pub revoked_at: OffsetDateTime,
+    pub signature: String,
+    pub signing_key_id: Uuid,
+}
+
+impl From<KeyRevocation> for RevocationInfo {
+    fn from(revocation: KeyRevocation) -> Self {
+        Self {
+            key_id: revocation.key_id,
+            user_id: revocation.user_id,
+            reason: revocation.reason,
+            revoked_at: revocation.revoked_at,
+            signature: BASE64.encode(&revocation.signature),
+            signing_key_id: revocation.signing_key_id,
+        }
+    }
+}
+
+/// A user's current public key as senders should encrypt to it
+#[derive(Debug, Serialize)]
+pub struct DirectoryEntry {
+    pub user_id: Uuid,
+    pub username: String,
+    pub email: String,
+    pub key_id: Uuid,
+    pub algorithm: String,
+    pub public_key: String,
+    /// SHA3-256 fingerprint for comparing the key out of band
+    pub fingerprint: String,
+    pub created_at: OffsetDateTime,
+    pub expires_at: OffsetDateTime,
+    pub status: KeyStatus,
+    /// Every key the user revoked, newest first, so pinned copies can be dropped
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub revoked_keys: Vec<RevocationInfo>,
+}
+
+/// Public columns of a `quantum_keys` row; the private key is never loaded
+#[derive(Debug, Clone, FromRow)]
+pub(crate) struct PublishedKey {
+    pub key_id: Uuid,
+    pub public_key: Vec<u8>,
+    pub encryption_method: String,
+    pub key_generation_timestamp: OffsetDateTime,
+    pub expiration_timestamp: OffsetDateTime,
+}
+
+impl PublishedKey {
+    pub fn fingerprint(&self) -> Fingerprint {
+        Fingerprint::of(&self.encryption_method, &self.public_key)
+    }
+}
+
+const PUBLISHED_KEY_COLUMNS: &str =
+    "key_id, public_key, encryption_method, key_generation_timestamp, expiration_timestamp";
+
+/// Fetches the user's active public key; a revoked key is never returned
+pub(crate) async fn published_key(pool: &Pool<Postgres>, user_id: Uuid) -> Result<Option<PublishedKey>, sqlx::Error> {
+    sqlx::query_as::<_, PublishedKey>(&format!(
+        "SELECT {} FROM quantum_keys k WHERE k.user_id = $1 AND k.is_active
+             AND NOT EXISTS (SELECT 1 FROM key_revocations r WHERE r.key_id = k.key_id)
+         ORDER BY k.key_generation_timestamp DESC LIMIT 1",
+        PUBLISHED_KEY_COLUMNS,
+    ))
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Lists the revocations of the user's keys, newest first
+pub(crate) async fn revocations(pool: &Pool<Postgres>, user_id: Uuid) -> Result<Vec<KeyRevocation>, sqlx::Error> {
+    sqlx::query_as::<_, KeyRevocation>("SELECT * FROM key_revocations WHERE user_id = $1 ORDER BY revoked_at DESC")
+        .bind(user_id)
+        .fetch_all(pool)
+        .await
+}
+
+/// Checks whether a key has been revoked
+pub(crate) async fn is_revoked(pool: &Pool<Postgres>, key_id: Uuid) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM key_revocations WHERE key_id = $1)")
+        .bind(key_id)
+        .fetch_one(pool)
+        .await
+}
+
+/// Builds the directory entry for a user; fails if they have never published a key
+///
+/// When the user's last key was revoked and not replaced, that key is listed as revoked.
+pub(crate) async fn directory_entry(pool: &Pool<Postgres>, user: User) -> Result<DirectoryEntry, AppError> {
+    let revoked = revocations(pool, user.user_id).await?;
+    let (key, status) = match (published_key(pool, user.user_id).await?, revoked.first()) {
+        (Some(key), _) if key.expiration_timestamp < OffsetDateTime::now_utc() => (key, KeyStatus::Expired),
+        (Some(key), _) => (key, KeyStatus::Active),
+        (None, Some(revocation)) => {
+            let key = sqlx::query_as::<_, PublishedKey>(&format!(
+                "SELECT {} FROM quantum_keys WHERE key_id = $1", PUBLISHED_KEY_COLUMNS,
+            ))
+                .bind(revocation.key_id)
+                .fetch_one(pool)
+                .await?;
+            (key, KeyStatus::Revoked)
+        }
+        (None, None) => return Err(AppError::NotFoundError(format!("{} has not published a key", user.username))),
+    };
+
+    Ok(DirectoryEntry {
+        user_id: user.user_id,
+        username: user.username,
+        email: user.email,
+        key_id: key.key_id,
+        fingerprint: key.fingerprint().to_string(),
+        public_key: BASE64.encode(&key.public_key),
+        algorithm: key.encryption_method,
+        created_at: key.key_generation_timestamp,
+        expires_at: key.expiration_timestamp,
+        status,
+        revoked_keys: revoked.into_iter().map(RevocationInfo::from).collect(),
+    })
+}
+
+/// Finds a user's current key by email address or username
+async fn lookup(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(_): AuthUser,
+    Query(query): Query<DirectoryQuery>,
+) -> Result<Json<DirectoryEntry>, AppError> {
+    let user = match (query.email, query.username) {
+        (Some(email), None) => sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
+            .bind(email.trim().to_lowercase())
+            .fetch_optional(&state.db_pool)
+            .await?,
+        (None, Some(username)) => sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = $1")
+            .bind(username.trim())
+            .fetch_optional(&state.db_pool)
+            .await?,
+        _ => return Err(AppError::ValidationError("Look up by exactly one of email or username".to_string())),
+    };
+    let user = user.ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+
+    debug!("Directory lookup for user {}", user.user_id);
+    Ok(Json(directory_entry(&state.db_pool, user).await?))
+}
+
+/// Finds a user's current key by user ID
+async fn lookup_by_id(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(_): AuthUser,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<DirectoryEntry>, AppError> {
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_optional(&state.db_pool)
+        .await?
+        .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
+    Ok(Json(directory_entry(&state.db_pool, user).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_support::{app, insert_user, login, send_as, test_state};
+    use axum::http::{Method, StatusCode};
+
+    #[tokio::test]
+    async fn test_directory_lookup() {
+        let state = test_state().await;
+        let alice = insert_user(&state).await;
+        let bob = insert_user(&state).await;
+        let bob_token = login(&state, &bob).await;
+
+        let by_email = format!("/directory?email={}", alice.email.to_uppercase());
+        let (status, _) = send_as(app(state.clone()), Some(&bob_token), Method::GET, &by_email, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let alice_token = login(&state, &alice).await;
+        let (_, first) = send_as(app(state.clone()), Some(&alice_token), Method::POST, "/encryption/generate-key-pair", None).await;
+
+        let (status, entry) = send_as(app(state.clone()), Some(&bob_token), Method::GET, &by_email, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(entry["user_id"], alice.user_id.to_string());
+        assert_eq!(entry["key_id"], first["key_id"]);
+        assert_eq!(entry["public_key"], first["public_key"]);
+        assert_eq!(entry["status"], "active");
+        let public_key = BASE64.decode(entry["public_key"].as_str().unwrap()).unwrap();
+        let fingerprint = Fingerprint::of(entry["algorithm"].as_str().unwrap(), &public_key);
+        assert_eq!(entry["fingerprint"], fingerprint.to_string());
+        let (_, own_status) = send_as(app(state.clone()), Some(&alice_token), Method::GET, "/encryption/status", None).await;
+        assert_eq!(own_status["fingerprint"], entry["fingerprint"]);
+
+        // A new key replaces the published one
+        let (_, second) = send_as(app(state.clone()), Some(&alice_token), Method::POST, "/encryption/generate-key-pair", None).await;
+        let by_username = format!("/directory?username={}", alice.username);
+        let (_, entry) = send_as(app(state.clone()), Some(&bob_token), Method::GET, &by_username, None).await;
+        assert_eq!(entry["key_id"], second["key_id"]);
+        assert_ne!(entry["fingerprint"], fingerprint.to_string());
+
+        let (_, entry) = send_as(app(state.clone()), Some(&bob_token), Method::GET, &format!("/directory/{}", alice.user_id), None).await;
+        assert_eq!(entry["key_id"], second["key_id"]);
+
+        let (status, _) = send_as(app(state.clone()), Some(&bob_token), Method::GET, "/directory?email=nobody@example.com", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let both = format!("/directory?email={}&username={}", alice.email, alice.username);
+        let (status, _) = send_as(app(state.clone()), Some(&bob_token), Method::GET, &both, None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send_as(app(state), None, Method::GET, &by_email, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
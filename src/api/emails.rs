@@ -0,0 +1,827 @@
+// src/api/emails.rs
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use axum::extract::{Path, Query};
+use axum::http::StatusCode;
+use axum::routing::{get, patch};
+use axum::{Extension, Json, Router};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use crate::api::auth::AuthUser;
+use crate::api::directory::is_revoked;
+use crate::database::models::{Email, EmailRecipient, RecipientType};
+use crate::quantum_encryption::kdf::SHARED_CONTENT_KEY;
+use crate::quantum_encryption::kem::kem_for_suite;
+use crate::utils::error_handling::AppError;
+use crate::websocket::server::{WebSocketMessage, WebSocketMessageType};
+use crate::AppState;
+
+/// Routes for `/api/emails`
+pub fn routes() -> Router {
+    Router::new()
+        .route("/", get(list_emails).post(send_email))
+        .route("/:id", get(get_email).delete(delete_email))
+        .route("/:id/read", patch(mark_as_read))
+        .route("/:id/recipients", get(list_recipients))
+}
+
+/// Columns of `emails e`, with `is_read` taken from user `$1`'s recipient row when there is one
+const EMAIL_COLUMNS: &str = "e.email_id, e.sender_id, e.recipient_id, e.subject, e.encrypted_content, \
+    e.encrypted_shared_secret, e.timestamp, e.encryption_method, \
+    COALESCE((SELECT r.is_read FROM email_recipients r WHERE r.email_id = e.email_id AND r.recipient_id = $1), e.is_read) AS is_read, \
+    e.is_starred, e.is_archived, e.signature, e.signing_key_id, e.encrypted_headers";
+
+/// Emails `e` delivered to user `$1`, either through a To/Cc/Bcc row or as the single recipient
+const RECEIVED_BY_USER: &str = "(EXISTS (SELECT 1 FROM email_recipients r WHERE r.email_id = e.email_id \
+        AND r.recipient_id = $1 AND r.recipient_type <> 'sender') \
+    OR (e.recipient_id = $1 AND NOT e.deleted_by_recipient \
+        AND NOT EXISTS (SELECT 1 FROM email_recipients r WHERE r.email_id = e.email_id)))";
+
+/// Emails `e` sent by user `$1` that are still in their sent folder
+const SENT_BY_USER: &str = "(e.sender_id = $1 AND NOT e.deleted_by_sender)";
+
+/// Mailbox listed by `GET /api/emails`
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Folder {
+    #[default]
+    Inbox,
+    Sent,
+}
+
+/// Query parameters for listing emails
+#[derive(Debug, Default, Deserialize)]
+pub struct ListEmailsQuery {
+    #[serde(default)]
+    pub folder: Folder,
+}
+
+/// One recipient of a multi-recipient email and the content key wrapped for them
+#[derive(Debug, Deserialize)]
+pub struct EmailRecipientRequest {
+    pub recipient_id: Uuid,
+    pub recipient_type: RecipientType,
+    /// The recipient's quantum key the content key was wrapped to
+    pub key_id: Uuid,
+    pub encryption_method: String,
+    pub kem_ciphertext: Vec<u8>,
+    pub wrapped_key: Vec<u8>,
+}
+
+/// Request body for storing a new (already encrypted) email from the authenticated user
+///
+/// Single-recipient mail names `recipient_id` and carries its own encapsulated secret.
+/// Mail for several recipients lists them in `recipients` instead, each with the content
+/// key wrapped to their key; the sender may include a `sender` entry for their own copy.
+/// The subject is sealed inside `encrypted_headers`, which every new email must carry, and
+/// is stored only as a placeholder.
+#[derive(Debug, Deserialize)]
+pub struct SendEmailRequest {
+    /// ID the content was encrypted for; generated when omitted
+    pub email_id: Option<Uuid>,
+    pub recipient_id: Option<Uuid>,
+    #[serde(default)]
+    pub subject: String,
+    #[serde(default)]
+    pub encrypted_headers: Vec<u8>,
+    pub encrypted_content: Vec<u8>,
+    #[serde(default)]
+    pub encrypted_shared_secret: Vec<u8>,
+    #[serde(default)]
+    pub encryption_method: String,
+    pub signature: Option<Vec<u8>>,
+    pub signing_key_id: Option<Uuid>,
+    #[serde(default)]
+    pub recipients: Vec<EmailRecipientRequest>,
+}
+
+impl SendEmailRequest {
+    /// Checks the request against the column constraints of the emails table
+    fn validate(&self, sender_id: Uuid) -> Result<(), AppError> {
+        if self.subject.len() > 255 {
+            return Err(AppError::ValidationError("Subject must be at most 255 bytes".to_string()));
+        }
+        if self.encrypted_content.is_empty() {
+            return Err(AppError::ValidationError("Encrypted content must not be empty".to_string()));
+        }
+        if self.signature.is_some() != self.signing_key_id.is_some() {
+            return Err(AppError::ValidationError(
+                "Signature and signing key ID must be given together".to_string(),
+            ));
+        }
+        if self.encrypted_headers.is_empty() {
+            return Err(AppError::ValidationError("Encrypted headers are required".to_string()));
+        }
+        if !self.subject.is_empty() && self.subject != Email::ENCRYPTED_SUBJECT {
+            return Err(AppError::ValidationError(
+                "Subject must be left empty; it belongs in the encrypted headers".to_string(),
+            ));
+        }
+
+        if self.recipients.is_empty() {
+            if self.recipient_id.is_none() {
+                return Err(AppError::ValidationError("Either recipient_id or recipients is required".to_string()));
+            }
+            return validate_method(&self.encryption_method);
+        }
+
+        if self.recipient_id.is_some() {
+            return Err(AppError::ValidationError("Give either recipient_id or recipients, not both".to_string()));
+        }
+        if !self.encryption_method.is_empty() && self.encryption_method != SHARED_CONTENT_KEY {
+            return Err(AppError::ValidationError(
+                "Encryption method is recorded per recipient for multi-recipient mail".to_string(),
+            ));
+        }
+        if !self.recipients.iter().any(|r| r.recipient_type == RecipientType::To) {
+            return Err(AppError::ValidationError("At least one To recipient is required".to_string()));
+        }
+        let mut seen = HashSet::new();
+        for recipient in &self.recipients {
+            if !seen.insert(recipient.recipient_id) {
+                return Err(AppError::ValidationError(format!(
+                    "Recipient {} is listed more than once", recipient.recipient_id
+                )));
+            }
+            if recipient.recipient_type == RecipientType::Sender && recipient.recipient_id != sender_id {
+                return Err(AppError::ValidationError("Only the sender can hold the sender copy".to_string()));
+            }
+            if recipient.kem_ciphertext.is_empty() || recipient.wrapped_key.is_empty() {
+                return Err(AppError::ValidationError(format!(
+                    "Recipient {} has no wrapped content key", recipient.recipient_id
+                )));
+            }
+            validate_method(&recipient.encryption_method)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that an encryption method names a supported KEM suite
+fn validate_method(encryption_method: &str) -> Result<(), AppError> {
+    if encryption_method.is_empty() || encryption_method.len() > 50 {
+        return Err(AppError::ValidationError("Encryption method must be 1 to 50 bytes".to_string()));
+    }
+    kem_for_suite(encryption_method).map_err(|e| AppError::ValidationError(e.to_string()))?;
+    Ok(())
+}
+
+/// Fetches an email the user sent or received, with their own read state
+pub(crate) async fn find_email(pool: &Pool<Postgres>, user_id: Uuid, email_id: Uuid) -> Result<Option<Email>, sqlx::Error> {
+    sqlx::query_as::<_, Email>(&format!(
+        "SELECT {} FROM emails e WHERE e.email_id = $2 AND ({} OR {})",
+        EMAIL_COLUMNS, SENT_BY_USER, RECEIVED_BY_USER,
+    ))
+        .bind(user_id)
+        .bind(email_id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Fetches the content key of a multi-recipient email wrapped for the user, if there is one
+pub(crate) async fn find_wrapped_key(
+    pool: &Pool<Postgres>,
+    user_id: Uuid,
+    email_id: Uuid,
+) -> Result<Option<EmailRecipient>, sqlx::Error> {
+    sqlx::query_as::<_, EmailRecipient>("SELECT * FROM email_recipients WHERE email_id = $1 AND recipient_id = $2")
+        .bind(email_id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Lists the emails the authenticated user received (or sent, with `?folder=sent`), newest first
+async fn list_emails(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<ListEmailsQuery>,
+) -> Result<Json<Vec<Email>>, AppError> {
+    let filter = match query.folder {
+        Folder::Inbox => RECEIVED_BY_USER,
+        Folder::Sent => SENT_BY_USER,
+    };
+    let emails = sqlx::query_as::<_, Email>(&format!(
+        "SELECT {} FROM emails e WHERE {} ORDER BY e.timestamp DESC",
+        EMAIL_COLUMNS, filter,
+    ))
+        .bind(user.user_id)
+        .fetch_all(&state.db_pool)
+        .await?;
+
+    debug!("Fetched {} {:?} emails for user {}", emails.len(), query.folder, user.user_id);
+    Ok(Json(emails))
+}
+
+/// Stores a new email from the authenticated user and delivers it to every recipient's mailbox
+async fn send_email(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Json(request): Json<SendEmailRequest>,
+) -> Result<(StatusCode, Json<Email>), AppError> {
+    request.validate(user.user_id)?;
+
+    if let Some(recipient_id) = request.recipient_id {
+        let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM users WHERE user_id = $1)")
+            .bind(recipient_id)
+            .fetch_one(&state.db_pool)
+            .await?;
+        if !exists {
+            return Err(AppError::NotFoundError(format!("Recipient {} does not exist", recipient_id)));
+        }
+    }
+
+    // Each wrapped content key must be for a key its recipient actually holds and has not revoked
+    for recipient in &request.recipients {
+        let holds_key = state.key_store.key_history(recipient.recipient_id).await?
+            .iter()
+            .any(|key| key.key_id == recipient.key_id);
+        if !holds_key {
+            return Err(AppError::NotFoundError(format!(
+                "Recipient {} has no key {}", recipient.recipient_id, recipient.key_id
+            )));
+        }
+        if is_revoked(&state.db_pool, recipient.key_id).await? {
+            return Err(AppError::ValidationError(format!(
+                "Key {} of recipient {} has been revoked", recipient.key_id, recipient.recipient_id
+            )));
+        }
+    }
+
+    // Only the sender's own keys may sign their mail; the signature itself is checked on decrypt
+    if let Some(key_id) = request.signing_key_id {
+        let owned = state.key_store.signing_key(key_id).await?
+            .is_some_and(|key| key.user_id == user.user_id);
+        if !owned {
+            return Err(AppError::ValidationError(format!("Signing key {} does not belong to the sender", key_id)));
+        }
+    }
+
+    // Multi-recipient mail is listed under its first To recipient and records no single suite
+    let (recipient_id, encryption_method) = match request.recipient_id {
+        Some(recipient_id) => (recipient_id, kem_for_suite(&request.encryption_method)?.suite().to_string()),
+        None => {
+            let first_to = request.recipients.iter()
+                .find(|r| r.recipient_type == RecipientType::To)
+                .map(|r| r.recipient_id)
+                .unwrap_or_default();
+            (first_to, SHARED_CONTENT_KEY.to_string())
+        }
+    };
+    let mut email = Email::new(
+        user.user_id,
+        recipient_id,
+        request.subject,
+        request.encrypted_content,
+        request.encrypted_shared_secret,
+        // Record the exact suite so decryption can dispatch on it
+        encryption_method,
+    );
+    if let Some(email_id) = request.email_id {
+        // The email ID is bound into the content key, so it must be kept as encrypted
+        email.email_id = email_id;
+    }
+    if let (Some(key_id), Some(signature)) = (request.signing_key_id, request.signature) {
+        email = email.with_signature(key_id, signature);
+    }
+    email = email.with_encrypted_headers(request.encrypted_headers);
+
+    let mut tx = state.db_pool.begin().await?;
+    sqlx::query(
+        "INSERT INTO emails (email_id, sender_id, recipient_id, subject, encrypted_content, encrypted_shared_secret,
+                             timestamp, encryption_method, is_read, is_starred, is_archived, signature, signing_key_id,
+                             encrypted_headers)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)",
+    )
+        .bind(email.email_id)
+        .bind(email.sender_id)
+        .bind(email.recipient_id)
+        .bind(&email.subject)
+        .bind(&email.encrypted_content)
+        .bind(&email.encrypted_shared_secret)
+        .bind(email.timestamp)
+        .bind(&email.encryption_method)
+        .bind(email.is_read)
+        .bind(email.is_starred)
+        .bind(email.is_archived)
+        .bind(&email.signature)
+        .bind(email.signing_key_id)
+        .bind(&email.encrypted_headers)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| match e.as_database_error().and_then(|db| db.code()) {
+            Some(code) if code == "23505" => {
+                AppError::ValidationError(format!("Email {} already exists", email.email_id))
+            }
+            _ => AppError::DatabaseError(e),
+        })?;
+
+    for recipient in &request.recipients {
+        sqlx::query(
+            "INSERT INTO email_recipients (email_id, recipient_id, recipient_type, key_id, encryption_method,
+                                           kem_ciphertext, wrapped_key, is_read)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, FALSE)",
+        )
+            .bind(email.email_id)
+            .bind(recipient.recipient_id)
+            .bind(recipient.recipient_type.as_str())
+            .bind(recipient.key_id)
+            .bind(kem_for_suite(&recipient.encryption_method)?.suite())
+            .bind(&recipient.kem_ciphertext)
+            .bind(&recipient.wrapped_key)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    notify_recipients(&state, &email, &request.recipients).await;
+
+    if request.recipients.is_empty() {
+        info!("Stored email {} from {} to {}", email.email_id, email.sender_id, email.recipient_id);
+    } else {
+        info!("Stored email {} from {} for {} recipients", email.email_id, email.sender_id, request.recipients.len());
+    }
+    Ok((StatusCode::CREATED, Json(email)))
+}
+
+/// Pushes a `NewEmail` event to the connected recipients of a stored email
+///
+/// The event only names the email; clients fetch and decrypt it themselves.
+async fn notify_recipients(state: &AppState, email: &Email, recipients: &[EmailRecipientRequest]) {
+    let recipient_ids: HashSet<Uuid> = if recipients.is_empty() {
+        HashSet::from([email.recipient_id])
+    } else {
+        recipients.iter()
+            .filter(|recipient| recipient.recipient_type != RecipientType::Sender)
+            .map(|recipient| recipient.recipient_id)
+            .collect()
+    };
+
+    for recipient_id in recipient_ids {
+        state.hub.send_to_user(recipient_id, &WebSocketMessage {
+            message_type: WebSocketMessageType::NewEmail,
+            sender_id: Some(email.sender_id),
+            recipient_id: Some(recipient_id),
+            payload: serde_json::json!({ "email_id": email.email_id }),
+            timestamp: email.timestamp,
+        }).await;
+    }
+}
+
+/// Fetches a single email the authenticated user sent or received
+async fn get_email(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(email_id): Path<Uuid>,
+) -> Result<Json<Email>, AppError> {
+    // Emails of other users are reported as missing rather than forbidden to avoid leaking IDs
+    find_email(&state.db_pool, user.user_id, email_id)
+        .await?
+        .map(Json)
+        .ok_or_else(|| AppError::NotFoundError(format!("Email {} not found", email_id)))
+}
+
+/// Lists who a multi-recipient email was sent to, hiding Bcc recipients from everyone else
+async fn list_recipients(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(email_id): Path<Uuid>,
+) -> Result<Json<Vec<EmailRecipient>>, AppError> {
+    let email = find_email(&state.db_pool, user.user_id, email_id)
+        .await?
+        .ok_or_else(|| AppError::NotFoundError(format!("Email {} not found", email_id)))?;
+
+    let recipients = sqlx::query_as::<_, EmailRecipient>(
+        "SELECT * FROM email_recipients
+         WHERE email_id = $1 AND ($3 OR recipient_id = $2 OR recipient_type IN ('to', 'cc'))
+         ORDER BY recipient_type, recipient_id",
+    )
+        .bind(email_id)
+        .bind(user.user_id)
+        .bind(email.sender_id == user.user_id)
+        .fetch_all(&state.db_pool)
+        .await?;
+    Ok(Json(recipients))
+}
+
+/// Deletes an email from the authenticated user's mailbox
+///
+/// Only the user's own copy goes: the sender's sent copy, or the recipient's copy. The
+/// email itself is deleted once neither the sender nor any recipient still has it.
+async fn delete_email(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(email_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let mut tx = state.db_pool.begin().await?;
+    let mut removed = sqlx::query(
+        "UPDATE emails SET deleted_by_sender = TRUE WHERE email_id = $2 AND sender_id = $1 AND NOT deleted_by_sender",
+    )
+        .bind(user.user_id)
+        .bind(email_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    removed += sqlx::query(
+        "UPDATE emails e SET deleted_by_recipient = TRUE
+         WHERE e.email_id = $2 AND e.recipient_id = $1 AND NOT e.deleted_by_recipient
+             AND NOT EXISTS (SELECT 1 FROM email_recipients r WHERE r.email_id = e.email_id)",
+    )
+        .bind(user.user_id)
+        .bind(email_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    // Multi-recipient mail: the user's To/Cc/Bcc copy, or the sender's wrapped key
+    removed += sqlx::query("DELETE FROM email_recipients WHERE email_id = $1 AND recipient_id = $2")
+        .bind(email_id)
+        .bind(user.user_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    if removed == 0 {
+        return Err(AppError::NotFoundError(format!("Email {} not found", email_id)));
+    }
+
+    let purged = sqlx::query(
+        "DELETE FROM emails e WHERE e.email_id = $1 AND e.deleted_by_sender
+             AND NOT EXISTS (SELECT 1 FROM email_recipients r WHERE r.email_id = e.email_id)
+             AND (e.deleted_by_recipient OR e.encryption_method = $2)",
+    )
+        .bind(email_id)
+        .bind(SHARED_CONTENT_KEY)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+    tx.commit().await?;
+
+    info!("Deleted email {} for user {}", email_id, user.user_id);
+    if purged > 0 {
+        debug!("Email {} is gone from every mailbox and was removed", email_id);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Marks an email received by the authenticated user as read and returns it
+async fn mark_as_read(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(email_id): Path<Uuid>,
+) -> Result<Json<Email>, AppError> {
+    // Read state is per recipient for multi-recipient mail and per email otherwise
+    sqlx::query(
+        "UPDATE email_recipients SET is_read = TRUE
+         WHERE email_id = $2 AND recipient_id = $1 AND recipient_type <> 'sender'",
+    )
+        .bind(user.user_id)
+        .bind(email_id)
+        .execute(&state.db_pool)
+        .await?;
+    sqlx::query(
+        "UPDATE emails e SET is_read = TRUE WHERE e.email_id = $2 AND e.recipient_id = $1
+             AND NOT EXISTS (SELECT 1 FROM email_recipients r WHERE r.email_id = e.email_id)",
+    )
+        .bind(user.user_id)
+        .bind(email_id)
+        .execute(&state.db_pool)
+        .await?;
+
+    sqlx::query_as::<_, Email>(&format!(
+        "SELECT {} FROM emails e WHERE e.email_id = $2 AND {}",
+        EMAIL_COLUMNS, RECEIVED_BY_USER,
+    ))
+        .bind(user.user_id)
+        .bind(email_id)
+        .fetch_optional(&state.db_pool)
+        .await?
+        .map(Json)
+        .ok_or_else(|| AppError::NotFoundError(format!("Email {} not found", email_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_support::{app, insert_user, login, send, send_as, test_state};
+    use axum::http::Method;
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use base64::Engine;
+    use serde_json::json;
+    use tokio_tungstenite::tungstenite::Message;
+
+    #[tokio::test]
+    async fn test_email_crud() {
+        let state = test_state().await;
+        let sender = insert_user(&state).await;
+        let recipient = insert_user(&state).await;
+        let sender_token = login(&state, &sender).await;
+        let recipient_token = login(&state, &recipient).await;
+        let mut client = state.hub.connect_test_client(recipient.user_id).await;
+
+        // New mail always seals its subject in encrypted headers
+        let (status, body) = send_as(app(state.clone()), Some(&sender_token), Method::POST, "/emails", Some(json!({
+            "recipient_id": recipient.user_id,
+            "subject": "Hello",
+            "encrypted_content": [1, 2, 3],
+            "encrypted_shared_secret": [4, 5, 6],
+            "encryption_method": "kyber",
+        }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "VALIDATION_ERROR");
+
+        let (status, body) = send_as(app(state.clone()), Some(&sender_token), Method::POST, "/emails", Some(json!({
+            "recipient_id": recipient.user_id,
+            "encrypted_headers": [9],
+            "encrypted_content": [1, 2, 3],
+            "encrypted_shared_secret": [4, 5, 6],
+            "encryption_method": "kyber",
+        }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let email_id = body["email_id"].as_str().unwrap().to_string();
+        assert_eq!(body["sender_id"], sender.user_id.to_string());
+        assert_eq!(body["is_read"], false);
+        assert_eq!(body["encryption_method"], "kyber1024");
+        assert_eq!(body["subject"], Email::ENCRYPTED_SUBJECT);
+
+        // The connected recipient is told about the new email
+        let Ok(Message::Text(text)) = client.try_recv() else { panic!("expected a NewEmail event") };
+        let event: WebSocketMessage = serde_json::from_str(&text).unwrap();
+        assert_eq!(event.message_type, WebSocketMessageType::NewEmail);
+        assert_eq!(event.sender_id, Some(sender.user_id));
+        assert_eq!(event.payload["email_id"], email_id.as_str());
+
+        let (status, body) = send_as(app(state.clone()), Some(&recipient_token), Method::GET, "/emails", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["email_id"], email_id.as_str());
+
+        let uri = format!("/emails/{}/read", email_id);
+        let (status, body) = send_as(app(state.clone()), Some(&recipient_token), Method::PATCH, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["is_read"], true);
+
+        // Deleting the sent copy leaves the recipient's copy alone, and the other way round
+        let uri = format!("/emails/{}", email_id);
+        let (status, _) = send_as(app(state.clone()), Some(&sender_token), Method::DELETE, &uri, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, body) = send_as(app(state.clone()), Some(&sender_token), Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "NOT_FOUND");
+        let (_, sent) = send_as(app(state.clone()), Some(&sender_token), Method::GET, "/emails?folder=sent", None).await;
+        assert!(sent.as_array().unwrap().is_empty());
+        let (status, body) = send_as(app(state.clone()), Some(&recipient_token), Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["encrypted_content"], json!([1, 2, 3]));
+
+        let (status, _) = send_as(app(state.clone()), Some(&recipient_token), Method::DELETE, &uri, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send_as(app(state.clone()), Some(&recipient_token), Method::DELETE, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM emails WHERE email_id = $1")
+            .bind(Uuid::parse_str(&email_id).unwrap())
+            .fetch_one(&state.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 0);
+    }
+
+    #[tokio::test]
+    async fn test_emails_require_authentication() {
+        let state = test_state().await;
+
+        let (status, body) = send(app(state.clone()), Method::GET, "/emails", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "AUTHENTICATION_ERROR");
+
+        let (status, _) = send_as(app(state), Some("bogus"), Method::GET, "/emails", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_other_users_email_is_hidden() {
+        let state = test_state().await;
+        let sender = insert_user(&state).await;
+        let recipient = insert_user(&state).await;
+        let outsider = insert_user(&state).await;
+
+        let (_, body) = send_as(app(state.clone()), Some(&login(&state, &sender).await), Method::POST, "/emails", Some(json!({
+            "recipient_id": recipient.user_id,
+            "encrypted_headers": [9],
+            "encrypted_content": [1],
+            "encrypted_shared_secret": [2],
+            "encryption_method": "kyber",
+        }))).await;
+
+        let uri = format!("/emails/{}", body["email_id"].as_str().unwrap());
+        let (status, _) = send_as(app(state.clone()), Some(&login(&state, &outsider).await), Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_send_email_to_unknown_recipient() {
+        let state = test_state().await;
+        let sender = insert_user(&state).await;
+        let token = login(&state, &sender).await;
+
+        let (status, body) = send_as(app(state), Some(&token), Method::POST, "/emails", Some(json!({
+            "recipient_id": Uuid::new_v4(),
+            "encrypted_headers": [9],
+            "encrypted_content": [1],
+            "encrypted_shared_secret": [2],
+            "encryption_method": "kyber",
+        }))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["status_code"], 404);
+    }
+
+    #[tokio::test]
+    async fn test_send_email_with_unknown_method() {
+        let state = test_state().await;
+        let sender = insert_user(&state).await;
+        let recipient = insert_user(&state).await;
+        let token = login(&state, &sender).await;
+
+        let (status, body) = send_as(app(state), Some(&token), Method::POST, "/emails", Some(json!({
+            "recipient_id": recipient.user_id,
+            "encrypted_headers": [9],
+            "encrypted_content": [1],
+            "encrypted_shared_secret": [2],
+            "encryption_method": "rot13",
+        }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "VALIDATION_ERROR");
+    }
+
+    #[tokio::test]
+    async fn test_multi_recipient_delivery() {
+        let state = test_state().await;
+        let sender = insert_user(&state).await;
+        let to = insert_user(&state).await;
+        let cc = insert_user(&state).await;
+        let bcc = insert_user(&state).await;
+        let outsider = insert_user(&state).await;
+        let mut tokens = Vec::new();
+        for user in [&sender, &to, &cc, &bcc, &outsider] {
+            let token = login(&state, user).await;
+            send_as(app(state.clone()), Some(&token), Method::POST, "/encryption/generate-key-pair", None).await;
+            tokens.push(token);
+        }
+        let [sender_token, to_token, cc_token, bcc_token, outsider_token] = &tokens[..] else { unreachable!() };
+
+        let (status, encrypted) = send_as(app(state.clone()), Some(sender_token), Method::POST, "/encryption/encrypt", Some(json!({
+            "to": [to.user_id],
+            "cc": [cc.user_id],
+            "bcc": [bcc.user_id],
+            "subject": "Team update",
+            "content": "Hello, everyone",
+        }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(encrypted["encryption_method"], SHARED_CONTENT_KEY);
+        let recipients = encrypted["recipients"].as_array().unwrap();
+        assert_eq!(recipients.len(), 4);
+        assert_eq!(recipients[3]["recipient_type"], "sender");
+
+        let decode = |value: &serde_json::Value| BASE64.decode(value.as_str().unwrap()).unwrap();
+        let (status, email) = send_as(app(state.clone()), Some(sender_token), Method::POST, "/emails", Some(json!({
+            "email_id": encrypted["email_id"],
+            "encrypted_headers": decode(&encrypted["encrypted_headers"]),
+            "encrypted_content": decode(&encrypted["encrypted_content"]),
+            "signature": decode(&encrypted["signature"]),
+            "signing_key_id": encrypted["signing_key_id"],
+            "recipients": recipients.iter().map(|r| json!({
+                "recipient_id": r["recipient_id"],
+                "recipient_type": r["recipient_type"],
+                "key_id": r["key_id"],
+                "encryption_method": r["encryption_method"],
+                "kem_ciphertext": decode(&r["kem_ciphertext"]),
+                "wrapped_key": decode(&r["wrapped_key"]),
+            })).collect::<Vec<_>>(),
+        }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(email["recipient_id"], to.user_id.to_string());
+        assert_eq!(email["subject"], Email::ENCRYPTED_SUBJECT);
+        let email_id = email["email_id"].as_str().unwrap().to_string();
+
+        // Every recipient, and the sender's own copy, decrypts and verifies the same content
+        for token in [to_token, cc_token, bcc_token, sender_token] {
+            let (status, body) = send_as(app(state.clone()), Some(token), Method::POST, "/encryption/decrypt", Some(json!({
+                "emailId": email_id,
+                "encryptedContent": encrypted["encrypted_content"],
+            }))).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+            assert_eq!(body["content"], "Hello, everyone");
+            assert_eq!(body["subject"], "Team update");
+            assert_eq!(body["signature_status"], "verified");
+        }
+        let (status, _) = send_as(app(state.clone()), Some(outsider_token), Method::POST, "/encryption/decrypt", Some(json!({
+            "emailId": email_id,
+            "encryptedContent": encrypted["encrypted_content"],
+        }))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Delivered to each recipient's inbox and the sender's sent folder only
+        for token in [to_token, cc_token, bcc_token] {
+            let (_, inbox) = send_as(app(state.clone()), Some(token), Method::GET, "/emails", None).await;
+            assert_eq!(inbox.as_array().unwrap().len(), 1);
+        }
+        let (_, inbox) = send_as(app(state.clone()), Some(sender_token), Method::GET, "/emails", None).await;
+        assert!(inbox.as_array().unwrap().is_empty());
+        let (_, sent) = send_as(app(state.clone()), Some(sender_token), Method::GET, "/emails?folder=sent", None).await;
+        assert_eq!(sent.as_array().unwrap().len(), 1);
+
+        // Bcc recipients are visible only to the sender and themselves
+        let uri = format!("/emails/{}/recipients", email_id);
+        let (_, listed) = send_as(app(state.clone()), Some(cc_token), Method::GET, &uri, None).await;
+        assert_eq!(listed.as_array().unwrap().len(), 2);
+        let (_, listed) = send_as(app(state.clone()), Some(bcc_token), Method::GET, &uri, None).await;
+        assert_eq!(listed.as_array().unwrap().len(), 3);
+        let (_, listed) = send_as(app(state.clone()), Some(sender_token), Method::GET, &uri, None).await;
+        assert_eq!(listed.as_array().unwrap().len(), 4);
+        let (status, _) = send_as(app(state.clone()), Some(outsider_token), Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Read state and deletion are per recipient
+        let (_, body) = send_as(app(state.clone()), Some(cc_token), Method::PATCH, &format!("/emails/{}/read", email_id), None).await;
+        assert_eq!(body["is_read"], true);
+        let uri = format!("/emails/{}", email_id);
+        let (_, body) = send_as(app(state.clone()), Some(to_token), Method::GET, &uri, None).await;
+        assert_eq!(body["is_read"], false);
+
+        let (status, _) = send_as(app(state.clone()), Some(to_token), Method::DELETE, &uri, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send_as(app(state.clone()), Some(to_token), Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send_as(app(state.clone()), Some(cc_token), Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // The sender deleting their sent copy does not take it from the remaining recipients
+        let (status, _) = send_as(app(state.clone()), Some(sender_token), Method::DELETE, &uri, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, sent) = send_as(app(state.clone()), Some(sender_token), Method::GET, "/emails?folder=sent", None).await;
+        assert!(sent.as_array().unwrap().is_empty());
+        let (status, body) = send_as(app(state.clone()), Some(cc_token), Method::POST, "/encryption/decrypt", Some(json!({
+            "emailId": email_id,
+            "encryptedContent": encrypted["encrypted_content"],
+        }))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["content"], "Hello, everyone");
+
+        // Once the last recipient deletes it too, the email is gone
+        for token in [cc_token, bcc_token] {
+            let (status, _) = send_as(app(state.clone()), Some(token), Method::DELETE, &uri, None).await;
+            assert_eq!(status, StatusCode::NO_CONTENT);
+        }
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM emails WHERE email_id = $1")
+            .bind(Uuid::parse_str(&email_id).unwrap())
+            .fetch_one(&state.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 0);
+    }
+
+    #[tokio::test]
+    async fn test_multi_recipient_validation() {
+        let state = test_state().await;
+        let sender = insert_user(&state).await;
+        let recipient = insert_user(&state).await;
+        let token = login(&state, &sender).await;
+
+        let entry = |recipient_type: &str| json!({
+            "recipient_id": recipient.user_id,
+            "recipient_type": recipient_type,
+            "key_id": Uuid::new_v4(),
+            "encryption_method": "kyber768",
+            "kem_ciphertext": [1],
+            "wrapped_key": [2],
+        });
+        let request = |recipients: Vec<serde_json::Value>| json!({
+            "encrypted_headers": [9],
+            "encrypted_content": [1],
+            "recipients": recipients,
+        });
+
+        for invalid in [vec![entry("cc")], vec![entry("to"), entry("cc")], vec![entry("sender")]] {
+            let (status, body) = send_as(app(state.clone()), Some(&token), Method::POST, "/emails", Some(request(invalid))).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(body["error"], "VALIDATION_ERROR");
+        }
+
+        // A wrapped key must be for a key the recipient holds
+        let (status, _) = send_as(app(state.clone()), Some(&token), Method::POST, "/emails", Some(request(vec![entry("to")]))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // and not for one they revoked
+        let recipient_token = login(&state, &recipient).await;
+        let (_, key) = send_as(app(state.clone()), Some(&recipient_token), Method::POST, "/encryption/generate-key-pair", None).await;
+        let revoke = format!("/encryption/keys/{}/revoke", key["key_id"].as_str().unwrap());
+        let (status, _) = send_as(app(state.clone()), Some(&recipient_token), Method::POST, &revoke, None).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let mut revoked = entry("to");
+        revoked["key_id"] = key["key_id"].clone();
+        let (status, body) = send_as(app(state), Some(&token), Method::POST, "/emails", Some(request(vec![revoked]))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["message"].as_str().unwrap().contains("revoked"));
+    }
+}
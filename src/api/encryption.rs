@@ -0,0 +1,1025 @@
+// src/api/encryption.rs
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use time::OffsetDateTime;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::api::auth::AuthUser;
+use crate::api::contacts::{contact_owners, key_changes, KeyChangeWarning};
+use crate::api::directory::{is_revoked, revocations, RevocationInfo};
+use crate::api::emails::{find_email, find_wrapped_key};
+use crate::api::prekeys::{encapsulation_key, is_claimed_prekey, EncapsulationKey};
+use crate::database::models::{KeyRevocation, RecipientType, RevocationReason};
+use crate::quantum_encryption::decryption::DecryptionService;
+use crate::quantum_encryption::encryption::{RecipientKey, WrappedKey};
+use crate::quantum_encryption::envelope::Envelope;
+use crate::quantum_encryption::fingerprint::Fingerprint;
+use crate::quantum_encryption::kdf::SHARED_CONTENT_KEY;
+use crate::quantum_encryption::kem::kem_for_suite;
+use crate::quantum_encryption::key_exchange::{KeyPair, QuantumKeyExchange};
+use crate::quantum_encryption::signing::{
+    EmailHeader, RevocationRecord, SignatureService, SignatureStatus, SigningKeyPair,
+};
+use crate::utils::error_handling::AppError;
+use crate::websocket::server::{WebSocketMessage, WebSocketMessageType};
+use crate::AppState;
+
+/// Routes for `/api/encryption`
+pub fn routes() -> Router {
+    Router::new()
+        .route("/generate-key-pair", post(generate_key_pair))
+        .route("/complete-key-exchange", post(complete_key_exchange))
+        .route("/status", get(encryption_status))
+        .route("/encrypt", post(encrypt))
+        .route("/decrypt", post(decrypt))
+        .route("/keys/:key_id/revoke", post(revoke_key))
+}
+
+/// Public half of a freshly generated key pair
+#[derive(Debug, Serialize)]
+pub struct KeyPairResponse {
+    pub key_id: Uuid,
+    pub public_key: String,
+    pub algorithm: String,
+    pub created_at: OffsetDateTime,
+    pub expires_at: OffsetDateTime,
+    pub signing_key_id: Uuid,
+    pub signing_public_key: String,
+}
+
+/// Request body for completing a key exchange against the user's active key
+///
+/// When `ciphertext` is omitted the server encapsulates to the user's own public key,
+/// which lets the client confirm that the stored key pair is usable.
+#[derive(Debug, Default, Deserialize)]
+pub struct CompleteKeyExchangeRequest {
+    pub ciphertext: Option<String>,
+}
+
+/// Result of a key exchange: the KEM ciphertext and a hash confirming the shared secret
+#[derive(Debug, Serialize)]
+pub struct KeyExchangeResponse {
+    pub status: String,
+    pub key_id: Uuid,
+    pub algorithm: String,
+    pub ciphertext: String,
+    pub key_confirmation: String,
+}
+
+/// Current state of the user's encryption keys
+#[derive(Debug, Serialize)]
+pub struct EncryptionStatusResponse {
+    pub status: String,
+    pub message: String,
+    pub key_id: Option<Uuid>,
+    pub algorithm: Option<String>,
+    pub created_at: Option<OffsetDateTime>,
+    pub expires_at: Option<OffsetDateTime>,
+    pub key_expires_in_days: Option<i64>,
+    /// Fingerprint of the active public key, as contacts see it in the key directory
+    pub fingerprint: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub revoked_keys: Vec<RevocationInfo>,
+}
+
+/// Request body for revoking a key; the reason defaults to `unspecified`
+#[derive(Debug, Default, Deserialize)]
+pub struct RevokeKeyRequest {
+    #[serde(default)]
+    pub reason: RevocationReason,
+}
+
+/// Request body for encrypting content for a recipient
+///
+/// Either `recipient_id` names a single recipient, or `to`, `cc` and `bcc` list several;
+/// the content is then encrypted once and its key wrapped for each of them and the sender.
+#[derive(Debug, Deserialize)]
+pub struct EncryptRequest {
+    /// ID the email will be stored under; generated when omitted
+    pub email_id: Option<Uuid>,
+    pub recipient_id: Option<Uuid>,
+    #[serde(default)]
+    pub to: Vec<Uuid>,
+    #[serde(default)]
+    pub cc: Vec<Uuid>,
+    #[serde(default)]
+    pub bcc: Vec<Uuid>,
+    pub content: String,
+    #[serde(default)]
+    pub subject: String,
+    /// Further headers sealed with the subject
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Encrypted content, the encapsulated secret and the sender's signature, all base64 encoded
+///
+/// Multi-recipient content has no single encapsulated secret or recipient key; each
+/// recipient's wrapped content key is listed in `recipients` instead.
+#[derive(Debug, Serialize)]
+pub struct EncryptResponse {
+    pub email_id: Uuid,
+    pub encrypted_content: String,
+    pub encrypted_shared_secret: String,
+    pub encryption_method: String,
+    /// Sealed subject and headers, stored in place of the plaintext subject
+    pub encrypted_headers: String,
+    pub recipient_key_id: Option<Uuid>,
+    /// Whether the recipient key is a one-time prekey rather than their long-term key
+    pub one_time_prekey: bool,
+    pub signature: String,
+    pub signing_key_id: Uuid,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub recipients: Vec<WrappedKeyResponse>,
+    /// Recipients whose key differs from the one pinned for them as a contact
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub key_warnings: Vec<KeyChangeWarning>,
+}
+
+/// The content key wrapped for one recipient, base64 encoded as `/api/emails` expects it decoded
+#[derive(Debug, Serialize)]
+pub struct WrappedKeyResponse {
+    pub recipient_id: Uuid,
+    pub recipient_type: RecipientType,
+    pub key_id: Uuid,
+    pub one_time_prekey: bool,
+    pub encryption_method: String,
+    pub kem_ciphertext: String,
+    pub wrapped_key: String,
+}
+
+/// Request body for decrypting content, named as encryptionService.ts sends it
+///
+/// When `emailId` is given it must name a stored email the user sent or received, and the
+/// content, sender, subject and signature are taken from it; any content the request carries
+/// is ignored. Without it they come from the request, and the content must have been
+/// encrypted for the nil email ID, since the email ID is bound into the content key.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecryptRequest {
+    pub email_id: Option<Uuid>,
+    /// Required without `emailId`
+    pub encrypted_content: Option<String>,
+    #[serde(default)]
+    pub encrypted_shared_secret: String,
+    pub sender_id: Option<Uuid>,
+    #[serde(default)]
+    pub subject: String,
+    pub signature: Option<String>,
+    pub signing_key_id: Option<Uuid>,
+    pub encryption_method: Option<String>,
+    pub encrypted_headers: Option<String>,
+}
+
+/// Decrypted plaintext content and whether the claimed sender really signed it
+#[derive(Debug, Serialize)]
+pub struct DecryptResponse {
+    pub content: String,
+    pub subject: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    pub signature_status: SignatureStatus,
+    pub sender_id: Option<Uuid>,
+    /// The one-time prekey that opened the content; the client deletes it with
+    /// `DELETE /api/encryption/prekeys/:key_id` once it has kept the message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prekey_id: Option<Uuid>,
+}
+
+/// Generates a new key pair for the user and makes it their active key
+///
+/// A signing key is created alongside it the first time, so every user who can receive
+/// mail can also sign it.
+async fn generate_key_pair(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+) -> Result<(StatusCode, Json<KeyPairResponse>), AppError> {
+    let key_exchange = QuantumKeyExchange::new(&state.config.encryption);
+    let key_pair = key_exchange.generate_key_pair()
+        .map_err(|e| AppError::KeyExchangeError(e.to_string()))?;
+    state.key_store
+        .insert(&QuantumKeyExchange::to_db_model(user.user_id, &key_pair))
+        .await?;
+    let signing_key = ensure_signing_key(&state, user.user_id).await?;
+
+    info!("Generated and stored quantum key {} for user {}", key_pair.id, user.user_id);
+    Ok((StatusCode::CREATED, Json(KeyPairResponse {
+        key_id: key_pair.id,
+        public_key: BASE64.encode(&key_pair.public_key),
+        algorithm: key_pair.algorithm,
+        created_at: key_pair.created_at,
+        expires_at: key_pair.expires_at,
+        signing_key_id: signing_key.id,
+        signing_public_key: BASE64.encode(&signing_key.public_key),
+    })))
+}
+
+/// Decapsulates a client ciphertext (or a self-encapsulation) with the user's active key
+async fn complete_key_exchange(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    request: Option<Json<CompleteKeyExchangeRequest>>,
+) -> Result<Json<KeyExchangeResponse>, AppError> {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    let key_pair = require_active_key(&state, user.user_id).await?;
+    let key_exchange = QuantumKeyExchange::new(&state.config.encryption);
+
+    let ciphertext = match request.ciphertext {
+        Some(encoded) => decode_base64("ciphertext", &encoded)?,
+        None => {
+            let (ciphertext, _) = key_exchange.encapsulate(&key_pair.algorithm, &key_pair.public_key)
+                .map_err(|e| AppError::KeyExchangeError(e.to_string()))?;
+            ciphertext
+        }
+    };
+
+    let shared_secret = key_exchange.decapsulate(&key_pair.algorithm, &key_pair.private_key, &ciphertext)
+        .map_err(|e| AppError::KeyExchangeError(e.to_string()))?;
+
+    debug!("Completed key exchange for user {} with key {}", user.user_id, key_pair.id);
+    Ok(Json(KeyExchangeResponse {
+        status: "completed".to_string(),
+        key_id: key_pair.id,
+        algorithm: key_pair.algorithm,
+        ciphertext: BASE64.encode(&ciphertext),
+        key_confirmation: BASE64.encode(Sha3_256::digest(shared_secret.expose_secret())),
+    }))
+}
+
+/// Reports whether the user has an active, unexpired key, and which keys they revoked
+async fn encryption_status(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+) -> Result<Json<EncryptionStatusResponse>, AppError> {
+    let revoked_keys: Vec<RevocationInfo> = revocations(&state.db_pool, user.user_id).await?
+        .into_iter()
+        .map(RevocationInfo::from)
+        .collect();
+    let response = match state.key_store.active_key_pair(user.user_id).await? {
+        Some(key_pair) => {
+            let expired = QuantumKeyExchange::is_expired(&key_pair);
+            let remaining = key_pair.expires_at - OffsetDateTime::now_utc();
+            EncryptionStatusResponse {
+                status: if expired { "expired" } else { "active" }.to_string(),
+                message: if expired {
+                    "Quantum key has expired".to_string()
+                } else {
+                    "Quantum encryption active".to_string()
+                },
+                key_id: Some(key_pair.id),
+                fingerprint: Some(Fingerprint::of(&key_pair.algorithm, &key_pair.public_key).to_string()),
+                algorithm: Some(key_pair.algorithm),
+                created_at: Some(key_pair.created_at),
+                expires_at: Some(key_pair.expires_at),
+                key_expires_in_days: Some(remaining.whole_days().max(0)),
+                revoked_keys,
+            }
+        }
+        None => {
+            let revoked = !revoked_keys.is_empty();
+            EncryptionStatusResponse {
+                status: if revoked { "revoked" } else { "inactive" }.to_string(),
+                message: if revoked {
+                    "Quantum key was revoked, generate a new key pair".to_string()
+                } else {
+                    "No quantum key pair has been generated".to_string()
+                },
+                key_id: None,
+                algorithm: None,
+                created_at: None,
+                expires_at: None,
+                key_expires_in_days: None,
+                fingerprint: None,
+                revoked_keys,
+            }
+        }
+    };
+
+    Ok(Json(response))
+}
+
+/// Encrypts content for its recipients under their active keys and signs it as the user
+async fn encrypt(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Json(request): Json<EncryptRequest>,
+) -> Result<Json<EncryptResponse>, AppError> {
+    let signing_key = ensure_signing_key(&state, user.user_id).await?;
+    let addressed: Vec<(Uuid, RecipientType)> = request.to.iter().map(|id| (*id, RecipientType::To))
+        .chain(request.cc.iter().map(|id| (*id, RecipientType::Cc)))
+        .chain(request.bcc.iter().map(|id| (*id, RecipientType::Bcc)))
+        .collect();
+    let recipient_id = match (request.recipient_id, addressed.is_empty()) {
+        (Some(recipient_id), true) => recipient_id,
+        (None, false) => return encrypt_for_recipients(&state, user.user_id, &signing_key, addressed, request).await.map(Json),
+        (Some(_), false) => return Err(AppError::ValidationError(
+            "Give either recipient_id or to/cc/bcc, not both".to_string(),
+        )),
+        (None, true) => return Err(AppError::ValidationError("Either recipient_id or to is required".to_string())),
+    };
+    let long_term_key = recipient_key(&state, recipient_id).await?;
+
+    // Pinned keys are compared against the long-term key, whichever key is encrypted to
+    let key_warnings = key_changes(&state.db_pool, user.user_id, &[(recipient_id, &long_term_key)]).await?;
+    let recipient_key = encapsulation_key(&state, recipient_id, user.user_id, long_term_key).await?;
+
+    let header = EmailHeader {
+        email_id: request.email_id.unwrap_or_else(Uuid::new_v4),
+        sender_id: user.user_id,
+        recipient_id,
+        subject: request.subject,
+    };
+    let encrypted = state.encryption_service
+        .encrypt_email(
+            &request.content,
+            &header,
+            &request.headers,
+            &signing_key,
+            &recipient_key.algorithm,
+            &recipient_key.public_key,
+        )
+        .map_err(|e| AppError::EncryptionError(e.to_string()))?;
+
+    debug!("Encrypted content from {} for key {}", user.user_id, recipient_key.key_id);
+    Ok(Json(EncryptResponse {
+        email_id: header.email_id,
+        encrypted_content: BASE64.encode(&encrypted.ciphertext),
+        encrypted_shared_secret: BASE64.encode(&encrypted.encapsulated_secret),
+        encryption_method: encrypted.encryption_method,
+        encrypted_headers: BASE64.encode(&encrypted.encrypted_headers),
+        recipient_key_id: Some(recipient_key.key_id),
+        one_time_prekey: recipient_key.one_time,
+        signature: BASE64.encode(&encrypted.signature),
+        signing_key_id: encrypted.signing_key_id,
+        recipients: Vec::new(),
+        key_warnings,
+    }))
+}
+
+/// Encrypts content once for To/Cc/Bcc recipients, wrapping its key for each and for the sender
+async fn encrypt_for_recipients(
+    state: &AppState,
+    sender_id: Uuid,
+    signing_key: &SigningKeyPair,
+    mut recipients: Vec<(Uuid, RecipientType)>,
+    request: EncryptRequest,
+) -> Result<EncryptResponse, AppError> {
+    if !recipients.iter().any(|(_, recipient_type)| *recipient_type == RecipientType::To) {
+        return Err(AppError::ValidationError("At least one To recipient is required".to_string()));
+    }
+    let mut seen = HashSet::new();
+    if let Some((duplicate, _)) = recipients.iter().find(|(id, _)| !seen.insert(*id)) {
+        return Err(AppError::ValidationError(format!("Recipient {} is listed more than once", duplicate)));
+    }
+    // The sender keeps a copy they can read, unless they already addressed themselves
+    if !seen.contains(&sender_id) {
+        recipients.push((sender_id, RecipientType::Sender));
+    }
+
+    let mut key_pairs = Vec::with_capacity(recipients.len());
+    for (recipient_id, recipient_type) in &recipients {
+        let key_pair = match recipient_type {
+            RecipientType::Sender => require_active_key(state, *recipient_id).await?,
+            _ => recipient_key(state, *recipient_id).await?,
+        };
+        key_pairs.push(key_pair);
+    }
+    let addressed: Vec<(Uuid, &KeyPair)> = recipients.iter().zip(&key_pairs)
+        .filter(|((_, recipient_type), _)| *recipient_type != RecipientType::Sender)
+        .map(|((recipient_id, _), key_pair)| (*recipient_id, key_pair))
+        .collect();
+    let key_warnings = key_changes(&state.db_pool, sender_id, &addressed).await?;
+
+    // Each addressed recipient gets one of their prekeys; the sender's copy uses their long-term key
+    let mut encapsulation_keys: Vec<EncapsulationKey> = Vec::with_capacity(recipients.len());
+    for ((recipient_id, recipient_type), key_pair) in recipients.iter().zip(key_pairs) {
+        encapsulation_keys.push(match recipient_type {
+            RecipientType::Sender => key_pair.into(),
+            _ => encapsulation_key(state, *recipient_id, sender_id, key_pair).await?,
+        });
+    }
+    let recipient_keys: Vec<RecipientKey> = recipients.iter().zip(&encapsulation_keys)
+        .map(|((recipient_id, _), key)| RecipientKey {
+            recipient_id: *recipient_id,
+            algorithm: &key.algorithm,
+            public_key: &key.public_key,
+        })
+        .collect();
+
+    let header = EmailHeader {
+        email_id: request.email_id.unwrap_or_else(Uuid::new_v4),
+        sender_id,
+        recipient_id: Uuid::nil(),
+        subject: request.subject,
+    };
+    let encrypted = state.encryption_service
+        .encrypt_for_recipients(&request.content, &header, &request.headers, signing_key, &recipient_keys)
+        .map_err(|e| AppError::EncryptionError(e.to_string()))?;
+
+    debug!("Encrypted content from {} for {} recipients", sender_id, recipients.len());
+    Ok(EncryptResponse {
+        email_id: header.email_id,
+        encrypted_content: BASE64.encode(&encrypted.ciphertext),
+        encrypted_shared_secret: String::new(),
+        encryption_method: SHARED_CONTENT_KEY.to_string(),
+        encrypted_headers: BASE64.encode(&encrypted.encrypted_headers),
+        recipient_key_id: None,
+        one_time_prekey: false,
+        signature: BASE64.encode(&encrypted.signature),
+        signing_key_id: encrypted.signing_key_id,
+        recipients: encrypted.wrapped_keys.into_iter().zip(recipients).zip(encapsulation_keys)
+            .map(|((wrapped, (_, recipient_type)), key)| WrappedKeyResponse {
+                recipient_id: wrapped.recipient_id,
+                recipient_type,
+                key_id: key.key_id,
+                one_time_prekey: key.one_time,
+                encryption_method: wrapped.encryption_method,
+                kem_ciphertext: BASE64.encode(&wrapped.kem_ciphertext),
+                wrapped_key: BASE64.encode(&wrapped.wrapped_key),
+            })
+            .collect(),
+        key_warnings,
+    })
+}
+
+/// Decrypts content addressed to the user, trying the active key before older ones
+async fn decrypt(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Json(request): Json<DecryptRequest>,
+) -> Result<Json<DecryptResponse>, AppError> {
+    // Content, header and signature come from the stored email when one is named, never from the client
+    let mut stored = match request.email_id {
+        Some(email_id) => Some(
+            find_email(&state.db_pool, user.user_id, email_id).await?
+                .ok_or_else(|| AppError::NotFoundError(format!("Email {} not found", email_id)))?,
+        ),
+        None => None,
+    };
+    let (encrypted_content, encrypted_shared_secret) = match stored.as_mut() {
+        Some(email) => (std::mem::take(&mut email.encrypted_content), std::mem::take(&mut email.encrypted_shared_secret)),
+        None => {
+            let encrypted_content = request.encrypted_content.as_deref()
+                .ok_or_else(|| AppError::ValidationError("Field encryptedContent is required without emailId".to_string()))?;
+            (
+                decode_base64("encryptedContent", encrypted_content)?,
+                decode_base64("encryptedSharedSecret", &request.encrypted_shared_secret)?,
+            )
+        }
+    };
+    // Multi-recipient content is opened with the content key wrapped for this user's key
+    let wrapped_key = match &stored {
+        Some(email) if email.encryption_method == SHARED_CONTENT_KEY => {
+            let recipient = find_wrapped_key(&state.db_pool, user.user_id, email.email_id).await?
+                .ok_or_else(|| AppError::AuthorizationError("No content key was wrapped for this user".to_string()))?;
+            Some((recipient.key_id, WrappedKey {
+                recipient_id: recipient.recipient_id,
+                encryption_method: recipient.encryption_method,
+                kem_ciphertext: recipient.kem_ciphertext,
+                wrapped_key: recipient.wrapped_key,
+            }))
+        }
+        Some(email) if email.recipient_id != user.user_id => {
+            return Err(AppError::AuthorizationError("Only the recipient can decrypt this email".to_string()));
+        }
+        _ => None,
+    };
+    let (sender_id, mut header, signature, signing_key_id, encryption_method, encrypted_headers) = match stored {
+        Some(email) => {
+            let header = EmailHeader {
+                email_id: email.email_id,
+                sender_id: email.sender_id,
+                recipient_id: if wrapped_key.is_some() { Uuid::nil() } else { email.recipient_id },
+                subject: email.subject,
+            };
+            let encryption_method = Some(email.encryption_method);
+            (Some(email.sender_id), header, email.signature, email.signing_key_id, encryption_method, email.encrypted_headers)
+        }
+        None => {
+            let signature = request.signature.as_deref()
+                .map(|signature| decode_base64("signature", signature))
+                .transpose()?;
+            let header = EmailHeader {
+                email_id: Uuid::nil(),
+                sender_id: request.sender_id.unwrap_or_default(),
+                recipient_id: user.user_id,
+                subject: request.subject,
+            };
+            let encrypted_headers = request.encrypted_headers.as_deref()
+                .map(|encrypted_headers| decode_base64("encryptedHeaders", encrypted_headers))
+                .transpose()?;
+            (request.sender_id, header, signature, request.signing_key_id, request.encryption_method, encrypted_headers)
+        }
+    };
+    let sender_public_key = match (sender_id, signing_key_id) {
+        (Some(sender_id), Some(key_id)) => signer_public_key(&state, sender_id, key_id).await?,
+        _ => SignerKey::Unknown,
+    };
+
+    // A malformed envelope is rejected as bad input before any key is tried, and names its own suite
+    let suite = if let Some((_, wrapped_key)) = &wrapped_key {
+        Some(kem_for_suite(&wrapped_key.encryption_method).map_err(|e| AppError::DecryptionError(e.to_string()))?.suite())
+    } else {
+        content_suite(&encrypted_content, encryption_method.as_deref())?
+    };
+
+    let keys = state.key_store.key_pair_history(user.user_id).await?;
+    if keys.is_empty() {
+        return Err(AppError::DecryptionError("No quantum keys found for user".to_string()));
+    }
+
+    let decryption_service = DecryptionService::new(&state.config.encryption);
+
+    // Sealed headers are opened first so the signature is checked against the real subject
+    let mut headers = BTreeMap::new();
+    if let Some(encrypted_headers) = &encrypted_headers {
+        let sealed = keys.iter()
+            .find_map(|key_pair| match &wrapped_key {
+                Some((key_id, wrapped_key)) if *key_id == key_pair.id => decryption_service
+                    .open_shared_headers(encrypted_headers, wrapped_key, key_pair, &header)
+                    .ok(),
+                Some(_) => None,
+                None => decryption_service.open_headers(encrypted_headers, key_pair, &header).ok(),
+            })
+            .ok_or_else(|| AppError::DecryptionError("Headers could not be decrypted with any of the user's keys".to_string()))?;
+        header.subject = sealed.subject;
+        headers = sealed.headers;
+    }
+
+    for key_pair in keys {
+        // Only keys of the suite the content was encrypted under can open it
+        if suite.is_some_and(|suite| !has_suite(&key_pair, suite)) {
+            continue;
+        }
+        if wrapped_key.as_ref().is_some_and(|(key_id, _)| *key_id != key_pair.id) {
+            continue;
+        }
+        let public_key = match &sender_public_key {
+            SignerKey::Known(public_key) => Some(public_key.as_slice()),
+            SignerKey::Unknown | SignerKey::Mismatched => None,
+        };
+        let decrypted = match &wrapped_key {
+            Some((_, wrapped_key)) => decryption_service.decrypt_for_recipient(
+                &encrypted_content,
+                wrapped_key,
+                &key_pair,
+                &header,
+                signature.as_deref(),
+                public_key,
+            ),
+            None => decryption_service.decrypt_email(
+                &encrypted_content,
+                &encrypted_shared_secret,
+                &key_pair,
+                &header,
+                signature.as_deref(),
+                public_key,
+            ),
+        };
+        if let Ok(decrypted) = decrypted {
+            debug!("Decrypted content for user {} with key {}", user.user_id, key_pair.id);
+            let prekey_id = is_claimed_prekey(&state, key_pair.id).await?.then_some(key_pair.id);
+            let signature_status = match sender_public_key {
+                SignerKey::Mismatched if signature.is_some() => SignatureStatus::Invalid,
+                _ => decrypted.signature_status,
+            };
+            return Ok(Json(DecryptResponse {
+                content: decrypted.content,
+                subject: header.subject,
+                headers,
+                signature_status,
+                sender_id,
+                prekey_id,
+            }));
+        }
+    }
+
+    warn!("No key of user {} could decrypt email {:?}", user.user_id, request.email_id);
+    Err(AppError::DecryptionError("Content could not be decrypted with any of the user's keys".to_string()))
+}
+
+/// Revokes one of the user's quantum keys so nothing new is encrypted to it
+///
+/// The revocation is signed with the user's signing key and sent to the user's own connections
+/// and to those of users who have them in their contacts.
+/// Mail already encrypted to the key stays readable.
+async fn revoke_key(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(key_id): Path<Uuid>,
+    request: Option<Json<RevokeKeyRequest>>,
+) -> Result<(StatusCode, Json<RevocationInfo>), AppError> {
+    let reason = request.map(|Json(request)| request.reason).unwrap_or_default();
+    let owner: Option<Uuid> = sqlx::query_scalar("SELECT user_id FROM quantum_keys WHERE key_id = $1")
+        .bind(key_id)
+        .fetch_optional(&state.db_pool)
+        .await?;
+    if owner != Some(user.user_id) {
+        return Err(AppError::NotFoundError("Key not found".to_string()));
+    }
+
+    let signing_key = ensure_signing_key(&state, user.user_id).await?;
+    let revocation = KeyRevocation {
+        key_id,
+        user_id: user.user_id,
+        reason: reason.as_str().to_string(),
+        revoked_at: OffsetDateTime::from_unix_timestamp(OffsetDateTime::now_utc().unix_timestamp())
+            .map_err(|e| AppError::InternalServerError(e.to_string()))?,
+        signature: Vec::new(),
+        signing_key_id: signing_key.id,
+    };
+    let signature = SignatureService::new()
+        .sign_revocation(&signing_key, &RevocationRecord {
+            key_id,
+            user_id: user.user_id,
+            reason: &revocation.reason,
+            revoked_at: revocation.revoked_at,
+        })
+        .map_err(|e| AppError::EncryptionError(e.to_string()))?;
+    let revocation = KeyRevocation { signature, ..revocation };
+
+    let mut tx = state.db_pool.begin().await?;
+    sqlx::query(
+        "INSERT INTO key_revocations (key_id, user_id, reason, revoked_at, signature, signing_key_id)
+         VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+        .bind(revocation.key_id)
+        .bind(revocation.user_id)
+        .bind(&revocation.reason)
+        .bind(revocation.revoked_at)
+        .bind(&revocation.signature)
+        .bind(revocation.signing_key_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| match e.as_database_error().and_then(|db| db.code()) {
+            Some(code) if code == "23505" => AppError::ValidationError(format!("Key {} is already revoked", key_id)),
+            _ => AppError::DatabaseError(e),
+        })?;
+    sqlx::query("UPDATE quantum_keys SET is_active = FALSE WHERE key_id = $1")
+        .bind(key_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    warn!("User {} revoked quantum key {} ({})", user.user_id, key_id, revocation.reason);
+    let info = RevocationInfo::from(revocation);
+    let message = WebSocketMessage {
+        message_type: WebSocketMessageType::KeyRevocation,
+        sender_id: Some(user.user_id),
+        recipient_id: None,
+        payload: serde_json::to_value(&info).map_err(|e| AppError::InternalServerError(e.to_string()))?,
+        timestamp: OffsetDateTime::now_utc(),
+    };
+    state.hub.send_to_user(user.user_id, &message).await;
+    for owner in contact_owners(&state.db_pool, user.user_id).await? {
+        state.hub.send_to_user(owner, &message).await;
+    }
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+/// Public signing key of the claimed sender, as far as the key store knows it
+enum SignerKey {
+    Known(Vec<u8>),
+    Unknown,
+    /// The signing key exists but belongs to someone other than the claimed sender
+    Mismatched,
+}
+
+/// Looks up a signing key and checks that it belongs to the claimed sender
+async fn signer_public_key(state: &AppState, sender_id: Uuid, key_id: Uuid) -> Result<SignerKey, AppError> {
+    Ok(match state.key_store.signing_key(key_id).await? {
+        Some(key) if key.user_id == sender_id => SignerKey::Known(key.public_key),
+        Some(key) => {
+            warn!("Signing key {} belongs to {} but was presented for {}", key_id, key.user_id, sender_id);
+            SignerKey::Mismatched
+        }
+        None => SignerKey::Unknown,
+    })
+}
+
+/// Returns the user's active signing key, generating one if they have none yet
+pub(crate) async fn ensure_signing_key(state: &AppState, user_id: Uuid) -> Result<SigningKeyPair, AppError> {
+    if let Some(key) = state.key_store.active_signing_key(user_id).await? {
+        return Ok(SignatureService::from_db_model(key));
+    }
+
+    let key_pair = SignatureService::new().generate_key_pair();
+    state.key_store
+        .insert_signing_key(&SignatureService::to_db_model(user_id, &key_pair))
+        .await?;
+    info!("Generated signing key {} for user {}", key_pair.id, user_id);
+    Ok(key_pair)
+}
+
+/// Returns the recipient's active key, refusing keys that have been revoked
+pub(crate) async fn recipient_key(state: &AppState, recipient_id: Uuid) -> Result<KeyPair, AppError> {
+    let key_pair = state.key_store.active_key_pair(recipient_id).await?
+        .ok_or_else(|| AppError::NotFoundError(format!(
+            "Recipient {} has no active quantum key", recipient_id
+        )))?;
+    ensure_not_revoked(state, &key_pair).await?;
+    Ok(key_pair)
+}
+
+/// The KEM suite single-recipient content was encrypted under, as named by its envelope or
+/// else by its encryption method
+///
+/// A malformed envelope, or one for several recipients, is rejected as bad input.
+pub(crate) fn content_suite(encrypted_content: &[u8], encryption_method: Option<&str>) -> Result<Option<&'static str>, AppError> {
+    if Envelope::is_envelope(encrypted_content) {
+        let envelope = Envelope::parse(encrypted_content).map_err(|e| AppError::ValidationError(e.to_string()))?;
+        let kem = envelope.kem.ok_or_else(|| AppError::ValidationError(
+            "Content for several recipients can only be decrypted through its stored email".to_string(),
+        ))?;
+        return Ok(Some(kem.suite()));
+    }
+    let kem = encryption_method.map(kem_for_suite)
+        .transpose()
+        .map_err(|e| AppError::DecryptionError(e.to_string()))?;
+    Ok(kem.map(|kem| kem.suite()))
+}
+
+/// Whether the key pair belongs to the given KEM suite
+pub(crate) fn has_suite(key_pair: &KeyPair, suite: &str) -> bool {
+    kem_for_suite(&key_pair.algorithm).is_ok_and(|kem| kem.suite() == suite)
+}
+
+async fn ensure_not_revoked(state: &AppState, key_pair: &KeyPair) -> Result<(), AppError> {
+    if is_revoked(&state.db_pool, key_pair.id).await? {
+        return Err(AppError::ValidationError(format!("Key {} has been revoked", key_pair.id)));
+    }
+    Ok(())
+}
+
+/// Loads the user's active key pair or fails with a hint to generate one
+async fn require_active_key(state: &AppState, user_id: Uuid) -> Result<KeyPair, AppError> {
+    let key_pair = state.key_store.active_key_pair(user_id).await?
+        .ok_or_else(|| AppError::KeyExchangeError("No active quantum key, generate a key pair first".to_string()))?;
+    ensure_not_revoked(state, &key_pair).await?;
+    Ok(key_pair)
+}
+
+/// Decodes a base64 request field, reporting the field name on failure
+fn decode_base64(field: &str, value: &str) -> Result<Vec<u8>, AppError> {
+    BASE64.decode(value)
+        .map_err(|e| AppError::ValidationError(format!("Field {} is not valid base64: {}", field, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_support::{app, insert_user, login, send_as, send_encrypted_email, test_state};
+    use axum::http::Method;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_status_without_key() {
+        let state = test_state().await;
+        let user = insert_user(&state).await;
+        let token = login(&state, &user).await;
+
+        let (status, body) = send_as(app(state), Some(&token), Method::GET, "/encryption/status", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "inactive");
+    }
+
+    #[tokio::test]
+    async fn test_key_exchange_and_encrypt_decrypt() {
+        let state = test_state().await;
+        let sender = insert_user(&state).await;
+        let recipient = insert_user(&state).await;
+        let outsider = insert_user(&state).await;
+        let sender_token = login(&state, &sender).await;
+        let recipient_token = login(&state, &recipient).await;
+        let outsider_token = login(&state, &outsider).await;
+
+        for token in [&sender_token, &recipient_token, &outsider_token] {
+            let (status, body) = send_as(app(state.clone()), Some(token), Method::POST, "/encryption/generate-key-pair", None).await;
+            assert_eq!(status, StatusCode::CREATED);
+            assert!(BASE64.decode(body["public_key"].as_str().unwrap()).is_ok());
+        }
+
+        let (status, body) = send_as(app(state.clone()), Some(&recipient_token), Method::GET, "/encryption/status", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "active");
+
+        let (status, body) = send_as(app(state.clone()), Some(&recipient_token), Method::POST, "/encryption/complete-key-exchange", Some(json!({}))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "completed");
+
+        let encrypted = send_encrypted_email(&state, &sender_token, &recipient).await;
+        let decrypt_body = json!({ "emailId": encrypted["email_id"] });
+        let (status, body) = send_as(app(state.clone()), Some(&recipient_token), Method::POST, "/encryption/decrypt", Some(decrypt_body.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["content"], "Hello, Quantum World!");
+        assert_eq!(body["signature_status"], "unverified");
+
+        // Older mail stays readable after the recipient generates a new key
+        send_as(app(state.clone()), Some(&recipient_token), Method::POST, "/encryption/generate-key-pair", None).await;
+        let (status, body) = send_as(app(state.clone()), Some(&recipient_token), Method::POST, "/encryption/decrypt", Some(decrypt_body.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["content"], "Hello, Quantum World!");
+
+        // Only the recipient may decrypt, and others cannot see the email at all
+        let (status, body) = send_as(app(state.clone()), Some(&sender_token), Method::POST, "/encryption/decrypt", Some(decrypt_body.clone())).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"], "AUTHORIZATION_ERROR");
+        let (status, body) = send_as(app(state.clone()), Some(&outsider_token), Method::POST, "/encryption/decrypt", Some(decrypt_body.clone())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "NOT_FOUND");
+        let mut unknown = decrypt_body.clone();
+        unknown["emailId"] = json!(Uuid::new_v4());
+        let (status, _) = send_as(app(state.clone()), Some(&recipient_token), Method::POST, "/encryption/decrypt", Some(unknown)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Stored mail is decrypted as stored, whatever content the client sends along
+        let mut envelope = BASE64.decode(encrypted["encrypted_content"].as_str().unwrap()).unwrap();
+        envelope[4] = 99;
+        let mut ignored = decrypt_body.clone();
+        ignored["encryptedContent"] = json!(BASE64.encode(&envelope));
+        let (status, body) = send_as(app(state.clone()), Some(&recipient_token), Method::POST, "/encryption/decrypt", Some(ignored)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["content"], "Hello, Quantum World!");
+
+        // Content without an email is required, and a malformed envelope is rejected as bad input
+        let (status, _) = send_as(app(state.clone()), Some(&recipient_token), Method::POST, "/encryption/decrypt", Some(json!({}))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, body) = send_as(app(state), Some(&recipient_token), Method::POST, "/encryption/decrypt", Some(json!({
+            "encryptedContent": BASE64.encode(&envelope),
+        }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "VALIDATION_ERROR");
+        assert!(body["message"].as_str().unwrap().contains("Unsupported envelope version"));
+    }
+
+    #[tokio::test]
+    async fn test_key_revocation() {
+        let state = test_state().await;
+        let owner = insert_user(&state).await;
+        let sender = insert_user(&state).await;
+        let stranger = insert_user(&state).await;
+        let owner_token = login(&state, &owner).await;
+        let sender_token = login(&state, &sender).await;
+        let (_, key) = send_as(app(state.clone()), Some(&owner_token), Method::POST, "/encryption/generate-key-pair", None).await;
+        send_as(app(state.clone()), Some(&sender_token), Method::POST, "/encryption/generate-key-pair", None).await;
+        let (status, _) = send_as(app(state.clone()), Some(&sender_token), Method::POST, "/contacts", Some(json!({
+            "name": owner.username,
+            "email": owner.email,
+        }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let mut client = state.hub.connect_test_client(sender.user_id).await;
+        let mut owner_client = state.hub.connect_test_client(owner.user_id).await;
+        let mut stranger_client = state.hub.connect_test_client(stranger.user_id).await;
+
+        let compose = json!({ "email_id": Uuid::nil(), "recipient_id": owner.user_id, "content": "Before the revocation" });
+        let (_, earlier) = send_as(app(state.clone()), Some(&sender_token), Method::POST, "/encryption/encrypt", Some(compose.clone())).await;
+
+        let revoke = format!("/encryption/keys/{}/revoke", key["key_id"].as_str().unwrap());
+        let (status, _) = send_as(app(state.clone()), Some(&sender_token), Method::POST, &revoke, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, revocation) = send_as(app(state.clone()), Some(&owner_token), Method::POST, &revoke, Some(json!({
+            "reason": "compromised",
+        }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(revocation["reason"], "compromised");
+        let (status, _) = send_as(app(state.clone()), Some(&owner_token), Method::POST, &revoke, None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // The stored record verifies against the owner's signing key
+        let stored: KeyRevocation = sqlx::query_as("SELECT * FROM key_revocations WHERE key_id = $1")
+            .bind(Uuid::parse_str(key["key_id"].as_str().unwrap()).unwrap())
+            .fetch_one(&state.db_pool)
+            .await
+            .unwrap();
+        let signing_key = state.key_store.signing_key(stored.signing_key_id).await.unwrap().unwrap();
+        let record = RevocationRecord {
+            key_id: stored.key_id,
+            user_id: stored.user_id,
+            reason: &stored.reason,
+            revoked_at: stored.revoked_at,
+        };
+        assert_eq!(
+            SignatureService::new().verify_revocation(&signing_key.public_key, &stored.signature, &record),
+            SignatureStatus::Verified,
+        );
+
+        let Some(tokio_tungstenite::tungstenite::Message::Text(text)) = client.recv().await else { panic!("expected a text message") };
+        let message: WebSocketMessage = serde_json::from_str(&text).unwrap();
+        assert_eq!(message.message_type, WebSocketMessageType::KeyRevocation);
+        assert_eq!(message.payload["key_id"], key["key_id"]);
+        // The owner's other devices hear of it too, but users without them as a contact do not
+        assert!(matches!(owner_client.try_recv(), Ok(tokio_tungstenite::tungstenite::Message::Text(_))));
+        assert!(stranger_client.try_recv().is_err());
+
+        // Nothing new is encrypted to the revoked key, but earlier mail still opens
+        let (status, _) = send_as(app(state.clone()), Some(&sender_token), Method::POST, "/encryption/encrypt", Some(compose.clone())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, body) = send_as(app(state.clone()), Some(&owner_token), Method::POST, "/encryption/decrypt", Some(json!({
+            "senderId": sender.user_id,
+            "encryptedContent": earlier["encrypted_content"],
+        }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["content"], "Before the revocation");
+
+        let (_, status) = send_as(app(state.clone()), Some(&owner_token), Method::GET, "/encryption/status", None).await;
+        assert_eq!(status["status"], "revoked");
+        assert_eq!(status["revoked_keys"][0]["key_id"], key["key_id"]);
+        let directory = format!("/directory/{}", owner.user_id);
+        let (_, entry) = send_as(app(state.clone()), Some(&sender_token), Method::GET, &directory, None).await;
+        assert_eq!(entry["status"], "revoked");
+        assert_eq!(entry["key_id"], key["key_id"]);
+
+        // A new key is published alongside the record of the revoked one
+        let (_, new_key) = send_as(app(state.clone()), Some(&owner_token), Method::POST, "/encryption/generate-key-pair", None).await;
+        let (_, entry) = send_as(app(state.clone()), Some(&sender_token), Method::GET, &directory, None).await;
+        assert_eq!(entry["status"], "active");
+        assert_eq!(entry["key_id"], new_key["key_id"]);
+        assert_eq!(entry["revoked_keys"][0]["key_id"], key["key_id"]);
+        let (status, _) = send_as(app(state), Some(&sender_token), Method::POST, "/encryption/encrypt", Some(compose)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_signature_verification() {
+        let state = test_state().await;
+        let sender = insert_user(&state).await;
+        let recipient = insert_user(&state).await;
+        let impostor = insert_user(&state).await;
+        let sender_token = login(&state, &sender).await;
+        let recipient_token = login(&state, &recipient).await;
+
+        for token in [&sender_token, &recipient_token] {
+            send_as(app(state.clone()), Some(token), Method::POST, "/encryption/generate-key-pair", None).await;
+        }
+
+        // Content that is not stored is encrypted for the nil email ID
+        let compose = |email_id: Option<Uuid>| json!({
+            "email_id": email_id,
+            "recipient_id": recipient.user_id,
+            "content": "Signed hello",
+            "subject": "Greetings",
+        });
+        let (status, encrypted) = send_as(app(state.clone()), Some(&sender_token), Method::POST, "/encryption/encrypt", Some(compose(Some(Uuid::nil())))).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let decrypt = |sender_id: Uuid, subject: &str| json!({
+            "encryptedContent": encrypted["encrypted_content"],
+            "encryptedSharedSecret": encrypted["encrypted_shared_secret"],
+            "senderId": sender_id,
+            "subject": subject,
+            "signature": encrypted["signature"],
+            "signingKeyId": encrypted["signing_key_id"],
+        });
+
+        let (status, body) = send_as(app(state.clone()), Some(&recipient_token), Method::POST, "/encryption/decrypt", Some(decrypt(sender.user_id, "Greetings"))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["content"], "Signed hello");
+        assert_eq!(body["signature_status"], "verified");
+        assert_eq!(body["sender_id"], sender.user_id.to_string());
+
+        // Claiming someone else sent it fails outright; changing the subject breaks the signature
+        let (_, body) = send_as(app(state.clone()), Some(&recipient_token), Method::POST, "/encryption/decrypt", Some(decrypt(impostor.user_id, "Greetings"))).await;
+        assert_eq!(body["error"], "DECRYPTION_ERROR");
+        let (_, body) = send_as(app(state.clone()), Some(&recipient_token), Method::POST, "/encryption/decrypt", Some(decrypt(sender.user_id, "Urgent"))).await;
+        assert_eq!(body["signature_status"], "invalid");
+
+        // A stored email is verified against its own header and signature
+        let (_, encrypted) = send_as(app(state.clone()), Some(&sender_token), Method::POST, "/encryption/encrypt", Some(compose(None))).await;
+        let (status, email) = send_as(app(state.clone()), Some(&sender_token), Method::POST, "/emails", Some(json!({
+            "email_id": encrypted["email_id"],
+            "recipient_id": recipient.user_id,
+            "encrypted_headers": BASE64.decode(encrypted["encrypted_headers"].as_str().unwrap()).unwrap(),
+            "encrypted_content": BASE64.decode(encrypted["encrypted_content"].as_str().unwrap()).unwrap(),
+            "encrypted_shared_secret": BASE64.decode(encrypted["encrypted_shared_secret"].as_str().unwrap()).unwrap(),
+            "encryption_method": encrypted["encryption_method"],
+            "signature": BASE64.decode(encrypted["signature"].as_str().unwrap()).unwrap(),
+            "signing_key_id": encrypted["signing_key_id"],
+        }))).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, body) = send_as(app(state), Some(&recipient_token), Method::POST, "/encryption/decrypt", Some(json!({
+            "emailId": email["email_id"],
+            "encryptedContent": encrypted["encrypted_content"],
+            "encryptedSharedSecret": encrypted["encrypted_shared_secret"],
+        }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["subject"], "Greetings");
+        assert_eq!(body["signature_status"], "verified");
+    }
+
+    #[test]
+    fn test_decode_base64_reports_field() {
+        let error = decode_base64("ciphertext", "not base64!").unwrap_err();
+        assert!(error.to_string().contains("ciphertext"));
+    }
+}
@@ -0,0 +1,624 @@
+// src/api/prekeys.rs
+use std::sync::Arc;
+
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::routing::{delete, get, post};
+use axum::{Extension, Json, Router};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::api::auth::AuthUser;
+use crate::api::encryption::recipient_key;
+use crate::database::models::{OneTimePrekey, QuantumKey};
+use crate::quantum_encryption::kem::kem_for_suite;
+use crate::quantum_encryption::key_exchange::KeyPair;
+use crate::quantum_encryption::secret::{Secret, SecretBytes};
+use crate::quantum_encryption::signing::{PrekeyRecord, SignatureService, SignatureStatus};
+use crate::utils::error_handling::AppError;
+use crate::AppState;
+
+/// Most prekeys accepted in one upload; each private key is wrapped with its own Argon2 salt
+const MAX_BATCH_SIZE: usize = 50;
+
+/// Most unclaimed prekeys a user may hold
+const MAX_POOL_SIZE: i64 = 100;
+
+/// How long an uploaded prekey may wait to be claimed before it is deleted
+///
+/// A claimed prekey stays until its owner deletes it, so mail encrypted to it and its
+/// attachments stay readable until the client has kept them.
+const PREKEY_LIFETIME: Duration = Duration::days(30);
+
+/// Most prekeys of one user that one caller may hold claimed and not yet deleted within `CLAIM_WINDOW`
+///
+/// A caller over the limit is handed the long-term key, so nobody can drain someone
+/// else's pool by claiming prekeys they never send to.
+const MAX_PENDING_CLAIMS: i64 = 10;
+
+/// Window in which pending claims count towards `MAX_PENDING_CLAIMS`
+const CLAIM_WINDOW: Duration = Duration::hours(1);
+
+/// Routes for `/api/encryption/prekeys`, merged into the encryption routes
+pub fn routes() -> Router {
+    Router::new()
+        .route("/prekeys", get(pool_status).post(upload_prekeys))
+        .route("/prekeys/:user_id/claim", post(claim))
+        .route("/prekeys/:key_id", delete(delete_prekey))
+}
+
+/// A prekey generated by the client and signed with the user's active signing key
+///
+/// The private half is kept wrapped so mail encrypted to the prekey can be decrypted. It is
+/// deleted when the prekey expires unclaimed, or when the owner deletes it after keeping
+/// the mail encrypted to it.
+#[derive(Debug, Deserialize)]
+pub struct UploadedPrekey {
+    pub key_id: Uuid,
+    pub algorithm: String,
+    pub public_key: String,
+    pub private_key: Secret<String>,
+    /// Signature over the prekey as `SignatureService::sign_prekey` makes it
+    pub signature: String,
+    pub signing_key_id: Uuid,
+}
+
+/// Request body for adding one-time prekeys to the user's pool
+#[derive(Debug, Deserialize)]
+pub struct UploadPrekeysRequest {
+    pub prekeys: Vec<UploadedPrekey>,
+}
+
+/// Public half of a one-time prekey with the owner's signature over it
+#[derive(Debug, Serialize)]
+pub struct PrekeyResponse {
+    pub key_id: Uuid,
+    pub algorithm: String,
+    pub public_key: String,
+    pub signature: String,
+    pub signing_key_id: Uuid,
+    pub expires_at: OffsetDateTime,
+}
+
+/// How many of the user's prekeys are still waiting to be claimed
+#[derive(Debug, Serialize)]
+pub struct PrekeyPoolResponse {
+    pub available: i64,
+    pub max_available: i64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub created: Vec<PrekeyResponse>,
+}
+
+/// The key a sender should encrypt one message to
+///
+/// `one_time` is false when the recipient's pool was empty, or the caller has too many
+/// pending claims, and their long-term key is handed out instead; it then carries no
+/// prekey signature.
+#[derive(Debug, Serialize)]
+pub struct PrekeyBundle {
+    pub user_id: Uuid,
+    pub key_id: Uuid,
+    pub algorithm: String,
+    pub public_key: String,
+    pub one_time: bool,
+    pub signature: Option<String>,
+    pub signing_key_id: Option<Uuid>,
+    pub signing_public_key: Option<String>,
+}
+
+/// A public key to encapsulate one message to
+#[derive(Debug, Clone)]
+pub(crate) struct EncapsulationKey {
+    pub key_id: Uuid,
+    pub algorithm: String,
+    pub public_key: Vec<u8>,
+    /// Whether this is a claimed one-time prekey rather than the long-term key
+    pub one_time: bool,
+}
+
+impl From<KeyPair> for EncapsulationKey {
+    fn from(key_pair: KeyPair) -> Self {
+        Self {
+            key_id: key_pair.id,
+            algorithm: key_pair.algorithm,
+            public_key: key_pair.public_key,
+            one_time: false,
+        }
+    }
+}
+
+/// A claimed prekey together with the public key its signature was checked against
+struct ClaimedPrekey {
+    prekey: OneTimePrekey,
+    signing_public_key: Vec<u8>,
+}
+
+/// Counts the user's unclaimed, unexpired prekeys
+async fn available_prekeys(state: &AppState, user_id: Uuid) -> Result<i64, AppError> {
+    Ok(sqlx::query_scalar(
+        "SELECT COUNT(*) FROM one_time_prekeys p JOIN quantum_keys k ON k.key_id = p.key_id
+         WHERE p.user_id = $1 AND p.claimed_at IS NULL AND k.expiration_timestamp > $2",
+    )
+        .bind(user_id)
+        .bind(OffsetDateTime::now_utc())
+        .fetch_one(&state.db_pool)
+        .await?)
+}
+
+/// Counts the user's prekeys the caller claimed recently that the user has not deleted yet
+async fn pending_claims(state: &AppState, user_id: Uuid, claimed_by: Uuid) -> Result<i64, AppError> {
+    Ok(sqlx::query_scalar(
+        "SELECT COUNT(*) FROM one_time_prekeys WHERE user_id = $1 AND claimed_by = $2 AND claimed_at > $3",
+    )
+        .bind(user_id)
+        .bind(claimed_by)
+        .bind(OffsetDateTime::now_utc() - CLAIM_WINDOW)
+        .fetch_one(&state.db_pool)
+        .await?)
+}
+
+/// Takes the user's oldest usable prekey out of the pool on behalf of `claimed_by`
+///
+/// The row is marked claimed in the same statement that selects it, and rows locked by a
+/// concurrent claim are skipped, so no prekey is ever handed out twice. A prekey whose
+/// signature does not verify is deleted and the next one is tried. Returns `None` when the
+/// pool is empty or the caller already holds `MAX_PENDING_CLAIMS` pending claims.
+async fn claim_prekey(state: &AppState, user_id: Uuid, claimed_by: Uuid) -> Result<Option<ClaimedPrekey>, AppError> {
+    if pending_claims(state, user_id, claimed_by).await? >= MAX_PENDING_CLAIMS {
+        warn!("User {} has too many pending claims on the prekeys of user {}", claimed_by, user_id);
+        return Ok(None);
+    }
+
+    let service = SignatureService::new();
+    loop {
+        let now = OffsetDateTime::now_utc();
+        let Some(prekey) = sqlx::query_as::<_, OneTimePrekey>(
+            "UPDATE one_time_prekeys SET claimed_at = $2, claimed_by = $3 WHERE key_id = (
+                 SELECT p.key_id FROM one_time_prekeys p JOIN quantum_keys k ON k.key_id = p.key_id
+                 WHERE p.user_id = $1 AND p.claimed_at IS NULL AND k.expiration_timestamp > $2
+                     AND NOT EXISTS (SELECT 1 FROM key_revocations r WHERE r.key_id = p.key_id)
+                 ORDER BY p.created_at
+                 LIMIT 1
+                 FOR UPDATE OF p SKIP LOCKED
+             )
+             RETURNING *",
+        )
+            .bind(user_id)
+            .bind(now)
+            .bind(claimed_by)
+            .fetch_optional(&state.db_pool)
+            .await? else {
+            return Ok(None);
+        };
+
+        let record = PrekeyRecord {
+            key_id: prekey.key_id,
+            user_id: prekey.user_id,
+            algorithm: &prekey.encryption_method,
+            public_key: &prekey.public_key,
+        };
+        let signing_key = state.key_store.signing_key(prekey.signing_key_id).await?
+            .filter(|key| key.user_id == user_id);
+        match signing_key {
+            Some(key) if service.verify_prekey(&key.public_key, &prekey.signature, &record) == SignatureStatus::Verified => {
+                debug!("Claimed prekey {} of user {} for user {}", prekey.key_id, user_id, claimed_by);
+                return Ok(Some(ClaimedPrekey { prekey, signing_public_key: key.public_key }));
+            }
+            _ => {
+                error!("Discarding prekey {} of user {}: signature does not verify", prekey.key_id, user_id);
+                state.key_store.delete_claimed_prekey(prekey.key_id).await?;
+            }
+        }
+    }
+}
+
+/// Picks the key `sender_id` encrypts one message to: a claimed prekey, else the long-term key
+///
+/// `long_term` must already have been checked to be the recipient's active, unrevoked key.
+pub(crate) async fn encapsulation_key(
+    state: &AppState,
+    user_id: Uuid,
+    sender_id: Uuid,
+    long_term: KeyPair,
+) -> Result<EncapsulationKey, AppError> {
+    match claim_prekey(state, user_id, sender_id).await? {
+        Some(ClaimedPrekey { prekey, .. }) => Ok(EncapsulationKey {
+            key_id: prekey.key_id,
+            algorithm: prekey.encryption_method,
+            public_key: prekey.public_key,
+            one_time: true,
+        }),
+        None => {
+            info!("No prekey of user {} for sender {}, using the long-term key", user_id, sender_id);
+            Ok(long_term.into())
+        }
+    }
+}
+
+/// Checks whether the key is a claimed one-time prekey rather than a long-term key
+pub(crate) async fn is_claimed_prekey(state: &AppState, key_id: Uuid) -> Result<bool, AppError> {
+    Ok(sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM one_time_prekeys WHERE key_id = $1 AND claimed_at IS NOT NULL)")
+        .bind(key_id)
+        .fetch_one(&state.db_pool)
+        .await?)
+}
+
+/// Deletes the private half of one of the user's claimed prekeys
+///
+/// Reading mail never deletes the key that opened it, so the client calls this once it has
+/// kept the message and its attachments; from then on they cannot be decrypted again, even
+/// by someone holding the database and the key-wrapping secret.
+async fn delete_prekey(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Path(key_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let owner: Option<Uuid> = sqlx::query_scalar("SELECT user_id FROM one_time_prekeys WHERE key_id = $1")
+        .bind(key_id)
+        .fetch_optional(&state.db_pool)
+        .await?;
+    if owner != Some(user.user_id) || !state.key_store.delete_claimed_prekey(key_id).await? {
+        return Err(AppError::NotFoundError(format!("No claimed prekey {}", key_id)));
+    }
+    info!("User {} deleted one-time prekey {}", user.user_id, key_id);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Reports how many prekeys remain in the user's pool
+async fn pool_status(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+) -> Result<Json<PrekeyPoolResponse>, AppError> {
+    Ok(Json(PrekeyPoolResponse {
+        available: available_prekeys(&state, user.user_id).await?,
+        max_available: MAX_POOL_SIZE,
+        created: Vec::new(),
+    }))
+}
+
+/// Checks an uploaded prekey and turns it into the rows to store
+///
+/// The signature must verify against the user's active signing key, and the private half
+/// must open what is encapsulated to the public half.
+fn check_prekey(
+    user_id: Uuid,
+    signing_key_id: Uuid,
+    signing_public_key: &[u8],
+    uploaded: UploadedPrekey,
+) -> Result<(QuantumKey, OneTimePrekey), AppError> {
+    let invalid = |reason: String| AppError::ValidationError(format!("Prekey {}: {}", uploaded.key_id, reason));
+    let public_key = decode_base64(&uploaded.public_key).map_err(invalid)?;
+    let private_key = SecretBytes::new(decode_base64(uploaded.private_key.expose_secret()).map_err(invalid)?);
+    let signature = decode_base64(&uploaded.signature).map_err(invalid)?;
+
+    if uploaded.signing_key_id != signing_key_id {
+        return Err(invalid("must be signed with the user's active signing key".to_string()));
+    }
+    let record = PrekeyRecord {
+        key_id: uploaded.key_id,
+        user_id,
+        algorithm: &uploaded.algorithm,
+        public_key: &public_key,
+    };
+    if SignatureService::new().verify_prekey(signing_public_key, &signature, &record) != SignatureStatus::Verified {
+        return Err(invalid("signature does not verify".to_string()));
+    }
+
+    let kem = kem_for_suite(&uploaded.algorithm).map_err(|e| invalid(e.to_string()))?;
+    let (ciphertext, shared_secret) = kem.encapsulate(&public_key)
+        .map_err(|e| invalid(format!("invalid public key: {}", e)))?;
+    if !kem.decapsulate(private_key.expose_secret(), &ciphertext).is_ok_and(|decapsulated| decapsulated == shared_secret) {
+        return Err(invalid("private key does not match the public key".to_string()));
+    }
+
+    let now = OffsetDateTime::now_utc();
+    let key = QuantumKey {
+        key_id: uploaded.key_id,
+        user_id,
+        public_key: public_key.clone(),
+        private_key,
+        encryption_method: uploaded.algorithm.clone(),
+        key_generation_timestamp: now,
+        expiration_timestamp: now + PREKEY_LIFETIME,
+        is_active: false,
+    };
+    let prekey = OneTimePrekey {
+        key_id: uploaded.key_id,
+        user_id,
+        public_key,
+        encryption_method: uploaded.algorithm,
+        signature,
+        signing_key_id,
+        created_at: now,
+        claimed_at: None,
+        claimed_by: None,
+    };
+    Ok((key, prekey))
+}
+
+/// Adds a batch of one-time prekeys the client generated and signed with its identity key
+///
+/// Every prekey is checked before any is stored, so a batch is accepted or rejected whole.
+/// Each private half is stored as an inactive quantum key in the same transaction as its
+/// pool entry, so mail encrypted to a claimed prekey is decrypted like mail to any older key.
+async fn upload_prekeys(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Json(request): Json<UploadPrekeysRequest>,
+) -> Result<(StatusCode, Json<PrekeyPoolResponse>), AppError> {
+    let count = request.prekeys.len();
+    if count == 0 || count > MAX_BATCH_SIZE {
+        return Err(AppError::ValidationError(format!("Between 1 and {} prekeys must be uploaded", MAX_BATCH_SIZE)));
+    }
+    let deleted = state.key_store.delete_expired_prekeys(OffsetDateTime::now_utc()).await?;
+    if deleted > 0 {
+        info!("Deleted {} expired one-time prekeys", deleted);
+    }
+    let available = available_prekeys(&state, user.user_id).await?;
+    if available + count as i64 > MAX_POOL_SIZE {
+        return Err(AppError::ValidationError(format!(
+            "{} prekeys are still unclaimed, at most {} may be held", available, MAX_POOL_SIZE
+        )));
+    }
+
+    let signing_key = state.key_store.active_signing_key(user.user_id).await?
+        .ok_or_else(|| AppError::ValidationError("Generate a key pair before uploading prekeys".to_string()))?;
+    let prekeys = request.prekeys.into_iter()
+        .map(|uploaded| check_prekey(user.user_id, signing_key.key_id, &signing_key.public_key, uploaded))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut created = Vec::with_capacity(count);
+    for (key, prekey) in prekeys {
+        state.key_store.insert_prekey(&key, &prekey).await
+            .map_err(|e| match e.downcast_ref::<sqlx::Error>().and_then(|e| e.as_database_error()).and_then(|db| db.code()) {
+                Some(code) if code == "23505" => AppError::ValidationError(format!("Prekey {} already exists", key.key_id)),
+                _ => AppError::from(e),
+            })?;
+        created.push(PrekeyResponse {
+            key_id: prekey.key_id,
+            algorithm: prekey.encryption_method,
+            public_key: BASE64.encode(&prekey.public_key),
+            signature: BASE64.encode(&prekey.signature),
+            signing_key_id: prekey.signing_key_id,
+            expires_at: key.expiration_timestamp,
+        });
+    }
+
+    info!("Added {} one-time prekeys for user {}", count, user.user_id);
+    Ok((StatusCode::CREATED, Json(PrekeyPoolResponse {
+        available: available + count as i64,
+        max_available: MAX_POOL_SIZE,
+        created,
+    })))
+}
+
+fn decode_base64(value: &str) -> Result<Vec<u8>, String> {
+    BASE64.decode(value).map_err(|e| format!("not valid base64: {}", e))
+}
+
+/// Hands out one of a user's prekeys to the caller, taking it out of the pool
+///
+/// Falls back to the recipient's long-term key once the pool is empty or the caller holds
+/// too many pending claims on it.
+async fn claim(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(caller): AuthUser,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<PrekeyBundle>, AppError> {
+    let long_term = recipient_key(&state, user_id).await?;
+    let bundle = match claim_prekey(&state, user_id, caller.user_id).await? {
+        Some(ClaimedPrekey { prekey, signing_public_key }) => PrekeyBundle {
+            user_id,
+            key_id: prekey.key_id,
+            algorithm: prekey.encryption_method,
+            public_key: BASE64.encode(&prekey.public_key),
+            one_time: true,
+            signature: Some(BASE64.encode(&prekey.signature)),
+            signing_key_id: Some(prekey.signing_key_id),
+            signing_public_key: Some(BASE64.encode(&signing_public_key)),
+        },
+        None => PrekeyBundle {
+            user_id,
+            key_id: long_term.id,
+            algorithm: long_term.algorithm,
+            public_key: BASE64.encode(&long_term.public_key),
+            one_time: false,
+            signature: None,
+            signing_key_id: None,
+            signing_public_key: None,
+        },
+    };
+    Ok(Json(bundle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_support::{app, insert_user, login, send_as, test_state};
+    use crate::database::models::User;
+    use crate::quantum_encryption::key_exchange::QuantumKeyExchange;
+    use axum::http::Method;
+    use serde_json::{json, Value};
+
+    /// Generates prekeys the way a client does, signed with the user's active signing key
+    async fn signed_prekeys(state: &AppState, user: &User, count: usize) -> Value {
+        let signing_key = SignatureService::from_db_model(
+            state.key_store.active_signing_key(user.user_id).await.unwrap().expect("signing key"),
+        );
+        let key_exchange = QuantumKeyExchange::new(&state.config.encryption);
+        let prekeys: Vec<Value> = (0..count).map(|_| {
+            let key_pair = key_exchange.generate_key_pair().unwrap();
+            let signature = SignatureService::new()
+                .sign_prekey(&signing_key, &PrekeyRecord {
+                    key_id: key_pair.id,
+                    user_id: user.user_id,
+                    algorithm: &key_pair.algorithm,
+                    public_key: &key_pair.public_key,
+                })
+                .unwrap();
+            json!({
+                "key_id": key_pair.id,
+                "algorithm": key_pair.algorithm,
+                "public_key": BASE64.encode(&key_pair.public_key),
+                "private_key": BASE64.encode(key_pair.private_key.expose_secret()),
+                "signature": BASE64.encode(&signature),
+                "signing_key_id": signing_key.id,
+            })
+        }).collect();
+        json!({ "prekeys": prekeys })
+    }
+
+    #[tokio::test]
+    async fn test_prekeys_are_claimed_once_then_fall_back() {
+        let state = test_state().await;
+        let alice = insert_user(&state).await;
+        let bob = insert_user(&state).await;
+        let alice_token = login(&state, &alice).await;
+        let bob_token = login(&state, &bob).await;
+        let (_, long_term) = send_as(app(state.clone()), Some(&bob_token), Method::POST, "/encryption/generate-key-pair", None).await;
+        send_as(app(state.clone()), Some(&alice_token), Method::POST, "/encryption/generate-key-pair", None).await;
+
+        let (status, _) = send_as(app(state.clone()), Some(&bob_token), Method::POST, "/encryption/prekeys", Some(json!({ "prekeys": [] }))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, pool) = send_as(app(state.clone()), Some(&bob_token), Method::POST, "/encryption/prekeys", Some(signed_prekeys(&state, &bob, 2).await)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(pool["available"], 2);
+        let created: Vec<&str> = pool["created"].as_array().unwrap().iter()
+            .map(|prekey| prekey["key_id"].as_str().unwrap())
+            .collect();
+
+        // The directory keeps publishing the long-term key
+        let (_, entry) = send_as(app(state.clone()), Some(&alice_token), Method::GET, &format!("/directory/{}", bob.user_id), None).await;
+        assert_eq!(entry["key_id"], long_term["key_id"]);
+
+        // A handed-out bundle carries a signature by Bob's identity key
+        let claim_uri = format!("/encryption/prekeys/{}/claim", bob.user_id);
+        let (status, bundle) = send_as(app(state.clone()), Some(&alice_token), Method::POST, &claim_uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(bundle["one_time"], true);
+        assert_eq!(bundle["key_id"], created[0]);
+        let record = PrekeyRecord {
+            key_id: bundle["key_id"].as_str().unwrap().parse().unwrap(),
+            user_id: bob.user_id,
+            algorithm: bundle["algorithm"].as_str().unwrap(),
+            public_key: &BASE64.decode(bundle["public_key"].as_str().unwrap()).unwrap(),
+        };
+        let signing_public_key = BASE64.decode(bundle["signing_public_key"].as_str().unwrap()).unwrap();
+        let signature = BASE64.decode(bundle["signature"].as_str().unwrap()).unwrap();
+        assert_eq!(SignatureService::new().verify_prekey(&signing_public_key, &signature, &record), SignatureStatus::Verified);
+
+        // Encrypting consumes the last prekey, and Bob can still read the message
+        let (_, encrypted) = send_as(app(state.clone()), Some(&alice_token), Method::POST, "/encryption/encrypt", Some(json!({
+            "email_id": Uuid::nil(),
+            "content": "for one prekey only",
+            "recipient_id": bob.user_id,
+        }))).await;
+        assert_eq!(encrypted["recipient_key_id"], created[1]);
+        assert_eq!(encrypted["one_time_prekey"], true);
+        let (_, pool) = send_as(app(state.clone()), Some(&bob_token), Method::GET, "/encryption/prekeys", None).await;
+        assert_eq!(pool["available"], 0);
+
+        let decrypt_request = json!({
+            "encryptedContent": encrypted["encrypted_content"],
+            "encryptedSharedSecret": encrypted["encrypted_shared_secret"],
+            "senderId": alice.user_id,
+            "signature": encrypted["signature"],
+            "signingKeyId": encrypted["signing_key_id"],
+        });
+        let (status, decrypted) = send_as(app(state.clone()), Some(&bob_token), Method::POST, "/encryption/decrypt", Some(decrypt_request.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(decrypted["content"], "for one prekey only");
+        assert_eq!(decrypted["prekey_id"], created[1]);
+
+        // Reading keeps the prekey, so the message can be opened again until Bob deletes it
+        let (status, _) = send_as(app(state.clone()), Some(&bob_token), Method::POST, "/encryption/decrypt", Some(decrypt_request.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        let prekey_uri = format!("/encryption/prekeys/{}", created[1]);
+        let (status, _) = send_as(app(state.clone()), Some(&alice_token), Method::DELETE, &prekey_uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send_as(app(state.clone()), Some(&bob_token), Method::DELETE, &prekey_uri, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send_as(app(state.clone()), Some(&bob_token), Method::DELETE, &prekey_uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Deleting the prekey removed its private half, so the message cannot be opened again
+        let prekey_id: Uuid = created[1].parse().unwrap();
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM quantum_keys WHERE key_id = $1")
+            .bind(prekey_id)
+            .fetch_one(&state.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 0);
+        assert!(!state.key_store.key_history(bob.user_id).await.unwrap().iter().any(|key| key.key_id == prekey_id));
+        let (status, _) = send_as(app(state.clone()), Some(&bob_token), Method::POST, "/encryption/decrypt", Some(decrypt_request)).await;
+        assert_ne!(status, StatusCode::OK);
+
+        // An empty pool falls back to the long-term key
+        let (_, bundle) = send_as(app(state.clone()), Some(&alice_token), Method::POST, &claim_uri, None).await;
+        assert_eq!(bundle["one_time"], false);
+        assert_eq!(bundle["key_id"], long_term["key_id"]);
+        let (_, encrypted) = send_as(app(state.clone()), Some(&alice_token), Method::POST, "/encryption/encrypt", Some(json!({
+            "content": "for the long-term key",
+            "recipient_id": bob.user_id,
+        }))).await;
+        assert_eq!(encrypted["recipient_key_id"], long_term["key_id"]);
+        assert_eq!(encrypted["one_time_prekey"], false);
+    }
+
+    #[tokio::test]
+    async fn test_uploaded_prekeys_must_be_signed_by_their_owner() {
+        let state = test_state().await;
+        let alice = insert_user(&state).await;
+        let bob = insert_user(&state).await;
+        let alice_token = login(&state, &alice).await;
+        let bob_token = login(&state, &bob).await;
+        send_as(app(state.clone()), Some(&alice_token), Method::POST, "/encryption/generate-key-pair", None).await;
+        send_as(app(state.clone()), Some(&bob_token), Method::POST, "/encryption/generate-key-pair", None).await;
+
+        // Alice's prekeys are signed by her, not Bob
+        let (status, _) = send_as(app(state.clone()), Some(&bob_token), Method::POST, "/encryption/prekeys", Some(signed_prekeys(&state, &alice, 1).await)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // A swapped public key breaks the signature, and nothing of the batch is stored
+        let mut batch = signed_prekeys(&state, &bob, 2).await;
+        let other = signed_prekeys(&state, &bob, 1).await;
+        batch["prekeys"][1]["public_key"] = other["prekeys"][0]["public_key"].clone();
+        let (status, error) = send_as(app(state.clone()), Some(&bob_token), Method::POST, "/encryption/prekeys", Some(batch)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(error["message"].as_str().unwrap().contains("signature does not verify"), "{}", error);
+        let (_, pool) = send_as(app(state.clone()), Some(&bob_token), Method::GET, "/encryption/prekeys", None).await;
+        assert_eq!(pool["available"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_claims_are_limited_per_caller() {
+        let state = test_state().await;
+        let alice = insert_user(&state).await;
+        let bob = insert_user(&state).await;
+        let carol = insert_user(&state).await;
+        let alice_token = login(&state, &alice).await;
+        let bob_token = login(&state, &bob).await;
+        let carol_token = login(&state, &carol).await;
+        send_as(app(state.clone()), Some(&bob_token), Method::POST, "/encryption/generate-key-pair", None).await;
+        let batch = signed_prekeys(&state, &bob, MAX_PENDING_CLAIMS as usize + 1).await;
+        let (status, _) = send_as(app(state.clone()), Some(&bob_token), Method::POST, "/encryption/prekeys", Some(batch)).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        // Alice cannot drain Bob's pool: past the limit she is handed the long-term key
+        let claim_uri = format!("/encryption/prekeys/{}/claim", bob.user_id);
+        for _ in 0..MAX_PENDING_CLAIMS {
+            let (_, bundle) = send_as(app(state.clone()), Some(&alice_token), Method::POST, &claim_uri, None).await;
+            assert_eq!(bundle["one_time"], true);
+        }
+        let (_, bundle) = send_as(app(state.clone()), Some(&alice_token), Method::POST, &claim_uri, None).await;
+        assert_eq!(bundle["one_time"], false);
+
+        // and the prekey she did not get is still there for others
+        let (_, bundle) = send_as(app(state.clone()), Some(&carol_token), Method::POST, &claim_uri, None).await;
+        assert_eq!(bundle["one_time"], true);
+    }
+}
@@ -0,0 +1,193 @@
+// src/api/presence.rs
+use std::sync::Arc;
+
+use axum::routing::get;
+use axum::{Extension, Json, Router};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::api::auth::AuthUser;
+use crate::utils::error_handling::AppError;
+use crate::websocket::presence;
+use crate::AppState;
+
+/// Routes for `/api/presence`
+pub fn routes() -> Router {
+    Router::new()
+        .route("/", get(list_presence))
+        .route("/settings", get(get_settings).put(update_settings))
+}
+
+/// Whether a contact is online and when they were last seen
+///
+/// Both are left empty for contacts who hide their presence or who do not have the user
+/// in their own contacts.
+#[derive(Debug, Serialize)]
+pub struct ContactPresence {
+    pub contact_id: Uuid,
+    pub user_id: Uuid,
+    pub online: bool,
+    pub last_seen_at: Option<OffsetDateTime>,
+}
+
+/// The user's presence privacy setting
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PresenceSettings {
+    /// Whether contacts are told when the user comes online and when they were last seen
+    pub share_presence: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct ContactPresenceRow {
+    contact_id: Uuid,
+    user_id: Uuid,
+    last_seen_at: Option<OffsetDateTime>,
+    shared: bool,
+}
+
+/// Lists the presence of the user's contacts that are registered users
+async fn list_presence(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+) -> Result<Json<Vec<ContactPresence>>, AppError> {
+    let rows = sqlx::query_as::<_, ContactPresenceRow>(
+        "SELECT c.contact_id, u.user_id, u.last_seen_at,
+                COALESCE(s.is_enabled, TRUE)
+                    AND EXISTS (SELECT 1 FROM contacts m WHERE m.user_id = u.user_id AND m.contact_user_id = $1) AS shared
+         FROM contacts c
+         JOIN users u ON u.user_id = c.contact_user_id
+         LEFT JOIN notification_settings s ON s.user_id = u.user_id AND s.notification_type = $2
+         WHERE c.user_id = $1
+         ORDER BY c.name, c.email",
+    )
+        .bind(user.user_id)
+        .bind(presence::PRESENCE_SETTING)
+        .fetch_all(&state.db_pool)
+        .await?;
+
+    let mut contacts = Vec::with_capacity(rows.len());
+    for row in rows {
+        let online = row.shared && state.hub.is_online(row.user_id).await;
+        contacts.push(ContactPresence {
+            contact_id: row.contact_id,
+            user_id: row.user_id,
+            online,
+            last_seen_at: row.last_seen_at.filter(|_| row.shared && !online),
+        });
+    }
+    Ok(Json(contacts))
+}
+
+/// Returns whether the user shares their presence
+async fn get_settings(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+) -> Result<Json<PresenceSettings>, AppError> {
+    let share_presence = presence::is_shared(&state.db_pool, user.user_id).await?;
+    Ok(Json(PresenceSettings { share_presence }))
+}
+
+/// Changes whether the user shares their presence
+///
+/// A user who is online appears to go offline to their contacts when they hide their
+/// presence, and online again when they share it.
+async fn update_settings(
+    Extension(state): Extension<Arc<AppState>>,
+    AuthUser(user): AuthUser,
+    Json(request): Json<PresenceSettings>,
+) -> Result<Json<PresenceSettings>, AppError> {
+    let was_shared = presence::is_shared(&state.db_pool, user.user_id).await?;
+    presence::set_shared(&state.db_pool, user.user_id, request.share_presence).await?;
+    info!("User {} {} their presence", user.user_id, if request.share_presence { "shares" } else { "hides" });
+
+    if was_shared != request.share_presence && state.hub.is_online(user.user_id).await {
+        let message = presence::presence_message(user.user_id, request.share_presence, None);
+        for watcher in presence::watchers(&state.db_pool, user.user_id).await? {
+            state.hub.send_to_user(watcher, &message).await;
+        }
+    }
+    Ok(Json(request))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_support::{add_contact, app, insert_user, login, send_as, test_state};
+    use crate::database::models::User;
+    use crate::websocket::fan_out::FanOut;
+    use crate::websocket::server::{WebSocketMessage, WebSocketMessageType};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+    use tokio_tungstenite::tungstenite::Message;
+
+    fn presence_of<'a>(listed: &'a serde_json::Value, user: &User) -> &'a serde_json::Value {
+        listed.as_array().unwrap().iter()
+            .find(|contact| contact["user_id"] == user.user_id.to_string())
+            .expect("Contact is missing from the presence list")
+    }
+
+    #[tokio::test]
+    async fn test_presence_can_be_hidden() -> anyhow::Result<()> {
+        let state = test_state().await;
+        let alice = insert_user(&state).await;
+        let bob = insert_user(&state).await;
+        let carol = insert_user(&state).await;
+        let dave = insert_user(&state).await;
+        for contact in [&bob, &carol, &dave] {
+            add_contact(&state, &alice, contact).await;
+        }
+        add_contact(&state, &bob, &alice).await;
+        add_contact(&state, &carol, &alice).await;
+        let alice_token = login(&state, &alice).await;
+        let bob_token = login(&state, &bob).await;
+        let mut alice_client = state.hub.connect_test_client(alice.user_id).await;
+        let _bob_client = state.hub.connect_test_client(bob.user_id).await;
+        let _dave_client = state.hub.connect_test_client(dave.user_id).await;
+        let last_seen_at = OffsetDateTime::now_utc().replace_nanosecond(0)?;
+        presence::record_last_seen(&state.db_pool, carol.user_id, last_seen_at).await?;
+        presence::record_last_seen(&state.db_pool, dave.user_id, last_seen_at).await?;
+
+        let (status, listed) = send_as(app(state.clone()), Some(&alice_token), Method::GET, "/presence", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(presence_of(&listed, &bob)["online"], true);
+        assert!(presence_of(&listed, &bob)["last_seen_at"].is_null());
+        assert_eq!(presence_of(&listed, &carol)["online"], false);
+        assert_eq!(presence_of(&listed, &carol)["last_seen_at"], json!(last_seen_at));
+
+        // Dave does not have alice as a contact, so she learns nothing about him
+        assert_eq!(presence_of(&listed, &dave)["online"], false);
+        assert!(presence_of(&listed, &dave)["last_seen_at"].is_null());
+        let fan_out = FanOut::new(state.hub.clone(), state.db_pool.clone());
+        assert!(fan_out.audience(&presence::presence_message(dave.user_id, true, None)).await?.is_empty());
+
+        // Hiding presence while online looks like going offline to contacts
+        let (status, settings) = send_as(app(state.clone()), Some(&bob_token), Method::PUT, "/presence/settings", Some(json!({
+            "share_presence": false,
+        }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(settings["share_presence"], false);
+        let Ok(Message::Text(text)) = alice_client.try_recv() else { panic!("Alice was not told bob went away") };
+        let offline: WebSocketMessage = serde_json::from_str(&text)?;
+        assert_eq!((offline.message_type, offline.sender_id), (WebSocketMessageType::UserOffline, Some(bob.user_id)));
+
+        let (_, listed) = send_as(app(state.clone()), Some(&alice_token), Method::GET, "/presence", None).await;
+        assert_eq!(presence_of(&listed, &bob)["online"], false);
+        assert!(presence_of(&listed, &bob)["last_seen_at"].is_null());
+
+        // Later presence changes of bob are not delivered at all
+        let online = presence::presence_message(bob.user_id, true, None);
+        assert!(fan_out.audience(&online).await?.is_empty());
+
+        let (_, settings) = send_as(app(state.clone()), Some(&bob_token), Method::GET, "/presence/settings", None).await;
+        assert_eq!(settings["share_presence"], false);
+        send_as(app(state.clone()), Some(&bob_token), Method::PUT, "/presence/settings", Some(json!({
+            "share_presence": true,
+        }))).await;
+        let Ok(Message::Text(text)) = alice_client.try_recv() else { panic!("Alice was not told bob is back") };
+        assert_eq!(serde_json::from_str::<WebSocketMessage>(&text)?.message_type, WebSocketMessageType::UserOnline);
+        assert_eq!(fan_out.audience(&online).await?, vec![alice.user_id]);
+        Ok(())
+    }
+}
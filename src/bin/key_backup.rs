@@ -0,0 +1,104 @@
+// src/bin/key_backup.rs
+//! Exports, verifies and restores sealed quantum key backups from the command line
+//!
+//! The passphrase is read from `KEY_BACKUP_PASSPHRASE`, or from the first line of stdin
+//! when the variable is not set, so it never appears in the process list.
+use std::env;
+use std::io::{self, BufRead};
+
+use anyhow::{Context, Result};
+use dotenv::dotenv;
+use uuid::Uuid;
+
+use quantum_email_client::config::AppConfig;
+use quantum_email_client::database::models::User;
+use quantum_email_client::quantum_encryption::key_backup::KeyBackup;
+use quantum_email_client::utils::logging;
+use quantum_email_client::AppState;
+
+const USAGE: &str = "Usage:
+  key_backup export <user-id|email> <archive>   Seal all of a user's quantum keys into a file
+  key_backup verify <archive>                   Check an archive opens and every key is intact
+  key_backup import <archive>                   Verify an archive and merge its keys";
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    logging::init_logging();
+    dotenv().ok();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["export", user, path] => export(user, path).await,
+        ["verify", path] => verify(path).map(|_| ()),
+        ["import", path] => import(path).await,
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+async fn export(user_ref: &str, path: &str) -> Result<()> {
+    let state = AppState::new(AppConfig::from_env()?).await?;
+    let user = match Uuid::parse_str(user_ref) {
+        Ok(user_id) => sqlx::query_as::<_, User>("SELECT * FROM users WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(&state.db_pool)
+            .await?,
+        Err(_) => sqlx::query_as::<_, User>("SELECT * FROM users WHERE email = $1")
+            .bind(user_ref.trim().to_lowercase())
+            .fetch_optional(&state.db_pool)
+            .await?,
+    };
+    let user = user.with_context(|| format!("No user {}", user_ref))?;
+
+    let backup = KeyBackup::export(state.key_store.as_ref(), user.user_id).await?;
+    if backup.keys.is_empty() {
+        anyhow::bail!("User {} has no quantum keys", user.username);
+    }
+    std::fs::write(path, backup.seal(&passphrase()?)?).with_context(|| format!("Could not write {}", path))?;
+    println!("Exported {} keys of {} to {}", backup.keys.len(), user.username, path);
+    Ok(())
+}
+
+fn verify(path: &str) -> Result<KeyBackup> {
+    let archive = std::fs::read(path).with_context(|| format!("Could not read {}", path))?;
+    let backup = KeyBackup::open(&archive, &passphrase()?)?;
+    println!("{} holds {} valid keys of user {}, exported {}", path, backup.keys.len(), backup.user_id, backup.exported_at);
+    for key in &backup.keys {
+        println!(
+            "  {} {} created {}{}",
+            key.key_id, key.encryption_method, key.key_generation_timestamp,
+            if key.is_active { " (active)" } else { "" },
+        );
+    }
+    Ok(backup)
+}
+
+async fn import(path: &str) -> Result<()> {
+    let backup = verify(path)?;
+    let state = AppState::new(AppConfig::from_env()?).await?;
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM users WHERE user_id = $1)")
+        .bind(backup.user_id)
+        .fetch_one(&state.db_pool)
+        .await?;
+    if !exists {
+        anyhow::bail!("User {} does not exist in this database", backup.user_id);
+    }
+
+    let report = backup.restore(state.key_store.as_ref()).await?;
+    println!("Restored {} keys, {} were already stored", report.restored.len(), report.skipped.len());
+    if let Some(key_id) = report.activated {
+        println!("Key {} is the active key again", key_id);
+    }
+    Ok(())
+}
+
+fn passphrase() -> Result<String> {
+    if let Ok(passphrase) = env::var("KEY_BACKUP_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+    let mut line = String::new();
+    io::stdin().lock().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
@@ -0,0 +1,730 @@
+// src/database/key_store.rs
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures_util::future::try_join_all;
+use sqlx::{Pool, Postgres};
+use time::OffsetDateTime;
+use tokio::sync::Mutex;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::database::models::{OneTimePrekey, QuantumKey, SigningKey};
+use crate::quantum_encryption::key_exchange::{KeyPair, QuantumKeyExchange};
+use crate::quantum_encryption::key_wrapping::{KeyWrapper, WRAPPED_KEY_MAGIC};
+use crate::quantum_encryption::secret::SecretBytes;
+
+/// Storage for users' quantum key pairs and signing keys
+///
+/// A user has at most one active key of each kind; inserting an active key deactivates
+/// the previous one, which stays available for decrypting or verifying older mail.
+#[async_trait]
+pub trait KeyStore: Send + Sync {
+    /// Stores a key, deactivating the user's other keys if this one is active
+    async fn insert(&self, key: &QuantumKey) -> Result<()>;
+
+    /// Fetches the user's active key, if any
+    async fn active_key(&self, user_id: Uuid) -> Result<Option<QuantumKey>>;
+
+    /// Lists the keys mail may have been encrypted to, the active key first and then newest first
+    async fn key_history(&self, user_id: Uuid) -> Result<Vec<QuantumKey>>;
+
+    /// Lists the user's keys that were never one-time prekeys, in the same order as `key_history`
+    async fn long_term_keys(&self, user_id: Uuid) -> Result<Vec<QuantumKey>>;
+
+    /// Marks a key inactive; returns false if the key does not exist
+    async fn deactivate(&self, key_id: Uuid) -> Result<bool>;
+
+    /// Lists active keys of all users that expire before the given time, soonest first
+    async fn expiring_keys(&self, before: OffsetDateTime) -> Result<Vec<QuantumKey>>;
+
+    /// Stores a one-time prekey's private half, which must be inactive, and its pool entry together
+    async fn insert_prekey(&self, key: &QuantumKey, prekey: &OneTimePrekey) -> Result<()>;
+
+    /// Deletes the private half of a claimed one-time prekey; returns false for any other key
+    async fn delete_claimed_prekey(&self, key_id: Uuid) -> Result<bool>;
+
+    /// Deletes every unclaimed one-time prekey that expired before the given time
+    ///
+    /// Claimed prekeys are kept until `delete_claimed_prekey`, since mail may be encrypted to them.
+    async fn delete_expired_prekeys(&self, before: OffsetDateTime) -> Result<u64>;
+
+    /// Stores a signing key, deactivating the user's other signing keys if this one is active
+    async fn insert_signing_key(&self, key: &SigningKey) -> Result<()>;
+
+    /// Fetches the user's active signing key, if any
+    async fn active_signing_key(&self, user_id: Uuid) -> Result<Option<SigningKey>>;
+
+    /// Fetches a signing key by ID, whether or not it is still active
+    async fn signing_key(&self, key_id: Uuid) -> Result<Option<SigningKey>>;
+
+    /// Fetches the user's active key as a KeyPair
+    async fn active_key_pair(&self, user_id: Uuid) -> Result<Option<KeyPair>> {
+        Ok(self.active_key(user_id).await?.map(QuantumKeyExchange::from_db_model))
+    }
+
+    /// Lists all of the user's keys as KeyPairs, in `key_history` order
+    async fn key_pair_history(&self, user_id: Uuid) -> Result<Vec<KeyPair>> {
+        Ok(self.key_history(user_id).await?
+            .into_iter()
+            .map(QuantumKeyExchange::from_db_model)
+            .collect())
+    }
+}
+
+/// KeyStore backed by the `quantum_keys` table
+pub struct PgKeyStore {
+    pool: Pool<Postgres>,
+}
+
+impl PgKeyStore {
+    /// Creates a PgKeyStore on top of an existing connection pool
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl KeyStore for PgKeyStore {
+    async fn insert(&self, key: &QuantumKey) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        if key.is_active {
+            sqlx::query("UPDATE quantum_keys SET is_active = FALSE WHERE user_id = $1 AND is_active")
+                .bind(key.user_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        sqlx::query(
+            "INSERT INTO quantum_keys (key_id, user_id, public_key, private_key, encryption_method,
+                                       key_generation_timestamp, expiration_timestamp, is_active)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        )
+            .bind(key.key_id)
+            .bind(key.user_id)
+            .bind(&key.public_key)
+            .bind(&key.private_key)
+            .bind(&key.encryption_method)
+            .bind(key.key_generation_timestamp)
+            .bind(key.expiration_timestamp)
+            .bind(key.is_active)
+            .execute(&mut *tx)
+            .await?;
+
+        if key.is_active {
+            // Keep the public key on the user row in step with the active key
+            sqlx::query("UPDATE users SET quantum_public_key = $1, updated_at = $2 WHERE user_id = $3")
+                .bind(&key.public_key)
+                .bind(OffsetDateTime::now_utc())
+                .bind(key.user_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        debug!("Stored quantum key {} for user {}", key.key_id, key.user_id);
+        Ok(())
+    }
+
+    async fn active_key(&self, user_id: Uuid) -> Result<Option<QuantumKey>> {
+        let key = sqlx::query_as::<_, QuantumKey>(
+            "SELECT * FROM quantum_keys WHERE user_id = $1 AND is_active
+             ORDER BY key_generation_timestamp DESC LIMIT 1",
+        )
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(key)
+    }
+
+    async fn key_history(&self, user_id: Uuid) -> Result<Vec<QuantumKey>> {
+        let keys = sqlx::query_as::<_, QuantumKey>(
+            // Unclaimed one-time prekeys cannot have been encrypted to yet
+            "SELECT * FROM quantum_keys k WHERE k.user_id = $1
+                 AND NOT EXISTS (SELECT 1 FROM one_time_prekeys p WHERE p.key_id = k.key_id AND p.claimed_at IS NULL)
+             ORDER BY k.is_active DESC, k.key_generation_timestamp DESC",
+        )
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(keys)
+    }
+
+    async fn long_term_keys(&self, user_id: Uuid) -> Result<Vec<QuantumKey>> {
+        let keys = sqlx::query_as::<_, QuantumKey>(
+            "SELECT * FROM quantum_keys k WHERE k.user_id = $1
+                 AND NOT EXISTS (SELECT 1 FROM one_time_prekeys p WHERE p.key_id = k.key_id)
+             ORDER BY k.is_active DESC, k.key_generation_timestamp DESC",
+        )
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(keys)
+    }
+
+    async fn deactivate(&self, key_id: Uuid) -> Result<bool> {
+        let result = sqlx::query("UPDATE quantum_keys SET is_active = FALSE WHERE key_id = $1")
+            .bind(key_id)
+            .execute(&self.pool)
+            .await?;
+        debug!("Deactivated quantum key {}", key_id);
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn expiring_keys(&self, before: OffsetDateTime) -> Result<Vec<QuantumKey>> {
+        let keys = sqlx::query_as::<_, QuantumKey>(
+            "SELECT * FROM quantum_keys WHERE is_active AND expiration_timestamp < $1
+             ORDER BY expiration_timestamp",
+        )
+            .bind(before)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(keys)
+    }
+
+    async fn insert_prekey(&self, key: &QuantumKey, prekey: &OneTimePrekey) -> Result<()> {
+        if key.is_active || key.key_id != prekey.key_id || key.user_id != prekey.user_id {
+            return Err(anyhow::anyhow!("Prekey {} does not match an inactive key of its owner", prekey.key_id));
+        }
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            "INSERT INTO quantum_keys (key_id, user_id, public_key, private_key, encryption_method,
+                                       key_generation_timestamp, expiration_timestamp, is_active)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, FALSE)",
+        )
+            .bind(key.key_id)
+            .bind(key.user_id)
+            .bind(&key.public_key)
+            .bind(&key.private_key)
+            .bind(&key.encryption_method)
+            .bind(key.key_generation_timestamp)
+            .bind(key.expiration_timestamp)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(
+            "INSERT INTO one_time_prekeys (key_id, user_id, public_key, encryption_method, signature,
+                                           signing_key_id, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+            .bind(prekey.key_id)
+            .bind(prekey.user_id)
+            .bind(&prekey.public_key)
+            .bind(&prekey.encryption_method)
+            .bind(&prekey.signature)
+            .bind(prekey.signing_key_id)
+            .bind(prekey.created_at)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        debug!("Stored one-time prekey {} for user {}", key.key_id, key.user_id);
+        Ok(())
+    }
+
+    async fn delete_claimed_prekey(&self, key_id: Uuid) -> Result<bool> {
+        // The pool entry goes with the key through ON DELETE CASCADE
+        let result = sqlx::query(
+            "DELETE FROM quantum_keys WHERE key_id = $1
+                 AND EXISTS (SELECT 1 FROM one_time_prekeys p WHERE p.key_id = $1 AND p.claimed_at IS NOT NULL)",
+        )
+            .bind(key_id)
+            .execute(&self.pool)
+            .await?;
+        debug!("Deleted claimed prekey {}", key_id);
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete_expired_prekeys(&self, before: OffsetDateTime) -> Result<u64> {
+        let result = sqlx::query(
+            "DELETE FROM quantum_keys k USING one_time_prekeys p
+             WHERE p.key_id = k.key_id AND p.claimed_at IS NULL AND k.expiration_timestamp < $1",
+        )
+            .bind(before)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    async fn insert_signing_key(&self, key: &SigningKey) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        if key.is_active {
+            sqlx::query("UPDATE signing_keys SET is_active = FALSE WHERE user_id = $1 AND is_active")
+                .bind(key.user_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        sqlx::query(
+            "INSERT INTO signing_keys (key_id, user_id, public_key, private_key, algorithm, created_at, is_active)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        )
+            .bind(key.key_id)
+            .bind(key.user_id)
+            .bind(&key.public_key)
+            .bind(&key.private_key)
+            .bind(&key.algorithm)
+            .bind(key.created_at)
+            .bind(key.is_active)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        debug!("Stored signing key {} for user {}", key.key_id, key.user_id);
+        Ok(())
+    }
+
+    async fn active_signing_key(&self, user_id: Uuid) -> Result<Option<SigningKey>> {
+        let key = sqlx::query_as::<_, SigningKey>(
+            "SELECT * FROM signing_keys WHERE user_id = $1 AND is_active ORDER BY created_at DESC LIMIT 1",
+        )
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(key)
+    }
+
+    async fn signing_key(&self, key_id: Uuid) -> Result<Option<SigningKey>> {
+        let key = sqlx::query_as::<_, SigningKey>("SELECT * FROM signing_keys WHERE key_id = $1")
+            .bind(key_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(key)
+    }
+}
+
+/// Wraps every private key found in `quantum_keys` that is still stored as raw key bytes
+///
+/// Returns the number of keys that were wrapped.
+pub async fn wrap_unprotected_keys(pool: &Pool<Postgres>, wrapper: &KeyWrapper) -> Result<u64> {
+    let keys = sqlx::query_as::<_, QuantumKey>(
+        "SELECT * FROM quantum_keys WHERE substring(private_key from 1 for 4) <> $1",
+    )
+        .bind(&WRAPPED_KEY_MAGIC[..])
+        .fetch_all(pool)
+        .await?;
+
+    let mut wrapped = 0;
+    for key in keys {
+        let blob = wrapper.wrap_private_key(key.key_id, key.user_id, key.private_key.expose_secret())?;
+        wrapped += sqlx::query("UPDATE quantum_keys SET private_key = $1 WHERE key_id = $2 AND private_key = $3")
+            .bind(&blob)
+            .bind(key.key_id)
+            .bind(&key.private_key)
+            .execute(pool)
+            .await?
+            .rows_affected();
+    }
+    Ok(wrapped)
+}
+
+/// KeyStore decorator that keeps private keys wrapped at rest
+///
+/// Private keys are wrapped with a KeyWrapper before reaching the inner store and
+/// unwrapped on the way out, so callers only ever see raw key bytes. Wrapping derives
+/// keys with Argon2id, so it runs on the blocking thread pool rather than the async workers.
+pub struct EncryptedKeyStore<S> {
+    inner: S,
+    wrapper: Arc<KeyWrapper>,
+}
+
+impl<S: KeyStore> EncryptedKeyStore<S> {
+    /// Creates an EncryptedKeyStore over the given store
+    pub fn new(inner: S, wrapper: KeyWrapper) -> Self {
+        Self { inner, wrapper: Arc::new(wrapper) }
+    }
+
+    async fn wrap(&self, key_id: Uuid, user_id: Uuid, private_key: &SecretBytes) -> Result<SecretBytes> {
+        let wrapper = self.wrapper.clone();
+        let private_key = private_key.clone();
+        tokio::task::spawn_blocking(move || wrapper.wrap_private_key(key_id, user_id, private_key.expose_secret()))
+            .await?
+            .map(SecretBytes::new)
+    }
+
+    async fn unwrap(&self, key_id: Uuid, user_id: Uuid, blob: SecretBytes) -> Result<SecretBytes> {
+        let wrapper = self.wrapper.clone();
+        tokio::task::spawn_blocking(move || wrapper.unwrap_private_key(key_id, user_id, blob.expose_secret())).await?
+    }
+
+    async fn unwrap_key(&self, mut key: QuantumKey) -> Result<QuantumKey> {
+        let blob = std::mem::replace(&mut key.private_key, SecretBytes::new(Vec::new()));
+        key.private_key = self.unwrap(key.key_id, key.user_id, blob).await?;
+        Ok(key)
+    }
+
+    async fn unwrap_signing_key(&self, mut key: SigningKey) -> Result<SigningKey> {
+        let blob = std::mem::replace(&mut key.private_key, SecretBytes::new(Vec::new()));
+        key.private_key = self.unwrap(key.key_id, key.user_id, blob).await?;
+        Ok(key)
+    }
+}
+
+#[async_trait]
+impl<S: KeyStore> KeyStore for EncryptedKeyStore<S> {
+    async fn insert(&self, key: &QuantumKey) -> Result<()> {
+        let mut wrapped = key.clone();
+        wrapped.private_key = self.wrap(key.key_id, key.user_id, &key.private_key).await?;
+        self.inner.insert(&wrapped).await
+    }
+
+    async fn active_key(&self, user_id: Uuid) -> Result<Option<QuantumKey>> {
+        match self.inner.active_key(user_id).await? {
+            Some(key) => self.unwrap_key(key).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    async fn key_history(&self, user_id: Uuid) -> Result<Vec<QuantumKey>> {
+        try_join_all(self.inner.key_history(user_id).await?.into_iter().map(|key| self.unwrap_key(key))).await
+    }
+
+    async fn long_term_keys(&self, user_id: Uuid) -> Result<Vec<QuantumKey>> {
+        try_join_all(self.inner.long_term_keys(user_id).await?.into_iter().map(|key| self.unwrap_key(key))).await
+    }
+
+    async fn deactivate(&self, key_id: Uuid) -> Result<bool> {
+        self.inner.deactivate(key_id).await
+    }
+
+    async fn expiring_keys(&self, before: OffsetDateTime) -> Result<Vec<QuantumKey>> {
+        try_join_all(self.inner.expiring_keys(before).await?.into_iter().map(|key| self.unwrap_key(key))).await
+    }
+
+    async fn insert_prekey(&self, key: &QuantumKey, prekey: &OneTimePrekey) -> Result<()> {
+        let mut wrapped = key.clone();
+        wrapped.private_key = self.wrap(key.key_id, key.user_id, &key.private_key).await?;
+        self.inner.insert_prekey(&wrapped, prekey).await
+    }
+
+    async fn delete_claimed_prekey(&self, key_id: Uuid) -> Result<bool> {
+        self.inner.delete_claimed_prekey(key_id).await
+    }
+
+    async fn delete_expired_prekeys(&self, before: OffsetDateTime) -> Result<u64> {
+        self.inner.delete_expired_prekeys(before).await
+    }
+
+    async fn insert_signing_key(&self, key: &SigningKey) -> Result<()> {
+        let mut wrapped = key.clone();
+        wrapped.private_key = self.wrap(key.key_id, key.user_id, &key.private_key).await?;
+        self.inner.insert_signing_key(&wrapped).await
+    }
+
+    async fn active_signing_key(&self, user_id: Uuid) -> Result<Option<SigningKey>> {
+        match self.inner.active_signing_key(user_id).await? {
+            Some(key) => self.unwrap_signing_key(key).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    async fn signing_key(&self, key_id: Uuid) -> Result<Option<SigningKey>> {
+        match self.inner.signing_key(key_id).await? {
+            Some(key) => self.unwrap_signing_key(key).await.map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// In-memory KeyStore for tests and running without a database
+#[derive(Default)]
+pub struct InMemoryKeyStore {
+    keys: Mutex<HashMap<Uuid, QuantumKey>>,
+    prekeys: Mutex<HashMap<Uuid, OneTimePrekey>>,
+    signing_keys: Mutex<HashMap<Uuid, SigningKey>>,
+}
+
+impl InMemoryKeyStore {
+    /// Creates an empty in-memory key store
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl KeyStore for InMemoryKeyStore {
+    async fn insert(&self, key: &QuantumKey) -> Result<()> {
+        let mut keys = self.keys.lock().await;
+        if keys.contains_key(&key.key_id) {
+            return Err(anyhow::anyhow!("Quantum key {} already exists", key.key_id));
+        }
+        if key.is_active {
+            keys.values_mut()
+                .filter(|existing| existing.user_id == key.user_id)
+                .for_each(|existing| existing.is_active = false);
+        }
+        keys.insert(key.key_id, key.clone());
+        Ok(())
+    }
+
+    async fn active_key(&self, user_id: Uuid) -> Result<Option<QuantumKey>> {
+        let keys = self.keys.lock().await;
+        Ok(keys.values()
+            .filter(|key| key.user_id == user_id && key.is_active)
+            .max_by_key(|key| key.key_generation_timestamp)
+            .cloned())
+    }
+
+    async fn key_history(&self, user_id: Uuid) -> Result<Vec<QuantumKey>> {
+        let keys = self.keys.lock().await;
+        let prekeys = self.prekeys.lock().await;
+        let mut history: Vec<QuantumKey> = keys.values()
+            .filter(|key| key.user_id == user_id)
+            .filter(|key| prekeys.get(&key.key_id).is_none_or(|prekey| prekey.claimed_at.is_some()))
+            .cloned()
+            .collect();
+        history.sort_by(|a, b| {
+            b.is_active.cmp(&a.is_active)
+                .then(b.key_generation_timestamp.cmp(&a.key_generation_timestamp))
+        });
+        Ok(history)
+    }
+
+    async fn long_term_keys(&self, user_id: Uuid) -> Result<Vec<QuantumKey>> {
+        let mut keys = self.key_history(user_id).await?;
+        let prekeys = self.prekeys.lock().await;
+        keys.retain(|key| !prekeys.contains_key(&key.key_id));
+        Ok(keys)
+    }
+
+    async fn deactivate(&self, key_id: Uuid) -> Result<bool> {
+        let mut keys = self.keys.lock().await;
+        Ok(keys.get_mut(&key_id)
+            .map(|key| key.is_active = false)
+            .is_some())
+    }
+
+    async fn expiring_keys(&self, before: OffsetDateTime) -> Result<Vec<QuantumKey>> {
+        let keys = self.keys.lock().await;
+        let mut expiring: Vec<QuantumKey> = keys.values()
+            .filter(|key| key.is_active && key.expiration_timestamp < before)
+            .cloned()
+            .collect();
+        expiring.sort_by_key(|key| key.expiration_timestamp);
+        Ok(expiring)
+    }
+
+    async fn insert_prekey(&self, key: &QuantumKey, prekey: &OneTimePrekey) -> Result<()> {
+        if key.is_active || key.key_id != prekey.key_id || key.user_id != prekey.user_id {
+            return Err(anyhow::anyhow!("Prekey {} does not match an inactive key of its owner", prekey.key_id));
+        }
+        self.insert(key).await?;
+        self.prekeys.lock().await.insert(prekey.key_id, prekey.clone());
+        Ok(())
+    }
+
+    async fn delete_claimed_prekey(&self, key_id: Uuid) -> Result<bool> {
+        let mut keys = self.keys.lock().await;
+        let mut prekeys = self.prekeys.lock().await;
+        if prekeys.get(&key_id).is_none_or(|prekey| prekey.claimed_at.is_none()) {
+            return Ok(false);
+        }
+        prekeys.remove(&key_id);
+        Ok(keys.remove(&key_id).is_some())
+    }
+
+    async fn delete_expired_prekeys(&self, before: OffsetDateTime) -> Result<u64> {
+        let mut keys = self.keys.lock().await;
+        let mut prekeys = self.prekeys.lock().await;
+        let expired: Vec<Uuid> = prekeys.values()
+            .filter(|prekey| prekey.claimed_at.is_none())
+            .filter(|prekey| keys.get(&prekey.key_id).is_some_and(|key| key.expiration_timestamp < before))
+            .map(|prekey| prekey.key_id)
+            .collect();
+        for key_id in &expired {
+            prekeys.remove(key_id);
+            keys.remove(key_id);
+        }
+        Ok(expired.len() as u64)
+    }
+
+    async fn insert_signing_key(&self, key: &SigningKey) -> Result<()> {
+        let mut keys = self.signing_keys.lock().await;
+        if keys.contains_key(&key.key_id) {
+            return Err(anyhow::anyhow!("Signing key {} already exists", key.key_id));
+        }
+        if key.is_active {
+            keys.values_mut()
+                .filter(|existing| existing.user_id == key.user_id)
+                .for_each(|existing| existing.is_active = false);
+        }
+        keys.insert(key.key_id, key.clone());
+        Ok(())
+    }
+
+    async fn active_signing_key(&self, user_id: Uuid) -> Result<Option<SigningKey>> {
+        let keys = self.signing_keys.lock().await;
+        Ok(keys.values()
+            .filter(|key| key.user_id == user_id && key.is_active)
+            .max_by_key(|key| key.created_at)
+            .cloned())
+    }
+
+    async fn signing_key(&self, key_id: Uuid) -> Result<Option<SigningKey>> {
+        Ok(self.signing_keys.lock().await.get(&key_id).cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EncryptionConfig;
+
+    fn test_key(user_id: Uuid, age_days: i64) -> QuantumKey {
+        let mut key = QuantumKey::new(user_id, vec![1, 2, 3], vec![4, 5, 6].into(), "kyber".to_string(), 30);
+        key.key_generation_timestamp -= time::Duration::days(age_days);
+        key
+    }
+
+    #[tokio::test]
+    async fn test_insert_replaces_active_key() -> Result<()> {
+        let store = InMemoryKeyStore::new();
+        let user_id = Uuid::new_v4();
+
+        let old_key = test_key(user_id, 10);
+        let new_key = test_key(user_id, 0);
+        store.insert(&old_key).await?;
+        store.insert(&new_key).await?;
+
+        let active = store.active_key(user_id).await?.expect("active key");
+        assert_eq!(active.key_id, new_key.key_id);
+
+        let history = store.key_history(user_id).await?;
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].key_id, new_key.key_id);
+        assert!(!history[1].is_active);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deactivate_and_isolation() -> Result<()> {
+        let store = InMemoryKeyStore::new();
+        let user_id = Uuid::new_v4();
+        let other_user_id = Uuid::new_v4();
+
+        let key = test_key(user_id, 0);
+        let other_key = test_key(other_user_id, 0);
+        store.insert(&key).await?;
+        store.insert(&other_key).await?;
+
+        assert!(store.deactivate(key.key_id).await?);
+        assert!(!store.deactivate(Uuid::new_v4()).await?);
+        assert!(store.active_key(user_id).await?.is_none());
+        assert!(store.active_key(other_user_id).await?.is_some());
+        assert!(store.insert(&key).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_key_pair_roundtrip() -> Result<()> {
+        let config = EncryptionConfig {
+            key_rotation_days: 30,
+            algorithm: "kyber".to_string(),
+            key_size: 1024,
+            key_encryption_secret: "test-secret".to_string(),
+        };
+        let store = InMemoryKeyStore::new();
+        let user_id = Uuid::new_v4();
+        let key_exchange = QuantumKeyExchange::new(&config);
+        let key_pair = key_exchange.generate_key_pair()?;
+
+        store.insert(&QuantumKeyExchange::to_db_model(user_id, &key_pair)).await?;
+
+        let stored = store.active_key_pair(user_id).await?.expect("active key pair");
+        assert_eq!(stored.id, key_pair.id);
+        assert_eq!(stored.private_key, key_pair.private_key);
+        assert_eq!(store.key_pair_history(user_id).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_store_wraps_at_rest() -> Result<()> {
+        use crate::quantum_encryption::key_wrapping::KdfParams;
+
+        let params = KdfParams { memory_kib: 64, iterations: 1, parallelism: 1 };
+        let store = EncryptedKeyStore::new(InMemoryKeyStore::new(), KeyWrapper::with_params(b"secret", params));
+        let user_id = Uuid::new_v4();
+        let key = test_key(user_id, 0);
+        store.insert(&key).await?;
+
+        let at_rest = store.inner.active_key(user_id).await?.expect("stored key");
+        assert!(KeyWrapper::is_wrapped(at_rest.private_key.expose_secret()));
+        assert_ne!(at_rest.private_key, key.private_key);
+
+        let active = store.active_key(user_id).await?.expect("active key");
+        assert_eq!(active.private_key, key.private_key);
+        assert_eq!(store.key_history(user_id).await?[0].private_key, key.private_key);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_signing_keys() -> Result<()> {
+        use crate::quantum_encryption::key_wrapping::KdfParams;
+        use crate::quantum_encryption::signing::SignatureService;
+
+        let params = KdfParams { memory_kib: 64, iterations: 1, parallelism: 1 };
+        let store = EncryptedKeyStore::new(InMemoryKeyStore::new(), KeyWrapper::with_params(b"secret", params));
+        let user_id = Uuid::new_v4();
+        let service = SignatureService::new();
+
+        let old_key = service.generate_key_pair();
+        let new_key = service.generate_key_pair();
+        store.insert_signing_key(&SignatureService::to_db_model(user_id, &old_key)).await?;
+        store.insert_signing_key(&SignatureService::to_db_model(user_id, &new_key)).await?;
+
+        let active = store.active_signing_key(user_id).await?.expect("active signing key");
+        assert_eq!(active.key_id, new_key.id);
+        assert_eq!(active.private_key, new_key.private_key);
+
+        let old = store.signing_key(old_key.id).await?.expect("old signing key");
+        assert!(!old.is_active);
+        assert_eq!(old.private_key, old_key.private_key);
+        assert!(KeyWrapper::is_wrapped(store.inner.signing_key(old_key.id).await?.unwrap().private_key.expose_secret()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_prekey_lifecycle() -> Result<()> {
+        let store = InMemoryKeyStore::new();
+        let user_id = Uuid::new_v4();
+        let prekey_for = |key: &QuantumKey| OneTimePrekey {
+            key_id: key.key_id,
+            user_id,
+            public_key: key.public_key.clone(),
+            encryption_method: key.encryption_method.clone(),
+            signature: vec![7],
+            signing_key_id: Uuid::new_v4(),
+            created_at: key.key_generation_timestamp,
+            claimed_at: None,
+            claimed_by: None,
+        };
+
+        let mut unclaimed = test_key(user_id, 0);
+        unclaimed.is_active = false;
+        assert!(store.insert_prekey(&test_key(user_id, 0), &prekey_for(&unclaimed)).await.is_err());
+        store.insert_prekey(&unclaimed, &prekey_for(&unclaimed)).await?;
+        let mut claimed = test_key(user_id, 0);
+        claimed.is_active = false;
+        store.insert_prekey(&claimed, &OneTimePrekey { claimed_at: Some(OffsetDateTime::now_utc()), ..prekey_for(&claimed) }).await?;
+
+        // Only a claimed prekey can have been encrypted to, so only it is in the history
+        let history: Vec<Uuid> = store.key_history(user_id).await?.iter().map(|key| key.key_id).collect();
+        assert_eq!(history, vec![claimed.key_id]);
+        assert!(store.long_term_keys(user_id).await?.is_empty());
+
+        // Expiry only removes unclaimed prekeys; a claimed one waits to be deleted explicitly
+        assert_eq!(store.delete_expired_prekeys(OffsetDateTime::now_utc()).await?, 0);
+        assert_eq!(store.delete_expired_prekeys(claimed.expiration_timestamp + time::Duration::SECOND).await?, 1);
+        assert_eq!(store.key_history(user_id).await?.len(), 1);
+        assert!(!store.delete_claimed_prekey(unclaimed.key_id).await?);
+        assert!(store.delete_claimed_prekey(claimed.key_id).await?);
+        assert!(store.key_history(user_id).await?.is_empty());
+        assert!(store.keys.lock().await.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+// src/database/migrations.rs
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use sqlx::{Pool, Postgres};
+use tracing::{debug, warn};
+
+use crate::database::key_store::KeyStore;
+use crate::database::models::Email;
+use crate::quantum_encryption::encryption::{EncryptionService, SealedHeaders};
+use crate::quantum_encryption::signing::EmailHeader;
+
+/// Seals the plaintext subject of every stored single-recipient email into encrypted headers
+///
+/// New mail must carry encrypted headers, so only emails stored before that have a
+/// plaintext subject.
+///
+/// Each subject is encapsulated to the recipient's active key, so the body's shared secret
+/// is never needed, and the column is replaced with the placeholder. Emails whose recipient
+/// has no active key are left as they are and picked up on a later run. Returns the number
+/// of emails sealed.
+pub async fn seal_plaintext_subjects(
+    pool: &Pool<Postgres>,
+    key_store: &dyn KeyStore,
+    encryption_service: &EncryptionService,
+) -> Result<u64> {
+    // Multi-recipient mail has no single key to seal to; its headers are sealed when sent
+    let emails = sqlx::query_as::<_, Email>(
+        "SELECT * FROM emails e WHERE e.encrypted_headers IS NULL
+             AND NOT EXISTS (SELECT 1 FROM email_recipients r WHERE r.email_id = e.email_id)",
+    )
+        .fetch_all(pool)
+        .await?;
+
+    let mut sealed = 0;
+    for email in emails {
+        let Some(key_pair) = key_store.active_key_pair(email.recipient_id).await? else {
+            debug!("Recipient {} of email {} has no active key yet", email.recipient_id, email.email_id);
+            continue;
+        };
+        let header = EmailHeader {
+            email_id: email.email_id,
+            sender_id: email.sender_id,
+            recipient_id: email.recipient_id,
+            subject: email.subject.clone(),
+        };
+        let encrypted_headers = match encryption_service.seal_headers_for(
+            &SealedHeaders::new(email.subject.as_str(), BTreeMap::new()),
+            &header,
+            &key_pair.algorithm,
+            &key_pair.public_key,
+        ) {
+            Ok(encrypted_headers) => encrypted_headers,
+            Err(e) => {
+                warn!("Could not seal the subject of email {}: {}", email.email_id, e);
+                continue;
+            }
+        };
+
+        sealed += sqlx::query(
+            "UPDATE emails SET encrypted_headers = $1, subject = $2
+             WHERE email_id = $3 AND encrypted_headers IS NULL AND subject = $4",
+        )
+            .bind(&encrypted_headers)
+            .bind(Email::ENCRYPTED_SUBJECT)
+            .bind(email.email_id)
+            .bind(&email.subject)
+            .execute(pool)
+            .await?
+            .rows_affected();
+    }
+    Ok(sealed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_support::{app, insert_user, login, send_as, test_state};
+    use axum::http::{Method, StatusCode};
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use base64::Engine;
+    use serde_json::json;
+    use time::OffsetDateTime;
+    use uuid::Uuid;
+
+    /// Stores mail the old way, with the subject in the clear and no encrypted headers
+    async fn insert_legacy_email(
+        pool: &Pool<Postgres>,
+        encrypted: &serde_json::Value,
+        sender_id: Uuid,
+        recipient_id: Uuid,
+        subject: &str,
+    ) -> Uuid {
+        let decode = |value: &serde_json::Value| BASE64.decode(value.as_str().unwrap()).unwrap();
+        let email_id = Uuid::parse_str(encrypted["email_id"].as_str().unwrap()).unwrap();
+        sqlx::query(
+            "INSERT INTO emails (email_id, sender_id, recipient_id, subject, encrypted_content, encrypted_shared_secret,
+                                 timestamp, encryption_method, is_read, is_starred, is_archived, signature, signing_key_id)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, FALSE, FALSE, FALSE, $9, $10)",
+        )
+            .bind(email_id)
+            .bind(sender_id)
+            .bind(recipient_id)
+            .bind(subject)
+            .bind(decode(&encrypted["encrypted_content"]))
+            .bind(decode(&encrypted["encrypted_shared_secret"]))
+            .bind(OffsetDateTime::now_utc())
+            .bind(encrypted["encryption_method"].as_str().unwrap())
+            .bind(decode(&encrypted["signature"]))
+            .bind(Uuid::parse_str(encrypted["signing_key_id"].as_str().unwrap()).unwrap())
+            .execute(pool)
+            .await
+            .expect("Failed to insert legacy email");
+        email_id
+    }
+
+    async fn stored_subject(pool: &Pool<Postgres>, email_id: Uuid) -> (String, Option<Vec<u8>>) {
+        sqlx::query_as("SELECT subject, encrypted_headers FROM emails WHERE email_id = $1")
+            .bind(email_id)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_seal_plaintext_subjects() -> Result<()> {
+        let state = test_state().await;
+        let sender = insert_user(&state).await;
+        let recipient = insert_user(&state).await;
+        let keyless = insert_user(&state).await;
+        let sender_token = login(&state, &sender).await;
+        let recipient_token = login(&state, &recipient).await;
+        for token in [&sender_token, &recipient_token] {
+            send_as(app(state.clone()), Some(token), Method::POST, "/encryption/generate-key-pair", None).await;
+        }
+
+        let (_, encrypted) = send_as(app(state.clone()), Some(&sender_token), Method::POST, "/encryption/encrypt", Some(json!({
+            "recipient_id": recipient.user_id,
+            "subject": "Quarterly numbers",
+            "content": "Stored in the clear",
+        }))).await;
+        let email_id = insert_legacy_email(&state.db_pool, &encrypted, sender.user_id, recipient.user_id, "Quarterly numbers").await;
+        // A recipient without an active key is left for a later run
+        let (_, unsealable) = send_as(app(state.clone()), Some(&sender_token), Method::POST, "/encryption/encrypt", Some(json!({
+            "recipient_id": recipient.user_id,
+            "subject": "Not yet",
+            "content": "Waiting for a key",
+        }))).await;
+        let waiting_id = insert_legacy_email(&state.db_pool, &unsealable, sender.user_id, keyless.user_id, "Not yet").await;
+
+        seal_plaintext_subjects(&state.db_pool, state.key_store.as_ref(), &state.encryption_service).await?;
+
+        let (subject, encrypted_headers) = stored_subject(&state.db_pool, email_id).await;
+        assert_eq!(subject, Email::ENCRYPTED_SUBJECT);
+        assert!(encrypted_headers.is_some());
+        assert_eq!(stored_subject(&state.db_pool, waiting_id).await, ("Not yet".to_string(), None));
+
+        // The real subject comes back from the sealed headers and still verifies
+        let (status, body) = send_as(app(state), Some(&recipient_token), Method::POST, "/encryption/decrypt", Some(json!({
+            "emailId": email_id,
+            "encryptedContent": encrypted["encrypted_content"],
+            "encryptedSharedSecret": encrypted["encrypted_shared_secret"],
+        }))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["subject"], "Quarterly numbers");
+        assert_eq!(body["signature_status"], "verified");
+        Ok(())
+    }
+}
@@ -1,2 +1,4 @@
+pub mod key_store;
+pub mod migrations;
 pub mod models;
 pub mod schema;
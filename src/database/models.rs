@@ -3,6 +3,8 @@ use sqlx::FromRow;
 use time::OffsetDateTime;
 use uuid::Uuid;
 
+use crate::quantum_encryption::secret::SecretBytes;
+
 /// User model representing a user in the system
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct User {
@@ -29,6 +31,46 @@ pub struct Email {
     pub is_read: bool,
     pub is_starred: bool,
     pub is_archived: bool,
+    pub signature: Option<Vec<u8>>,
+    pub signing_key_id: Option<Uuid>,
+    /// Sealed subject and headers; when set, `subject` only holds a placeholder
+    pub encrypted_headers: Option<Vec<u8>>,
+}
+
+/// How a user received a multi-recipient email, stored as `EmailRecipient.recipient_type`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecipientType {
+    To,
+    Cc,
+    Bcc,
+    /// The sender's own copy, so sent mail stays readable
+    Sender,
+}
+
+impl RecipientType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RecipientType::To => "to",
+            RecipientType::Cc => "cc",
+            RecipientType::Bcc => "bcc",
+            RecipientType::Sender => "sender",
+        }
+    }
+}
+
+/// EmailRecipient model holding one recipient's wrapped content key for a multi-recipient email
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct EmailRecipient {
+    pub email_id: Uuid,
+    pub recipient_id: Uuid,
+    /// `to`, `cc`, `bcc`, or `sender` for the sender's own copy
+    pub recipient_type: String,
+    pub key_id: Uuid,
+    pub encryption_method: String,
+    pub kem_ciphertext: Vec<u8>,
+    pub wrapped_key: Vec<u8>,
+    pub is_read: bool,
 }
 
 /// QuantumKey model representing a quantum key in the system
@@ -37,21 +79,96 @@ pub struct QuantumKey {
     pub key_id: Uuid,
     pub user_id: Uuid,
     pub public_key: Vec<u8>,
-    pub private_key: Vec<u8>,
+    #[serde(skip_serializing)]
+    pub private_key: SecretBytes,
     pub encryption_method: String,
     pub key_generation_timestamp: OffsetDateTime,
     pub expiration_timestamp: OffsetDateTime,
     pub is_active: bool,
 }
 
+/// SigningKey model representing a user's Dilithium signing key
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct SigningKey {
+    pub key_id: Uuid,
+    pub user_id: Uuid,
+    pub public_key: Vec<u8>,
+    #[serde(skip_serializing)]
+    pub private_key: SecretBytes,
+    pub algorithm: String,
+    pub created_at: OffsetDateTime,
+    pub is_active: bool,
+}
+
+/// OneTimePrekey model holding the public half of a one-time prekey
+///
+/// The private half is stored as an inactive QuantumKey with the same `key_id`, so mail
+/// encrypted to the prekey is decrypted like mail to any older key. A claimed prekey is
+/// never handed out again, and its private half is deleted once the message encrypted
+/// to it was read or the prekey expired.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct OneTimePrekey {
+    pub key_id: Uuid,
+    pub user_id: Uuid,
+    pub public_key: Vec<u8>,
+    pub encryption_method: String,
+    pub signature: Vec<u8>,
+    pub signing_key_id: Uuid,
+    pub created_at: OffsetDateTime,
+    pub claimed_at: Option<OffsetDateTime>,
+    /// The user the prekey was handed out to
+    pub claimed_by: Option<Uuid>,
+}
+
+/// Why a quantum key was revoked, stored as `KeyRevocation.reason`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RevocationReason {
+    /// The private key may be known to someone else
+    Compromised,
+    /// The key was replaced and should no longer be used
+    Superseded,
+    #[default]
+    Unspecified,
+}
+
+impl RevocationReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RevocationReason::Compromised => "compromised",
+            RevocationReason::Superseded => "superseded",
+            RevocationReason::Unspecified => "unspecified",
+        }
+    }
+}
+
+/// KeyRevocation model recording that a quantum key must no longer be encrypted to
+///
+/// The owner signs the record with their signing key, so it can be checked independently
+/// of the server.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct KeyRevocation {
+    pub key_id: Uuid,
+    pub user_id: Uuid,
+    pub reason: String,
+    pub revoked_at: OffsetDateTime,
+    pub signature: Vec<u8>,
+    pub signing_key_id: Uuid,
+}
+
 /// EmailAttachment model representing an attachment to an email
+///
+/// The content is encrypted as a chunked stream: `encrypted_content` holds the stream
+/// header and the sealed chunks are stored in `email_attachment_chunks`.
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct EmailAttachment {
     pub attachment_id: Uuid,
     pub email_id: Uuid,
     pub filename: String,
     pub content_type: String,
+    #[serde(skip_serializing)]
     pub encrypted_content: Vec<u8>,
+    /// Plaintext size
     pub size_bytes: i64,
 }
 
@@ -69,6 +186,9 @@ pub struct UserSession {
 }
 
 /// Contact model representing a user's contact
+///
+/// `public_key` and `key_algorithm` pin the contact's key as first seen (trust on first use);
+/// `key_verified` records that the owner compared its fingerprint out of band.
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct Contact {
     pub contact_id: Uuid,
@@ -77,6 +197,9 @@ pub struct Contact {
     pub name: String,
     pub email: String,
     pub public_key: Option<Vec<u8>>,
+    pub key_algorithm: Option<String>,
+    pub key_pinned_at: Option<OffsetDateTime>,
+    pub key_verified: bool,
     pub created_at: OffsetDateTime,
     pub updated_at: OffsetDateTime,
 }
@@ -151,8 +274,28 @@ impl Email {
             is_read: false,
             is_starred: false,
             is_archived: false,
+            signature: None,
+            signing_key_id: None,
+            encrypted_headers: None,
         }
     }
+
+    /// Placeholder kept in the subject column of emails whose headers are encrypted
+    pub const ENCRYPTED_SUBJECT: &'static str = "(encrypted)";
+
+    /// Attaches the sender's signature and the ID of the key that made it
+    pub fn with_signature(mut self, signing_key_id: Uuid, signature: Vec<u8>) -> Self {
+        self.signing_key_id = Some(signing_key_id);
+        self.signature = Some(signature);
+        self
+    }
+
+    /// Stores the sealed headers and replaces the subject with the placeholder
+    pub fn with_encrypted_headers(mut self, encrypted_headers: Vec<u8>) -> Self {
+        self.subject = Self::ENCRYPTED_SUBJECT.to_string();
+        self.encrypted_headers = Some(encrypted_headers);
+        self
+    }
 }
 
 /// Implementation for QuantumKey model
@@ -160,7 +303,7 @@ impl QuantumKey {
     pub fn new(
         user_id: Uuid,
         public_key: Vec<u8>,
-        private_key: Vec<u8>,
+        private_key: SecretBytes,
         encryption_method: String,
         expiration_days: i64,
     ) -> Self {
@@ -178,3 +321,48 @@ impl QuantumKey {
         }
     }
 }
+
+/// Implementation for Contact model
+impl Contact {
+    pub fn new(user_id: Uuid, name: String, email: String, contact_user_id: Option<Uuid>) -> Self {
+        let now = OffsetDateTime::now_utc();
+
+        Self {
+            contact_id: Uuid::new_v4(),
+            user_id,
+            contact_user_id,
+            name,
+            email,
+            public_key: None,
+            key_algorithm: None,
+            key_pinned_at: None,
+            key_verified: false,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+}
+
+/// Implementation for UserSession model
+impl UserSession {
+    pub fn new(
+        user_id: Uuid,
+        token: String,
+        duration_hours: i64,
+        ip_address: String,
+        user_agent: String,
+    ) -> Self {
+        let now = OffsetDateTime::now_utc();
+
+        Self {
+            session_id: Uuid::new_v4(),
+            user_id,
+            token,
+            created_at: now,
+            expires_at: now + time::Duration::hours(duration_hours),
+            last_active_at: now,
+            ip_address,
+            user_agent,
+        }
+    }
+}
@@ -17,6 +17,11 @@ impl DatabaseSchema {
             
         Ok(Self { pool })
     }
+
+    /// Create a DatabaseSchema on top of an existing connection pool
+    pub fn from_pool(pool: Pool<Postgres>) -> Self {
+        Self { pool }
+    }
     
     /// Initialize the database schema
     pub async fn initialize(&self) -> Result<()> {
@@ -36,10 +41,15 @@ impl DatabaseSchema {
                 email VARCHAR(255) NOT NULL UNIQUE,
                 quantum_public_key BYTEA NOT NULL,
                 authentication_method VARCHAR(50) NOT NULL,
+                password_hash VARCHAR(255),
                 created_at TIMESTAMPTZ NOT NULL,
                 updated_at TIMESTAMPTZ NOT NULL
             )
         "#).execute(&self.pool).await?;
+        sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS password_hash VARCHAR(255)")
+            .execute(&self.pool).await?;
+        sqlx::query("ALTER TABLE users ADD COLUMN IF NOT EXISTS last_seen_at TIMESTAMPTZ")
+            .execute(&self.pool).await?;
         
         // Emails table
         sqlx::query(r#"
@@ -54,7 +64,39 @@ impl DatabaseSchema {
                 encryption_method VARCHAR(50) NOT NULL,
                 is_read BOOLEAN NOT NULL DEFAULT FALSE,
                 is_starred BOOLEAN NOT NULL DEFAULT FALSE,
-                is_archived BOOLEAN NOT NULL DEFAULT FALSE
+                is_archived BOOLEAN NOT NULL DEFAULT FALSE,
+                signature BYTEA,
+                signing_key_id UUID,
+                encrypted_headers BYTEA,
+                deleted_by_sender BOOLEAN NOT NULL DEFAULT FALSE,
+                deleted_by_recipient BOOLEAN NOT NULL DEFAULT FALSE
+            )
+        "#).execute(&self.pool).await?;
+        sqlx::query("ALTER TABLE emails ADD COLUMN IF NOT EXISTS signature BYTEA")
+            .execute(&self.pool).await?;
+        sqlx::query("ALTER TABLE emails ADD COLUMN IF NOT EXISTS signing_key_id UUID")
+            .execute(&self.pool).await?;
+        sqlx::query("ALTER TABLE emails ADD COLUMN IF NOT EXISTS encrypted_headers BYTEA")
+            .execute(&self.pool).await?;
+        // Each side's copy of single-recipient mail can be deleted without the other's
+        sqlx::query("ALTER TABLE emails ADD COLUMN IF NOT EXISTS deleted_by_sender BOOLEAN NOT NULL DEFAULT FALSE")
+            .execute(&self.pool).await?;
+        sqlx::query("ALTER TABLE emails ADD COLUMN IF NOT EXISTS deleted_by_recipient BOOLEAN NOT NULL DEFAULT FALSE")
+            .execute(&self.pool).await?;
+        
+        // Email recipients table: one row per To/Cc/Bcc recipient and the sender's own copy,
+        // each holding the content key wrapped to that user's key
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS email_recipients (
+                email_id UUID NOT NULL REFERENCES emails(email_id) ON DELETE CASCADE,
+                recipient_id UUID NOT NULL REFERENCES users(user_id),
+                recipient_type VARCHAR(10) NOT NULL,
+                key_id UUID NOT NULL,
+                encryption_method VARCHAR(50) NOT NULL,
+                kem_ciphertext BYTEA NOT NULL,
+                wrapped_key BYTEA NOT NULL,
+                is_read BOOLEAN NOT NULL DEFAULT FALSE,
+                PRIMARY KEY (email_id, recipient_id)
             )
         "#).execute(&self.pool).await?;
         
@@ -72,6 +114,51 @@ impl DatabaseSchema {
             )
         "#).execute(&self.pool).await?;
         
+        // Signing keys table
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS signing_keys (
+                key_id UUID PRIMARY KEY,
+                user_id UUID NOT NULL REFERENCES users(user_id),
+                public_key BYTEA NOT NULL,
+                private_key BYTEA NOT NULL,
+                algorithm VARCHAR(50) NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL,
+                is_active BOOLEAN NOT NULL DEFAULT TRUE
+            )
+        "#).execute(&self.pool).await?;
+
+        // One-time prekeys, unclaimed or waiting for their message to be read; the private
+        // halves are inactive quantum keys that are deleted along with the prekey
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS one_time_prekeys (
+                key_id UUID PRIMARY KEY REFERENCES quantum_keys(key_id) ON DELETE CASCADE,
+                user_id UUID NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+                public_key BYTEA NOT NULL,
+                encryption_method VARCHAR(50) NOT NULL,
+                signature BYTEA NOT NULL,
+                signing_key_id UUID NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL,
+                claimed_at TIMESTAMPTZ,
+                claimed_by UUID
+            )
+        "#).execute(&self.pool).await?;
+        sqlx::query("ALTER TABLE one_time_prekeys ADD COLUMN IF NOT EXISTS claimed_at TIMESTAMPTZ")
+            .execute(&self.pool).await?;
+        sqlx::query("ALTER TABLE one_time_prekeys ADD COLUMN IF NOT EXISTS claimed_by UUID")
+            .execute(&self.pool).await?;
+
+        // Key revocations table, one signed record per revoked quantum key
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS key_revocations (
+                key_id UUID PRIMARY KEY REFERENCES quantum_keys(key_id) ON DELETE CASCADE,
+                user_id UUID NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+                reason VARCHAR(50) NOT NULL,
+                revoked_at TIMESTAMPTZ NOT NULL,
+                signature BYTEA NOT NULL,
+                signing_key_id UUID NOT NULL
+            )
+        "#).execute(&self.pool).await?;
+
         // Email attachments table
         sqlx::query(r#"
             CREATE TABLE IF NOT EXISTS email_attachments (
@@ -83,6 +170,16 @@ impl DatabaseSchema {
                 size_bytes BIGINT NOT NULL
             )
         "#).execute(&self.pool).await?;
+
+        // Encrypted attachment chunks, in stream order
+        sqlx::query(r#"
+            CREATE TABLE IF NOT EXISTS email_attachment_chunks (
+                attachment_id UUID NOT NULL REFERENCES email_attachments(attachment_id) ON DELETE CASCADE,
+                chunk_index INTEGER NOT NULL,
+                data BYTEA NOT NULL,
+                PRIMARY KEY (attachment_id, chunk_index)
+            )
+        "#).execute(&self.pool).await?;
         
         // User sessions table
         sqlx::query(r#"
@@ -107,11 +204,20 @@ impl DatabaseSchema {
                 name VARCHAR(255) NOT NULL,
                 email VARCHAR(255) NOT NULL,
                 public_key BYTEA,
+                key_algorithm VARCHAR(50),
+                key_pinned_at TIMESTAMPTZ,
+                key_verified BOOLEAN NOT NULL DEFAULT FALSE,
                 created_at TIMESTAMPTZ NOT NULL,
                 updated_at TIMESTAMPTZ NOT NULL,
                 UNIQUE(user_id, email)
             )
         "#).execute(&self.pool).await?;
+        sqlx::query("ALTER TABLE contacts ADD COLUMN IF NOT EXISTS key_algorithm VARCHAR(50)")
+            .execute(&self.pool).await?;
+        sqlx::query("ALTER TABLE contacts ADD COLUMN IF NOT EXISTS key_pinned_at TIMESTAMPTZ")
+            .execute(&self.pool).await?;
+        sqlx::query("ALTER TABLE contacts ADD COLUMN IF NOT EXISTS key_verified BOOLEAN NOT NULL DEFAULT FALSE")
+            .execute(&self.pool).await?;
         
         // Email folders table
         sqlx::query(r#"
@@ -163,12 +269,28 @@ impl DatabaseSchema {
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_emails_timestamp ON emails(timestamp)")
             .execute(&self.pool).await?;
             
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_email_recipients_recipient_id ON email_recipients(recipient_id)")
+            .execute(&self.pool).await?;
+            
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_one_time_prekeys_user_id ON one_time_prekeys(user_id, created_at)")
+            .execute(&self.pool).await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_one_time_prekeys_claimed_by ON one_time_prekeys(claimed_by, user_id, claimed_at)")
+            .execute(&self.pool).await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_key_revocations_user_id ON key_revocations(user_id)")
+            .execute(&self.pool).await?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_email_attachments_email_id ON email_attachments(email_id)")
+            .execute(&self.pool).await?;
+            
         // Quantum keys indexes
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_quantum_keys_user_id ON quantum_keys(user_id)")
             .execute(&self.pool).await?;
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_quantum_keys_expiration ON quantum_keys(expiration_timestamp)")
             .execute(&self.pool).await?;
             
+        // Signing keys indexes
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_signing_keys_user_id ON signing_keys(user_id)")
+            .execute(&self.pool).await?;
+
         // User sessions indexes
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_user_sessions_user_id ON user_sessions(user_id)")
             .execute(&self.pool).await?;
@@ -6,12 +6,18 @@ use anyhow::Result;
 use axum::{routing::get, Extension, Router};
 use dotenv::dotenv;
 use tokio::signal;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
+use quantum_email_client::api;
 use quantum_email_client::config::AppConfig;
+use quantum_email_client::database::{key_store, migrations};
+use quantum_email_client::database::schema::DatabaseSchema;
+use quantum_email_client::quantum_encryption::key_exchange::QuantumKeyExchange;
+use quantum_email_client::quantum_encryption::key_rotation::KeyRotationScheduler;
+use quantum_email_client::quantum_encryption::key_wrapping::KeyWrapper;
 use quantum_email_client::utils::logging;
-use quantum_email_client::websocket::server::WebSocketServer;
+use quantum_email_client::websocket;
 use quantum_email_client::AppState;
 
 #[tokio::main]
@@ -19,20 +25,30 @@ async fn main() -> Result<()> {
     logging::init_logging();
     dotenv().ok();
 
-    let config = AppConfig::from_env().unwrap_or_default();
+    // No fallback to the default configuration: it would wrap keys with the development secret
+    let config = AppConfig::from_env()?;
     let app_state = Arc::new(AppState::new(config.clone()).await?);
     let app = QuantumEmailApp::new(app_state.clone());
 
     app.initialize().await?;
     app.start().await?;
 
-    let websocket_server = WebSocketServer::new(&config.server);
-    // Pass a reference instead of cloning
-    tokio::spawn(async move {
-        if let Err(e) = websocket_server.start().await {
-            error!("WebSocket server error: {}", e);
-        }
-    });
+    let websocket_server = app_state.websocket.clone();
+    // WebSockets are served at /ws; the standalone listener only runs when WEBSOCKET_PORT is set
+    if config.server.websocket_port.is_some() {
+        let server = websocket_server.clone();
+        tokio::spawn(async move {
+            if let Err(e) = server.start().await {
+                error!("WebSocket server error: {}", e);
+            }
+        });
+    }
+
+    if config.encryption.key_rotation_days > 0 {
+        KeyRotationScheduler::new(app_state.key_store.clone(), &config.encryption, app_state.hub.clone()).spawn();
+    } else {
+        warn!("KEY_ROTATION_DAYS is 0, automatic key rotation is disabled");
+    }
 
     let router = create_router(app_state);
     let addr = format!("{}:{}", config.server.host, config.server.port);
@@ -40,8 +56,12 @@ async fn main() -> Result<()> {
 
     info!("Starting HTTP server on {}", socket_addr);
     axum::Server::bind(&socket_addr)
-        .serve(router.into_make_service())
-        .with_graceful_shutdown(shutdown_signal())
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            // Upgraded connections are not tracked by the HTTP server, so close them here
+            websocket_server.shutdown();
+        })
         .await?;
 
     info!("Server shutdown complete");
@@ -53,12 +73,14 @@ fn create_router(app_state: Arc<AppState>) -> Router {
         .route("/", get(health_check))
         .route("/api/health", get(health_check))
         .nest("/api", api_routes())
+        .merge(websocket::handler::routes())
         .layer(Extension(app_state))
 }
 
 fn api_routes() -> Router {
     Router::new()
         .route("/users", get(get_users))
+        .merge(api::routes())
 }
 
 async fn health_check() -> &'static str {
@@ -86,16 +108,39 @@ impl QuantumEmailApp {
     }
 
     async fn initialize_database(&self) -> Result<()> {
-        // Commenting out migrations until directory is created
-        // sqlx::migrate!()
-        //     .run(&self.state.db_pool)
-        //     .await
-        //     .map_err(|e| anyhow::anyhow!("Database migration failed: {}", e))?;
-        info!("Database initialization skipped (migrations not set up)");
+        DatabaseSchema::from_pool(self.state.db_pool.clone())
+            .initialize()
+            .await
+            .map_err(|e| anyhow::anyhow!("Database schema initialization failed: {}", e))?;
+        info!("Database schema initialized");
         Ok(())
     }
 
     async fn initialize_encryption(&self) -> Result<()> {
+        // Fail at startup rather than on the first key generation if the suite is misconfigured
+        let kem = QuantumKeyExchange::new(&self.state.config.encryption).kem()?;
+        info!("New quantum keys will use {}", kem.suite());
+
+        // Private keys stored before key wrapping was introduced are wrapped in place
+        let wrapper = KeyWrapper::new(self.state.config.encryption.key_encryption_secret.as_bytes());
+        let wrapped = key_store::wrap_unprotected_keys(&self.state.db_pool, &wrapper)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to wrap stored private keys: {}", e))?;
+        if wrapped > 0 {
+            info!("Wrapped {} private keys that were stored unencrypted", wrapped);
+        }
+
+        // Subjects stored before headers were encrypted are sealed to their recipient's key
+        let sealed = migrations::seal_plaintext_subjects(
+            &self.state.db_pool,
+            self.state.key_store.as_ref(),
+            &self.state.encryption_service,
+        )
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to seal stored subjects: {}", e))?;
+        if sealed > 0 {
+            info!("Sealed {} subjects that were stored in plaintext", sealed);
+        }
         info!("Encryption system initialized");
         Ok(())
     }
@@ -110,26 +155,13 @@ impl QuantumEmailApp {
     }
 
     pub async fn generate_key_pair(&self, user_id: Uuid) -> Result<()> {
-        // Skip database interaction unless explicitly needed and DB is running
-        info!("Generated new quantum key pair for user {} (not stored due to DB unavailability)", user_id);
-        // Uncomment and ensure DB is running when ready
-        /*
-        sqlx::query!(
-            "INSERT INTO quantum_keys (key_id, user_id, public_key, private_key, encryption_method, key_generation_timestamp, expiration_timestamp)
-             VALUES ($1, $2, $3, $4, $5, $6, $7)",
-            key_pair.id,
-            user_id,
-            key_pair.public_key,
-            key_pair.private_key,
-            key_pair.algorithm,
-            key_pair.created_at,
-            key_pair.expires_at
-        )
-            .execute(&self.state.db_pool)
+        let key_exchange = QuantumKeyExchange::new(&self.state.config.encryption);
+        let key_pair = key_exchange.generate_key_pair()?;
+        self.state.key_store
+            .insert(&QuantumKeyExchange::to_db_model(user_id, &key_pair))
             .await
             .map_err(|e| anyhow::anyhow!("Failed to store key pair: {}", e))?;
-        info!("Generated and stored new quantum key pair for user {}", user_id);
-        */
+        info!("Generated and stored new quantum key pair {} for user {}", key_pair.id, user_id);
         Ok(())
     }
 }
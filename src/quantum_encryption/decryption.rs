@@ -1,13 +1,25 @@
 // src/quantum_encryption/decryption.rs
 use anyhow::Result;
-use sha3::{Digest, Sha3_256};
 use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce}; // Explicitly import required types
-use aes_gcm::aead::Aead; // Import Aead trait
+use aes_gcm::aead::{Aead, Payload}; // Import Aead trait
 use crate::config::EncryptionConfig;
+use crate::quantum_encryption::envelope::{AeadAlgorithm, Envelope, KdfAlgorithm};
+use crate::quantum_encryption::encryption::{SealedHeaders, WrappedKey};
+use crate::quantum_encryption::kdf::{derive_key, legacy_key, KeyContext, KeyPurpose, KEY_LEN, SHARED_CONTENT_KEY};
+use crate::quantum_encryption::kem::kem_for_suite;
 use crate::quantum_encryption::key_exchange::{KeyPair, QuantumKeyExchange};
-use tracing::debug;
+use crate::quantum_encryption::secret::{SecretBytes, SecretKey};
+use crate::quantum_encryption::signing::{EmailHeader, SignatureService, SignatureStatus};
+use tracing::{debug, warn};
 
 
+/// A decrypted email and the result of checking its sender's signature
+#[derive(Debug, Clone)]
+pub struct DecryptedEmail {
+    pub content: String,
+    pub signature_status: SignatureStatus,
+}
+
 /// Service for decrypting messages using quantum-resistant algorithms
 pub struct DecryptionService {
     config: EncryptionConfig,
@@ -21,15 +33,15 @@ impl DecryptionService {
         }
     }
 
-    /// Decrypts a message using a shared secret and AES-GCM
+    /// Decrypts a message with AES-GCM under a key derived by the `kdf` module
     ///
     /// # Arguments
     /// * `ciphertext` - The encrypted data including the 12-byte nonce (IV) prepended
-    /// * `shared_secret` - The shared secret used to derive the decryption key
+    /// * `key` - The 32-byte content key
     ///
     /// # Returns
     /// A Result containing the decrypted plaintext as a Vec<u8> or an error if decryption fails
-    pub fn decrypt(&self, ciphertext: &[u8], shared_secret: &[u8]) -> Result<Vec<u8>> {
+    pub fn decrypt(&self, ciphertext: &[u8], key: &SecretKey) -> Result<Vec<u8>> {
         if ciphertext.len() < 12 {
             return Err(anyhow::anyhow!("Invalid ciphertext: length must be at least 12 bytes for nonce"));
         }
@@ -37,8 +49,7 @@ impl DecryptionService {
         let iv = &ciphertext[..12]; // 12-byte nonce for AES-GCM
         let encrypted_data = &ciphertext[12..];
 
-        let key_bytes = self.derive_key(shared_secret)?;
-        let key: &Key<Aes256Gcm> = Key::<Aes256Gcm>::from_slice(&key_bytes); // Explicit type annotation
+        let key: &Key<Aes256Gcm> = Key::<Aes256Gcm>::from_slice(key.expose_secret()); // Explicit type annotation
         let cipher = Aes256Gcm::new(key);
 
         debug!("Attempting to decrypt message with nonce length: {}", iv.len());
@@ -48,57 +59,310 @@ impl DecryptionService {
         Ok(plaintext)
     }
 
-    /// Derives a 32-byte decryption key from the shared secret using SHA3-256
+    /// Decrypts the body of a parsed envelope, authenticating its header
     ///
     /// # Arguments
-    /// * `shared_secret` - The shared secret to derive the key from
+    /// * `envelope` - The parsed envelope
+    /// * `key` - The 32-byte content key
     ///
     /// # Returns
-    /// A Result containing the derived 32-byte key or an error if derivation fails
-    fn derive_key(&self, shared_secret: &[u8]) -> Result<[u8; 32]> {
-        let mut hasher = Sha3_256::new();
-        hasher.update(shared_secret);
-        let result = hasher.finalize();
-
-        result.as_slice().try_into()
-            .map_err(|_| anyhow::anyhow!("Failed to derive 32-byte key from shared secret"))
+    /// A Result containing the decrypted plaintext or an error if the body or header was altered
+    pub fn open_envelope(&self, envelope: &Envelope, key: &SecretKey) -> Result<Vec<u8>> {
+        match envelope.aead {
+            AeadAlgorithm::Aes256Gcm => {
+                let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key.expose_secret()));
+                let header = envelope.header_bytes();
+                cipher
+                    .decrypt(Nonce::from_slice(&envelope.nonce), Payload { msg: &envelope.body, aad: &header })
+                    .map_err(|e| anyhow::anyhow!("Decryption failed: {}", e))
+            }
+        }
     }
 
-    /// Decrypts an email using the recipient's private key and quantum key exchange
+    /// Decrypts an email using the recipient's private key and verifies the sender's signature
     ///
     /// # Arguments
     /// * `encrypted_message` - The encrypted email content (with prepended nonce)
     /// * `encapsulated_secret` - The encapsulated shared secret from the sender
     /// * `recipient_key` - The recipient's quantum key pair
+    /// * `header` - The sender, recipient and subject the signature should cover
+    /// * `signature` - The sender's signature, if the email carries one
+    /// * `sender_public_key` - The public signing key of the claimed sender, if known
     ///
     /// # Returns
-    /// A Result containing the decrypted email as a String or an error if decryption fails
+    /// A Result containing the DecryptedEmail or an error if decryption fails.
+    /// A missing or bad signature does not fail decryption; it is reported in `signature_status`.
     pub fn decrypt_email(
         &self,
         encrypted_message: &[u8],
         encapsulated_secret: &[u8],
         recipient_key: &KeyPair,
-    ) -> Result<String> {
+        header: &EmailHeader,
+        signature: Option<&[u8]>,
+        sender_public_key: Option<&[u8]>,
+    ) -> Result<DecryptedEmail> {
         // Initialize quantum key exchange
         let key_exchange = QuantumKeyExchange::new(&self.config);
+        let kem = kem_for_suite(&recipient_key.algorithm)?;
+        let context = KeyContext {
+            email_id: header.email_id,
+            sender_id: header.sender_id,
+            recipient_id: header.recipient_id,
+            algorithm: kem.suite(),
+        };
+
+        let decrypted_data = if Envelope::is_envelope(encrypted_message) {
+            // The envelope says how it was encrypted, so no guessing is needed
+            let envelope = Envelope::parse(encrypted_message)?;
+            match envelope.kem {
+                Some(envelope_kem) if envelope_kem.id() == kem.id() => {}
+                Some(envelope_kem) => return Err(anyhow::anyhow!(
+                    "Content was encrypted for {} but the key is {}", envelope_kem.suite(), kem.suite()
+                )),
+                None => return Err(anyhow::anyhow!(
+                    "Content is encrypted for several recipients; decrypt it with the recipient's wrapped key"
+                )),
+            }
 
-        // Decapsulate the shared secret using recipient's private key
-        debug!("Decapsulating shared secret for email decryption");
-        let shared_secret = key_exchange.decapsulate(&recipient_key.private_key, encapsulated_secret)?;
+            debug!("Decapsulating shared secret from {} envelope", kem.suite());
+            let shared_secret = key_exchange.decapsulate(kem.suite(), &recipient_key.private_key, &envelope.kem_ciphertext)?;
+            let body_key = match envelope.kdf {
+                KdfAlgorithm::HkdfSha3 => derive_key(shared_secret.expose_secret(), &context, KeyPurpose::Body)?,
+                KdfAlgorithm::LegacySha3 => legacy_key(shared_secret.expose_secret()),
+            };
+            self.open_envelope(&envelope, &body_key)?
+        } else {
+            // Content stored before envelopes is `nonce || body` with the KEM ciphertext kept apart
+            debug!("Decapsulating shared secret for email decryption");
+            let shared_secret = key_exchange.decapsulate(kem.suite(), &recipient_key.private_key, encapsulated_secret)?;
+            let body_key = derive_key(shared_secret.expose_secret(), &context, KeyPurpose::Body)?;
+            match self.decrypt(encrypted_message, &body_key) {
+                Ok(data) => data,
+                // Mail stored before context binding used the bare hash of the shared secret
+                Err(_) => self.decrypt(encrypted_message, &legacy_key(shared_secret.expose_secret()))?,
+            }
+        };
+
+        Self::finish(decrypted_data, header, encapsulated_secret, encrypted_message, signature, sender_public_key)
+    }
+
+    /// Decrypts a multi-recipient email with the content key wrapped for this recipient
+    ///
+    /// # Arguments
+    /// * `encrypted_message` - The shared envelope holding the email body
+    /// * `wrapped_key` - The content key wrapped for the recipient
+    /// * `recipient_key` - The recipient's quantum key pair
+    /// * `header` - The sender and subject the signature should cover; `recipient_id` is nil
+    /// * `signature` - The sender's signature, if the email carries one
+    /// * `sender_public_key` - The public signing key of the claimed sender, if known
+    ///
+    /// # Returns
+    /// A Result containing the DecryptedEmail or an error if unwrapping or decryption fails
+    pub fn decrypt_for_recipient(
+        &self,
+        encrypted_message: &[u8],
+        wrapped_key: &WrappedKey,
+        recipient_key: &KeyPair,
+        header: &EmailHeader,
+        signature: Option<&[u8]>,
+        sender_public_key: Option<&[u8]>,
+    ) -> Result<DecryptedEmail> {
+        let envelope = Envelope::parse(encrypted_message)?;
+        if envelope.kem.is_some() {
+            return Err(anyhow::anyhow!("Content is encrypted for a single recipient, not with a wrapped key"));
+        }
+        let body_key = self.shared_key(wrapped_key, recipient_key, header, KeyPurpose::Body)?;
+        let decrypted_data = self.open_envelope(&envelope, &body_key)?;
 
-        // Decrypt the message with the shared secret
-        let decrypted_data = self.decrypt(encrypted_message, &shared_secret)?;
+        Self::finish(decrypted_data, header, &[], encrypted_message, signature, sender_public_key)
+    }
 
+    /// Decrypts headers sealed for a single recipient with their own KEM ciphertext
+    ///
+    /// # Arguments
+    /// * `encrypted_headers` - The headers envelope
+    /// * `recipient_key` - The recipient's quantum key pair
+    /// * `header` - The email the headers belong to; its subject is not used
+    ///
+    /// # Returns
+    /// A Result containing the SealedHeaders or an error if they cannot be opened
+    pub fn open_headers(
+        &self,
+        encrypted_headers: &[u8],
+        recipient_key: &KeyPair,
+        header: &EmailHeader,
+    ) -> Result<SealedHeaders> {
+        let envelope = Envelope::parse(encrypted_headers)?;
+        let kem = kem_for_suite(&recipient_key.algorithm)?;
+        match envelope.kem {
+            Some(envelope_kem) if envelope_kem.id() == kem.id() => {}
+            Some(envelope_kem) => return Err(anyhow::anyhow!(
+                "Headers were encrypted for {} but the key is {}", envelope_kem.suite(), kem.suite()
+            )),
+            None => return Err(anyhow::anyhow!(
+                "Headers are encrypted for several recipients; open them with the recipient's wrapped key"
+            )),
+        }
+
+        let shared_secret = QuantumKeyExchange::new(&self.config)
+            .decapsulate(kem.suite(), &recipient_key.private_key, &envelope.kem_ciphertext)?;
+        let subject_key = derive_key(shared_secret.expose_secret(), &KeyContext {
+            email_id: header.email_id,
+            sender_id: header.sender_id,
+            recipient_id: header.recipient_id,
+            algorithm: kem.suite(),
+        }, KeyPurpose::Subject)?;
+        SealedHeaders::from_bytes(&self.open_envelope(&envelope, &subject_key)?)
+    }
+
+    /// Decrypts the headers of a multi-recipient email with the content key wrapped for this recipient
+    ///
+    /// # Arguments
+    /// * `encrypted_headers` - The headers envelope
+    /// * `wrapped_key` - The content key wrapped for the recipient
+    /// * `recipient_key` - The recipient's quantum key pair
+    /// * `header` - The email the headers belong to; `recipient_id` is nil and the subject is not used
+    ///
+    /// # Returns
+    /// A Result containing the SealedHeaders or an error if they cannot be opened
+    pub fn open_shared_headers(
+        &self,
+        encrypted_headers: &[u8],
+        wrapped_key: &WrappedKey,
+        recipient_key: &KeyPair,
+        header: &EmailHeader,
+    ) -> Result<SealedHeaders> {
+        let envelope = Envelope::parse(encrypted_headers)?;
+        if envelope.kem.is_some() {
+            return Err(anyhow::anyhow!("Headers are encrypted for a single recipient, not with a wrapped key"));
+        }
+        let subject_key = self.shared_key(wrapped_key, recipient_key, header, KeyPurpose::Subject)?;
+        SealedHeaders::from_bytes(&self.open_envelope(&envelope, &subject_key)?)
+    }
+
+    /// Derives the key for one purpose from the secret encapsulated to a single recipient
+    ///
+    /// # Arguments
+    /// * `encrypted_message` - The email content; an envelope carries the KEM ciphertext itself
+    /// * `encapsulated_secret` - The KEM ciphertext stored beside content from before envelopes
+    /// * `recipient_key` - The recipient's quantum key pair
+    /// * `header` - The email the key is derived for; its subject is not used
+    /// * `purpose` - What the key will encrypt
+    ///
+    /// # Returns
+    /// A Result containing the derived key or an error if decapsulation fails
+    pub fn message_key(
+        &self,
+        encrypted_message: &[u8],
+        encapsulated_secret: &[u8],
+        recipient_key: &KeyPair,
+        header: &EmailHeader,
+        purpose: KeyPurpose,
+    ) -> Result<SecretKey> {
+        let kem = kem_for_suite(&recipient_key.algorithm)?;
+        let kem_ciphertext = if Envelope::is_envelope(encrypted_message) {
+            let envelope = Envelope::parse(encrypted_message)?;
+            match envelope.kem {
+                Some(envelope_kem) if envelope_kem.id() == kem.id() => envelope.kem_ciphertext,
+                Some(envelope_kem) => return Err(anyhow::anyhow!(
+                    "Content was encrypted for {} but the key is {}", envelope_kem.suite(), kem.suite()
+                )),
+                None => return Err(anyhow::anyhow!(
+                    "Content is encrypted for several recipients; derive its keys from the recipient's wrapped key"
+                )),
+            }
+        } else {
+            encapsulated_secret.to_vec()
+        };
+
+        let shared_secret = QuantumKeyExchange::new(&self.config)
+            .decapsulate(kem.suite(), &recipient_key.private_key, &kem_ciphertext)?;
+        derive_key(shared_secret.expose_secret(), &KeyContext {
+            email_id: header.email_id,
+            sender_id: header.sender_id,
+            recipient_id: header.recipient_id,
+            algorithm: kem.suite(),
+        }, purpose)
+    }
+
+    /// Unwraps the content key of a multi-recipient email and derives the key for one purpose
+    ///
+    /// # Arguments
+    /// * `wrapped_key` - The content key wrapped for the recipient
+    /// * `recipient_key` - The recipient's quantum key pair
+    /// * `header` - The email the key is derived for; `recipient_id` is nil and the subject is not used
+    /// * `purpose` - What the key will encrypt
+    ///
+    /// # Returns
+    /// A Result containing the derived key or an error if unwrapping fails
+    pub fn shared_key(
+        &self,
+        wrapped_key: &WrappedKey,
+        recipient_key: &KeyPair,
+        header: &EmailHeader,
+        purpose: KeyPurpose,
+    ) -> Result<SecretKey> {
+        let kem = kem_for_suite(&recipient_key.algorithm)?;
+        if kem.id() != kem_for_suite(&wrapped_key.encryption_method)?.id() {
+            return Err(anyhow::anyhow!(
+                "Content key was wrapped for {} but the key is {}", wrapped_key.encryption_method, kem.suite()
+            ));
+        }
+
+        debug!("Unwrapping content key for recipient {}", wrapped_key.recipient_id);
+        let shared_secret = QuantumKeyExchange::new(&self.config)
+            .decapsulate(kem.suite(), &recipient_key.private_key, &wrapped_key.kem_ciphertext)?;
+        let wrap_key = derive_key(shared_secret.expose_secret(), &KeyContext {
+            email_id: header.email_id,
+            sender_id: header.sender_id,
+            recipient_id: wrapped_key.recipient_id,
+            algorithm: kem.suite(),
+        }, KeyPurpose::KeyWrap)?;
+        let content_key = SecretBytes::new(self.decrypt(&wrapped_key.wrapped_key, &wrap_key)?);
+        if content_key.expose_secret().len() != KEY_LEN {
+            return Err(anyhow::anyhow!("Unwrapped content key has the wrong length"));
+        }
+
+        derive_key(content_key.expose_secret(), &KeyContext {
+            email_id: header.email_id,
+            sender_id: header.sender_id,
+            recipient_id: header.recipient_id,
+            algorithm: SHARED_CONTENT_KEY,
+        }, purpose)
+    }
+
+    /// Decodes the plaintext and checks the sender's signature over what was received
+    fn finish(
+        decrypted_data: Vec<u8>,
+        header: &EmailHeader,
+        encapsulated_secret: &[u8],
+        encrypted_message: &[u8],
+        signature: Option<&[u8]>,
+        sender_public_key: Option<&[u8]>,
+    ) -> Result<DecryptedEmail> {
         // Convert decrypted bytes to UTF-8 string
-        String::from_utf8(decrypted_data)
-            .map_err(|e| anyhow::anyhow!("Failed to decode decrypted email to UTF-8: {}", e))
+        let content = String::from_utf8(decrypted_data)
+            .map_err(|e| anyhow::anyhow!("Failed to decode decrypted email to UTF-8: {}", e))?;
+
+        let signature_status = match (signature, sender_public_key) {
+            (Some(signature), Some(public_key)) => SignatureService::new()
+                .verify_email(public_key, signature, header, encapsulated_secret, encrypted_message),
+            _ => SignatureStatus::Unverified,
+        };
+        if signature_status == SignatureStatus::Invalid {
+            warn!("Invalid signature on email claiming to be from {}", header.sender_id);
+        }
+
+        Ok(DecryptedEmail { content, signature_status })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::quantum_encryption::encryption::EncryptionService;
+    use crate::quantum_encryption::encryption::{EncryptionService, RecipientKey};
+    use std::collections::BTreeMap;
+    use uuid::Uuid;
 
     #[test]
     fn test_encrypt_decrypt_roundtrip() -> Result<()> {
@@ -106,29 +370,330 @@ mod tests {
             key_rotation_days: 30,
             algorithm: "kyber".to_string(),
             key_size: 1024,
+            key_encryption_secret: "test-secret".to_string(),
         };
 
         let encrypt_service = EncryptionService::new(&config);
         let decrypt_service = DecryptionService::new(&config);
 
         let key_exchange = QuantumKeyExchange::new(&config);
-        let sender_key = key_exchange.generate_key_pair()?;
+        let sender_key = SignatureService::new().generate_key_pair();
         let recipient_key = key_exchange.generate_key_pair()?;
+        let header = EmailHeader {
+            email_id: Uuid::new_v4(),
+            sender_id: Uuid::new_v4(),
+            recipient_id: Uuid::new_v4(),
+            subject: "Greetings".to_string(),
+        };
 
         let plaintext = "Hello, Quantum World!".as_bytes();
-        let (encapsulated_secret, encrypted_message) = encrypt_service.encrypt_email(
+        let encrypted = encrypt_service.encrypt_email(
             std::str::from_utf8(plaintext)?,
+            &header,
+            &BTreeMap::new(),
             &sender_key,
+            &recipient_key.algorithm,
             &recipient_key.public_key,
         )?;
 
         let decrypted = decrypt_service.decrypt_email(
-            &encrypted_message,
-            &encapsulated_secret,
+            &encrypted.ciphertext,
+            &encrypted.encapsulated_secret,
             &recipient_key,
+            &header,
+            Some(&encrypted.signature),
+            Some(&sender_key.public_key),
         )?;
+        assert_eq!(decrypted.content, "Hello, Quantum World!");
+        assert_eq!(decrypted.signature_status, SignatureStatus::Verified);
+
+        // The subject travels sealed in its own envelope
+        let headers = decrypt_service.open_headers(&encrypted.encrypted_headers, &recipient_key, &header)?;
+        assert_eq!(headers.subject, "Greetings");
+        let moved = EmailHeader { email_id: Uuid::new_v4(), ..header.clone() };
+        assert!(decrypt_service.open_headers(&encrypted.encrypted_headers, &recipient_key, &moved).is_err());
+
+        // Without a signature the content is still readable but not authenticated
+        let unsigned = decrypt_service.decrypt_email(
+            &encrypted.ciphertext,
+            &encrypted.encapsulated_secret,
+            &recipient_key,
+            &header,
+            None,
+            None,
+        )?;
+        assert_eq!(unsigned.signature_status, SignatureStatus::Unverified);
+
+        // A tampered subject is reported as invalid
+        let tampered = EmailHeader { subject: "Urgent".to_string(), ..header.clone() };
+        let forged = decrypt_service.decrypt_email(
+            &encrypted.ciphertext,
+            &encrypted.encapsulated_secret,
+            &recipient_key,
+            &tampered,
+            Some(&encrypted.signature),
+            Some(&sender_key.public_key),
+        )?;
+        assert_eq!(forged.signature_status, SignatureStatus::Invalid);
+
+        // A spoofed sender does not even decrypt, since the sender is bound into the key
+        let spoofed = EmailHeader { sender_id: Uuid::new_v4(), ..header };
+        assert!(decrypt_service.decrypt_email(
+            &encrypted.ciphertext,
+            &encrypted.encapsulated_secret,
+            &recipient_key,
+            &spoofed,
+            Some(&encrypted.signature),
+            Some(&sender_key.public_key),
+        ).is_err());
+        Ok(())
+    }
 
-        assert_eq!(decrypted, "Hello, Quantum World!");
+    #[test]
+    fn test_content_is_bound_to_its_email() -> Result<()> {
+        let config = EncryptionConfig {
+            key_rotation_days: 30,
+            algorithm: "kyber".to_string(),
+            key_size: 768,
+            key_encryption_secret: "test-secret".to_string(),
+        };
+        let recipient_key = QuantumKeyExchange::new(&config).generate_key_pair()?;
+        let sender_key = SignatureService::new().generate_key_pair();
+        let header = EmailHeader {
+            email_id: Uuid::new_v4(),
+            sender_id: Uuid::new_v4(),
+            recipient_id: Uuid::new_v4(),
+            subject: String::new(),
+        };
+        let encrypted = EncryptionService::new(&config).encrypt_email(
+            "Bound content",
+            &header,
+            &BTreeMap::new(),
+            &sender_key,
+            &recipient_key.algorithm,
+            &recipient_key.public_key,
+        )?;
+
+        let decrypt_service = DecryptionService::new(&config);
+        let moved = EmailHeader { email_id: Uuid::new_v4(), ..header.clone() };
+        assert!(decrypt_service.decrypt_email(
+            &encrypted.ciphertext, &encrypted.encapsulated_secret, &recipient_key, &moved, None, None,
+        ).is_err());
+        let readdressed = EmailHeader { recipient_id: Uuid::new_v4(), ..header };
+        assert!(decrypt_service.decrypt_email(
+            &encrypted.ciphertext, &encrypted.encapsulated_secret, &recipient_key, &readdressed, None, None,
+        ).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_envelope_header_is_authenticated() -> Result<()> {
+        let config = EncryptionConfig {
+            key_rotation_days: 30,
+            algorithm: "kyber".to_string(),
+            key_size: 768,
+            key_encryption_secret: "test-secret".to_string(),
+        };
+        let recipient_key = QuantumKeyExchange::new(&config).generate_key_pair()?;
+        let sender_key = SignatureService::new().generate_key_pair();
+        let header = EmailHeader {
+            email_id: Uuid::new_v4(),
+            sender_id: Uuid::new_v4(),
+            recipient_id: Uuid::new_v4(),
+            subject: String::new(),
+        };
+        let encrypted = EncryptionService::new(&config).encrypt_email(
+            "Enveloped",
+            &header,
+            &BTreeMap::new(),
+            &sender_key,
+            &recipient_key.algorithm,
+            &recipient_key.public_key,
+        )?;
+
+        let envelope = Envelope::parse(&encrypted.ciphertext)?;
+        assert_eq!(envelope.kem.map(|kem| kem.suite()), Some("kyber768"));
+        assert_eq!(envelope.kdf, KdfAlgorithm::HkdfSha3);
+        assert_eq!(envelope.kem_ciphertext, encrypted.encapsulated_secret);
+
+        // Relabelling the KDF is caught by the AEAD even though the envelope still parses
+        let mut relabelled = envelope.clone();
+        relabelled.kdf = KdfAlgorithm::LegacySha3;
+        let decrypt_service = DecryptionService::new(&config);
+        assert!(decrypt_service.decrypt_email(
+            &relabelled.serialize(), &[], &recipient_key, &header, None, None,
+        ).is_err());
+
+        let mut truncated = encrypted.ciphertext.clone();
+        truncated.truncate(40);
+        let error = decrypt_service
+            .decrypt_email(&truncated, &[], &recipient_key, &header, None, None)
+            .unwrap_err();
+        assert!(error.to_string().contains("truncated"));
+
+        // The KEM ciphertext travels inside the envelope
+        let decrypted = decrypt_service.decrypt_email(&encrypted.ciphertext, &[], &recipient_key, &header, None, None)?;
+        assert_eq!(decrypted.content, "Enveloped");
+
+        // The same secret yields the keys for the email's other parts
+        let body_key = decrypt_service.message_key(&encrypted.ciphertext, &[], &recipient_key, &header, KeyPurpose::Body)?;
+        assert_eq!(decrypt_service.open_envelope(&envelope, &body_key)?, b"Enveloped");
+        let attachment_key = decrypt_service.message_key(&encrypted.ciphertext, &[], &recipient_key, &header, KeyPurpose::Attachment)?;
+        assert_ne!(attachment_key, body_key);
+        Ok(())
+    }
+
+    #[test]
+    fn test_decrypts_legacy_hash_derived_content() -> Result<()> {
+        let config = EncryptionConfig {
+            key_rotation_days: 30,
+            algorithm: "kyber".to_string(),
+            key_size: 1024,
+            key_encryption_secret: "test-secret".to_string(),
+        };
+        let key_exchange = QuantumKeyExchange::new(&config);
+        let recipient_key = key_exchange.generate_key_pair()?;
+        let (encapsulated_secret, shared_secret) = key_exchange.encapsulate(&recipient_key.algorithm, &recipient_key.public_key)?;
+        let ciphertext = EncryptionService::new(&config).encrypt(b"Old mail", &legacy_key(shared_secret.expose_secret()))?;
+
+        let header = EmailHeader {
+            email_id: Uuid::new_v4(),
+            sender_id: Uuid::new_v4(),
+            recipient_id: Uuid::new_v4(),
+            subject: String::new(),
+        };
+        let decrypted = DecryptionService::new(&config)
+            .decrypt_email(&ciphertext, &encapsulated_secret, &recipient_key, &header, None, None)?;
+        assert_eq!(decrypted.content, "Old mail");
+        Ok(())
+    }
+
+    #[test]
+    fn test_decrypts_mail_from_older_suite() -> Result<()> {
+        let mut config = EncryptionConfig {
+            key_rotation_days: 30,
+            algorithm: "kyber".to_string(),
+            key_size: 512,
+            key_encryption_secret: "test-secret".to_string(),
+        };
+        let recipient_key = QuantumKeyExchange::new(&config).generate_key_pair()?;
+        let sender_key = SignatureService::new().generate_key_pair();
+        let header = EmailHeader {
+            email_id: Uuid::new_v4(),
+            sender_id: Uuid::new_v4(),
+            recipient_id: Uuid::new_v4(),
+            subject: String::new(),
+        };
+        let encrypted = EncryptionService::new(&config).encrypt_email(
+            "Sent under Kyber-512",
+            &header,
+            &BTreeMap::new(),
+            &sender_key,
+            &recipient_key.algorithm,
+            &recipient_key.public_key,
+        )?;
+        assert_eq!(encrypted.encryption_method, "kyber512");
+
+        // The deployment has since moved to Kyber-1024
+        config.key_size = 1024;
+        let decrypted = DecryptionService::new(&config).decrypt_email(
+            &encrypted.ciphertext,
+            &encrypted.encapsulated_secret,
+            &recipient_key,
+            &header,
+            None,
+            None,
+        )?;
+        assert_eq!(decrypted.content, "Sent under Kyber-512");
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_recipient_roundtrip() -> Result<()> {
+        let config = EncryptionConfig {
+            key_rotation_days: 30,
+            algorithm: "kyber".to_string(),
+            key_size: 768,
+            key_encryption_secret: "test-secret".to_string(),
+        };
+        let key_exchange = QuantumKeyExchange::new(&config);
+        let sender_key = SignatureService::new().generate_key_pair();
+        let to_key = key_exchange.generate_key_pair()?;
+        let cc_key = key_exchange.generate_key_pair()?;
+        let (to_id, cc_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let header = EmailHeader {
+            email_id: Uuid::new_v4(),
+            sender_id: Uuid::new_v4(),
+            recipient_id: Uuid::nil(),
+            subject: "Team update".to_string(),
+        };
+
+        let encrypted = EncryptionService::new(&config).encrypt_for_recipients(
+            "Hello, everyone",
+            &header,
+            &BTreeMap::new(),
+            &sender_key,
+            &[
+                RecipientKey { recipient_id: to_id, algorithm: &to_key.algorithm, public_key: &to_key.public_key },
+                RecipientKey { recipient_id: cc_id, algorithm: &cc_key.algorithm, public_key: &cc_key.public_key },
+            ],
+        )?;
+        assert_eq!(encrypted.wrapped_keys.len(), 2);
+        assert!(Envelope::parse(&encrypted.ciphertext)?.kem.is_none());
+
+        let decrypt_service = DecryptionService::new(&config);
+        for (wrapped, key) in encrypted.wrapped_keys.iter().zip([&to_key, &cc_key]) {
+            let decrypted = decrypt_service.decrypt_for_recipient(
+                &encrypted.ciphertext, wrapped, key, &header, Some(&encrypted.signature), Some(&sender_key.public_key),
+            )?;
+            assert_eq!(decrypted.content, "Hello, everyone");
+            assert_eq!(decrypted.signature_status, SignatureStatus::Verified);
+            let headers = decrypt_service.open_shared_headers(&encrypted.encrypted_headers, wrapped, key, &header)?;
+            assert_eq!(headers.subject, "Team update");
+        }
+
+        // A wrapped key only opens for the recipient it was wrapped for
+        assert!(decrypt_service.decrypt_for_recipient(
+            &encrypted.ciphertext, &encrypted.wrapped_keys[0], &cc_key, &header, None, None,
+        ).is_err());
+        let relabelled = WrappedKey { recipient_id: cc_id, ..encrypted.wrapped_keys[0].clone() };
+        assert!(decrypt_service.decrypt_for_recipient(
+            &encrypted.ciphertext, &relabelled, &to_key, &header, None, None,
+        ).is_err());
+
+        // The shared body is not readable as single-recipient mail
+        assert!(decrypt_service.decrypt_email(
+            &encrypted.ciphertext, &[], &to_key, &header, None, None,
+        ).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_headers_sealed_for_stored_mail() -> Result<()> {
+        let config = EncryptionConfig {
+            key_rotation_days: 30,
+            algorithm: "x25519-kyber768".to_string(),
+            key_size: 768,
+            key_encryption_secret: "test-secret".to_string(),
+        };
+        let recipient_key = QuantumKeyExchange::new(&config).generate_key_pair()?;
+        let header = EmailHeader {
+            email_id: Uuid::new_v4(),
+            sender_id: Uuid::new_v4(),
+            recipient_id: Uuid::new_v4(),
+            subject: "Stored before headers were sealed".to_string(),
+        };
+        let headers = SealedHeaders::new(
+            header.subject.as_str(),
+            BTreeMap::from([("in-reply-to".to_string(), Uuid::new_v4().to_string())]),
+        );
+        let sealed = EncryptionService::new(&config)
+            .seal_headers_for(&headers, &header, &recipient_key.algorithm, &recipient_key.public_key)?;
+
+        let decrypt_service = DecryptionService::new(&config);
+        assert_eq!(decrypt_service.open_headers(&sealed, &recipient_key, &header)?, headers);
+        let other_key = QuantumKeyExchange::new(&config).generate_key_pair()?;
+        assert!(decrypt_service.open_headers(&sealed, &other_key, &header).is_err());
         Ok(())
     }
 }
@@ -1,13 +1,90 @@
 // src/quantum_encryption/encryption.rs
+use std::collections::BTreeMap;
+
 use anyhow::Result;
 use rand::rngs::OsRng;
 use rand::RngCore;
-use sha3::{Digest, Sha3_256};
 use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce}; // Explicitly import required types
-use aes_gcm::aead::Aead; // Import Aead trait
+use aes_gcm::aead::{Aead, Payload}; // Import Aead trait
 use crate::config::EncryptionConfig;
-use crate::quantum_encryption::key_exchange::{KeyPair, QuantumKeyExchange};
+use crate::quantum_encryption::kdf::{derive_key, derive_message_keys, KeyContext, KeyPurpose, KEY_LEN, SHARED_CONTENT_KEY};
+use crate::quantum_encryption::envelope::{AeadAlgorithm, Envelope, KdfAlgorithm, ENVELOPE_VERSION, NONCE_LEN};
+use crate::quantum_encryption::kem::{kem_for_suite, Kem};
+use crate::quantum_encryption::key_exchange::QuantumKeyExchange;
+use crate::quantum_encryption::secret::SecretKey;
+use crate::quantum_encryption::signing::{EmailHeader, SignatureService, SigningKeyPair};
+use serde::{Deserialize, Serialize};
 use tracing::debug;
+use uuid::Uuid;
+
+/// Headers sealed into their own envelope so the server only stores a placeholder subject
+///
+/// Serialized as JSON before encryption. The subject is the one covered by the sender's
+/// signature; `headers` holds any further headers the client chose to protect.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealedHeaders {
+    pub subject: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl SealedHeaders {
+    pub fn new(subject: impl Into<String>, headers: BTreeMap<String, String>) -> Self {
+        Self { subject: subject.into(), headers }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes).map_err(|e| anyhow::anyhow!("Invalid sealed headers: {}", e))
+    }
+}
+
+/// An encrypted email together with the sender's signature over it
+#[derive(Debug, Clone)]
+pub struct EncryptedEmail {
+    pub encapsulated_secret: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+    /// Envelope holding the SealedHeaders, with its own copy of the KEM ciphertext
+    pub encrypted_headers: Vec<u8>,
+    /// KEM suite the secret was encapsulated with, stored as `Email.encryption_method`
+    pub encryption_method: String,
+    pub signature: Vec<u8>,
+    pub signing_key_id: Uuid,
+}
+
+/// A recipient of a multi-recipient email and the key their copy is wrapped to
+#[derive(Debug, Clone, Copy)]
+pub struct RecipientKey<'a> {
+    pub recipient_id: Uuid,
+    /// KEM suite recorded with the recipient's key
+    pub algorithm: &'a str,
+    pub public_key: &'a [u8],
+}
+
+/// The content key of a multi-recipient email, wrapped for one recipient
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WrappedKey {
+    pub recipient_id: Uuid,
+    /// KEM suite the wrapping secret was encapsulated with
+    pub encryption_method: String,
+    pub kem_ciphertext: Vec<u8>,
+    /// The content key encrypted under the recipient's key-wrap key (nonce prepended)
+    pub wrapped_key: Vec<u8>,
+}
+
+/// An email encrypted once under a random content key that is wrapped for every recipient
+#[derive(Debug, Clone)]
+pub struct MultiRecipientEmail {
+    pub ciphertext: Vec<u8>,
+    /// Envelope holding the SealedHeaders under the shared content key
+    pub encrypted_headers: Vec<u8>,
+    pub wrapped_keys: Vec<WrappedKey>,
+    pub signature: Vec<u8>,
+    pub signing_key_id: Uuid,
+}
 
 /// Service for encrypting messages using quantum-resistant algorithms
 pub struct EncryptionService {
@@ -22,17 +99,16 @@ impl EncryptionService {
         }
     }
 
-    /// Encrypts a message using a shared secret and AES-GCM
+    /// Encrypts a message with AES-GCM under a key derived by the `kdf` module
     ///
     /// # Arguments
     /// * `plaintext` - The data to encrypt
-    /// * `shared_secret` - The shared secret used to derive the encryption key
+    /// * `key` - The 32-byte content key
     ///
     /// # Returns
     /// A Result containing the encrypted data (nonce prepended) as a Vec<u8> or an error if encryption fails
-    pub fn encrypt(&self, plaintext: &[u8], shared_secret: &[u8]) -> Result<Vec<u8>> {
-        let key_bytes = self.derive_key(shared_secret)?;
-        let key: &Key<Aes256Gcm> = Key::<Aes256Gcm>::from_slice(&key_bytes); // Explicit type annotation
+    pub fn encrypt(&self, plaintext: &[u8], key: &SecretKey) -> Result<Vec<u8>> {
+        let key: &Key<Aes256Gcm> = Key::<Aes256Gcm>::from_slice(key.expose_secret()); // Explicit type annotation
         let cipher = Aes256Gcm::new(key);
 
         let mut iv = [0u8; 12]; // 96-bit (12-byte) nonce for AES-GCM
@@ -48,49 +124,253 @@ impl EncryptionService {
         Ok(result)
     }
 
-    /// Derives a 32-byte encryption key from the shared secret using SHA3-256
+    /// Encrypts a message into a versioned envelope that records how it was encrypted
+    ///
+    /// # Arguments
+    /// * `plaintext` - The data to encrypt
+    /// * `key` - The 32-byte content key
+    /// * `kem` - The KEM the content key's shared secret came from, if it was encapsulated directly
+    /// * `kdf` - How the content key was derived from the shared secret
+    /// * `kem_ciphertext` - The encapsulated shared secret
+    /// * `aad` - Associated data to authenticate alongside the body
+    ///
+    /// # Returns
+    /// A Result containing the sealed Envelope or an error if encryption fails
+    pub fn seal_envelope(
+        &self,
+        plaintext: &[u8],
+        key: &SecretKey,
+        kem: Option<&'static dyn Kem>,
+        kdf: KdfAlgorithm,
+        kem_ciphertext: Vec<u8>,
+        aad: Vec<u8>,
+    ) -> Result<Envelope> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut envelope = Envelope {
+            version: ENVELOPE_VERSION,
+            kem,
+            aead: AeadAlgorithm::Aes256Gcm,
+            kdf,
+            kem_ciphertext,
+            nonce,
+            aad,
+            body: Vec::new(),
+        };
+
+        // The whole header is authenticated so algorithm IDs cannot be swapped
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key.expose_secret()));
+        let header = envelope.header_bytes();
+        envelope.body = cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: &header })
+            .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
+        Ok(envelope)
+    }
+
+    /// Seals headers into an envelope under a subject key
     ///
     /// # Arguments
-    /// * `shared_secret` - The shared secret to derive the key from
+    /// * `headers` - The headers to seal
+    /// * `key` - The subject key derived for the email
+    /// * `kem` - The KEM the subject key's shared secret came from, if it was encapsulated directly
+    /// * `kem_ciphertext` - The encapsulated shared secret
     ///
     /// # Returns
-    /// A Result containing the derived 32-byte key or an error if derivation fails
-    fn derive_key(&self, shared_secret: &[u8]) -> Result<[u8; 32]> {
-        let mut hasher = Sha3_256::new();
-        hasher.update(shared_secret);
-        let result = hasher.finalize();
-
-        result.as_slice().try_into()
-            .map_err(|_| anyhow::anyhow!("Failed to derive 32-byte key from shared secret"))
+    /// A Result containing the serialized envelope or an error if encryption fails
+    pub fn seal_headers(
+        &self,
+        headers: &SealedHeaders,
+        key: &SecretKey,
+        kem: Option<&'static dyn Kem>,
+        kem_ciphertext: Vec<u8>,
+    ) -> Result<Vec<u8>> {
+        Ok(self
+            .seal_envelope(&headers.to_bytes()?, key, kem, KdfAlgorithm::HkdfSha3, kem_ciphertext, Vec::new())?
+            .serialize())
     }
 
-    /// Encrypts an email for a recipient using quantum key exchange
+    /// Seals headers for a recipient under a fresh encapsulation to their key
+    ///
+    /// Used to protect the subject of mail stored before headers were encrypted, which
+    /// needs no access to the secret the body was encrypted under.
+    ///
+    /// # Arguments
+    /// * `headers` - The headers to seal
+    /// * `header` - The email the headers belong to
+    /// * `recipient_algorithm` - The KEM suite recorded with the recipient's key
+    /// * `recipient_public_key` - The recipient's public key for encapsulation
+    ///
+    /// # Returns
+    /// A Result containing the serialized envelope or an error if encapsulation or encryption fails
+    pub fn seal_headers_for(
+        &self,
+        headers: &SealedHeaders,
+        header: &EmailHeader,
+        recipient_algorithm: &str,
+        recipient_public_key: &[u8],
+    ) -> Result<Vec<u8>> {
+        let (kem_ciphertext, shared_secret) = QuantumKeyExchange::new(&self.config)
+            .encapsulate(recipient_algorithm, recipient_public_key)?;
+        let kem = kem_for_suite(recipient_algorithm)?;
+        let subject_key = derive_key(shared_secret.expose_secret(), &KeyContext {
+            email_id: header.email_id,
+            sender_id: header.sender_id,
+            recipient_id: header.recipient_id,
+            algorithm: kem.suite(),
+        }, KeyPurpose::Subject)?;
+        self.seal_headers(headers, &subject_key, Some(kem), kem_ciphertext)
+    }
+
+    /// Encrypts an email for a recipient using quantum key exchange and signs it
     ///
     /// # Arguments
     /// * `plaintext` - The email content to encrypt as a string
-    /// * `sender_key` - The sender's quantum key pair (not used directly here but included for future extensions)
+    /// * `header` - The sender, recipient and subject covered by the signature
+    /// * `extra_headers` - Further headers to seal alongside the subject
+    /// * `sender_key` - The sender's Dilithium signing key pair
+    /// * `recipient_algorithm` - The KEM suite recorded with the recipient's key
     /// * `recipient_public_key` - The recipient's public key for encapsulation
     ///
     /// # Returns
-    /// A Result containing a tuple of (encapsulated_secret, encrypted_message) or an error if encryption fails
+    /// A Result containing the EncryptedEmail or an error if encryption or signing fails
     pub fn encrypt_email(
         &self,
         plaintext: &str,
-        _sender_key: &KeyPair, // Currently unused but kept for potential future use
+        header: &EmailHeader,
+        extra_headers: &BTreeMap<String, String>,
+        sender_key: &SigningKeyPair,
+        recipient_algorithm: &str,
         recipient_public_key: &[u8],
-    ) -> Result<(Vec<u8>, Vec<u8>)> {
+    ) -> Result<EncryptedEmail> {
         // Use the key exchange to create a shared secret
         let key_exchange = QuantumKeyExchange::new(&self.config);
 
         // Encapsulate a shared secret for the recipient
         debug!("Encapsulating shared secret for email encryption");
-        let (encapsulated_secret, shared_secret) = key_exchange.encapsulate(recipient_public_key)?;
+        let (encapsulated_secret, shared_secret) = key_exchange.encapsulate(recipient_algorithm, recipient_public_key)?;
+        let kem = kem_for_suite(recipient_algorithm)?;
+        let encryption_method = kem.suite();
+
+        // Derive the content keys for this email and encrypt the body
+        let keys = derive_message_keys(shared_secret.expose_secret(), &KeyContext {
+            email_id: header.email_id,
+            sender_id: header.sender_id,
+            recipient_id: header.recipient_id,
+            algorithm: encryption_method,
+        })?;
+        let encrypted_message = self
+            .seal_envelope(
+                plaintext.as_bytes(),
+                &keys.body,
+                Some(kem),
+                KdfAlgorithm::HkdfSha3,
+                encapsulated_secret.clone(),
+                Vec::new(),
+            )?
+            .serialize();
+        // The headers envelope repeats the KEM ciphertext so it can be opened on its own
+        let encrypted_headers = self.seal_headers(
+            &SealedHeaders::new(header.subject.as_str(), extra_headers.clone()),
+            &keys.subject,
+            Some(kem),
+            encapsulated_secret.clone(),
+        )?;
+
+        // Sign the header and both ciphertexts so recipients can authenticate the sender
+        let signature = SignatureService::new()
+            .sign_email(sender_key, header, &encapsulated_secret, &encrypted_message)?;
+
+        Ok(EncryptedEmail {
+            encapsulated_secret,
+            ciphertext: encrypted_message,
+            encrypted_headers,
+            encryption_method: encryption_method.to_string(),
+            signature,
+            signing_key_id: sender_key.id,
+        })
+    }
+
+    /// Encrypts an email once and wraps its content key for each recipient
+    ///
+    /// The body is sealed under a random content key in an envelope that names no KEM. For
+    /// every recipient a fresh secret is encapsulated to their key, and the content key is
+    /// encrypted under a key-wrap key derived from that secret and the recipient's ID.
+    ///
+    /// # Arguments
+    /// * `plaintext` - The email content to encrypt as a string
+    /// * `header` - The sender and subject covered by the signature; `recipient_id` must be nil
+    /// * `extra_headers` - Further headers to seal alongside the subject
+    /// * `sender_key` - The sender's Dilithium signing key pair
+    /// * `recipients` - Everyone who can read the email, including the sender's own copy
+    ///
+    /// # Returns
+    /// A Result containing the MultiRecipientEmail or an error if encryption or signing fails
+    pub fn encrypt_for_recipients(
+        &self,
+        plaintext: &str,
+        header: &EmailHeader,
+        extra_headers: &BTreeMap<String, String>,
+        sender_key: &SigningKeyPair,
+        recipients: &[RecipientKey],
+    ) -> Result<MultiRecipientEmail> {
+        if !header.recipient_id.is_nil() {
+            return Err(anyhow::anyhow!("A multi-recipient email is not addressed to a single recipient"));
+        }
+        if recipients.is_empty() {
+            return Err(anyhow::anyhow!("An email needs at least one recipient"));
+        }
 
-        // Encrypt the message with the shared secret
-        let encrypted_message = self.encrypt(plaintext.as_bytes(), &shared_secret)?;
+        let mut content_key = SecretKey::new([0u8; KEY_LEN]);
+        OsRng.fill_bytes(content_key.expose_secret_mut());
 
-        // Return the encapsulated shared secret and the encrypted message
-        Ok((encapsulated_secret, encrypted_message))
+        let keys = derive_message_keys(content_key.expose_secret(), &KeyContext {
+            email_id: header.email_id,
+            sender_id: header.sender_id,
+            recipient_id: header.recipient_id,
+            algorithm: SHARED_CONTENT_KEY,
+        })?;
+        let encrypted_message = self
+            .seal_envelope(plaintext.as_bytes(), &keys.body, None, KdfAlgorithm::HkdfSha3, Vec::new(), Vec::new())?
+            .serialize();
+        let encrypted_headers = self.seal_headers(
+            &SealedHeaders::new(header.subject.as_str(), extra_headers.clone()),
+            &keys.subject,
+            None,
+            Vec::new(),
+        )?;
+
+        let key_exchange = QuantumKeyExchange::new(&self.config);
+        let mut wrapped_keys = Vec::with_capacity(recipients.len());
+        for recipient in recipients {
+            debug!("Wrapping content key for recipient {}", recipient.recipient_id);
+            let (kem_ciphertext, shared_secret) = key_exchange.encapsulate(recipient.algorithm, recipient.public_key)?;
+            let encryption_method = kem_for_suite(recipient.algorithm)?.suite();
+            let wrap_key = derive_key(shared_secret.expose_secret(), &KeyContext {
+                email_id: header.email_id,
+                sender_id: header.sender_id,
+                recipient_id: recipient.recipient_id,
+                algorithm: encryption_method,
+            }, KeyPurpose::KeyWrap)?;
+
+            wrapped_keys.push(WrappedKey {
+                recipient_id: recipient.recipient_id,
+                encryption_method: encryption_method.to_string(),
+                kem_ciphertext,
+                wrapped_key: self.encrypt(content_key.expose_secret(), &wrap_key)?,
+            });
+        }
+
+        // One signature covers the shared body; there is no single encapsulated secret
+        let signature = SignatureService::new().sign_email(sender_key, header, &[], &encrypted_message)?;
+
+        Ok(MultiRecipientEmail {
+            ciphertext: encrypted_message,
+            encrypted_headers,
+            wrapped_keys,
+            signature,
+            signing_key_id: sender_key.id,
+        })
     }
 }
 
@@ -98,6 +378,7 @@ impl EncryptionService {
 mod tests {
     use super::*;
     use crate::quantum_encryption::decryption::DecryptionService;
+    use crate::quantum_encryption::signing::SignatureStatus;
 
     #[test]
     fn test_encrypt_decrypt_roundtrip() -> Result<()> {
@@ -105,29 +386,44 @@ mod tests {
             key_rotation_days: 30,
             algorithm: "kyber".to_string(),
             key_size: 1024,
+            key_encryption_secret: "test-secret".to_string(),
         };
 
         let encrypt_service = EncryptionService::new(&config);
         let decrypt_service = DecryptionService::new(&config);
 
         let key_exchange = QuantumKeyExchange::new(&config);
-        let sender_key = key_exchange.generate_key_pair()?;
+        let sender_key = SignatureService::new().generate_key_pair();
         let recipient_key = key_exchange.generate_key_pair()?;
+        let header = EmailHeader {
+            email_id: Uuid::new_v4(),
+            sender_id: Uuid::new_v4(),
+            recipient_id: Uuid::new_v4(),
+            subject: "Greetings".to_string(),
+        };
 
         let plaintext = "Hello, Quantum World!";
-        let (encapsulated_secret, encrypted_message) = encrypt_service.encrypt_email(
+        let encrypted = encrypt_service.encrypt_email(
             plaintext,
+            &header,
+            &BTreeMap::new(),
             &sender_key,
+            &recipient_key.algorithm,
             &recipient_key.public_key,
         )?;
+        assert_eq!(encrypted.signing_key_id, sender_key.id);
 
         let decrypted = decrypt_service.decrypt_email(
-            &encrypted_message,
-            &encapsulated_secret,
+            &encrypted.ciphertext,
+            &encrypted.encapsulated_secret,
             &recipient_key,
+            &header,
+            Some(&encrypted.signature),
+            Some(&sender_key.public_key),
         )?;
 
-        assert_eq!(decrypted, plaintext);
+        assert_eq!(decrypted.content, plaintext);
+        assert_eq!(decrypted.signature_status, SignatureStatus::Verified);
         Ok(())
     }
 
@@ -137,12 +433,13 @@ mod tests {
             key_rotation_days: 30,
             algorithm: "kyber".to_string(),
             key_size: 1024,
+            key_encryption_secret: "test-secret".to_string(),
         };
 
         let service = EncryptionService::new(&config);
         let plaintext = "Test message".as_bytes();
-        let shared_secret = vec![1, 2, 3, 4, 5]; // Dummy secret for testing
-        let encrypted = service.encrypt(plaintext, &shared_secret)?;
+        let key = SecretKey::new([7u8; KEY_LEN]); // Dummy key for testing
+        let encrypted = service.encrypt(plaintext, &key)?;
 
         assert!(encrypted.len() >= 12 + plaintext.len()); // Nonce + ciphertext
         Ok(())
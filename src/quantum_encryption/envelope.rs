@@ -0,0 +1,333 @@
+// src/quantum_encryption/envelope.rs
+use thiserror::Error;
+
+use crate::quantum_encryption::kem::{kem_for_id, Kem};
+
+/// Marks a ciphertext as a versioned envelope rather than the legacy `nonce || body` layout
+pub const ENVELOPE_MAGIC: &[u8; 4] = b"QENV";
+
+/// Current envelope version
+pub const ENVELOPE_VERSION: u8 = 1;
+
+/// AES-GCM nonce length
+pub const NONCE_LEN: usize = 12;
+
+/// AES-GCM authentication tag length; every body is at least this long
+pub const TAG_LEN: usize = 16;
+
+/// Upper bound for the KEM ciphertext, well above the largest supported suite
+const MAX_KEM_CIPHERTEXT_LEN: usize = 4096;
+
+/// KEM identifier for bodies whose content key is wrapped per recipient instead
+const NO_KEM_ID: u16 = 0;
+
+/// Upper bound for caller-supplied associated data
+const MAX_AAD_LEN: usize = 64 * 1024;
+
+/// Symmetric cipher used for the body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeadAlgorithm {
+    Aes256Gcm,
+}
+
+impl AeadAlgorithm {
+    pub fn id(self) -> u8 {
+        match self {
+            AeadAlgorithm::Aes256Gcm => 1,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(AeadAlgorithm::Aes256Gcm),
+            _ => None,
+        }
+    }
+}
+
+/// How the body key was derived from the KEM shared secret
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KdfAlgorithm {
+    /// Bare SHA3-256 of the shared secret, as used before context binding
+    LegacySha3,
+    /// HKDF-SHA3-256 bound to the email context (see the `kdf` module)
+    HkdfSha3,
+}
+
+impl KdfAlgorithm {
+    pub fn id(self) -> u8 {
+        match self {
+            KdfAlgorithm::LegacySha3 => 0,
+            KdfAlgorithm::HkdfSha3 => 1,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(KdfAlgorithm::LegacySha3),
+            1 => Some(KdfAlgorithm::HkdfSha3),
+            _ => None,
+        }
+    }
+}
+
+/// Reasons an envelope is rejected; every check is strict
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum EnvelopeError {
+    #[error("Not a ciphertext envelope: missing magic bytes")]
+    BadMagic,
+
+    #[error("Unsupported envelope version: {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("Unknown KEM identifier: {0:#06x}")]
+    UnknownKem(u16),
+
+    #[error("Unknown AEAD identifier: {0}")]
+    UnknownAead(u8),
+
+    #[error("Unknown KDF identifier: {0}")]
+    UnknownKdf(u8),
+
+    #[error("Envelope truncated while reading {0}")]
+    Truncated(&'static str),
+
+    #[error("Envelope field {field} has invalid length {len}")]
+    InvalidLength { field: &'static str, len: usize },
+}
+
+/// A self-describing encrypted email body
+///
+/// Binary layout (integers big-endian):
+///
+/// `"QENV" | version u8 | kem_id u16 | aead_id u8 | kdf_id u8 |
+///  kem_ciphertext_len u32 | kem_ciphertext | nonce_len u8 | nonce |
+///  aad_len u32 | aad | body (AEAD ciphertext + tag, to the end)`
+///
+/// Everything before the body is the header, which is also passed to the AEAD as
+/// associated data, so no algorithm identifier or length can be altered undetected.
+///
+/// Mail for several recipients carries no KEM ciphertext (`kem_id` 0, empty ciphertext):
+/// its body key comes from a random content key wrapped separately for each recipient.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Envelope {
+    pub version: u8,
+    pub kem: Option<&'static dyn Kem>,
+    pub aead: AeadAlgorithm,
+    pub kdf: KdfAlgorithm,
+    pub kem_ciphertext: Vec<u8>,
+    pub nonce: [u8; NONCE_LEN],
+    /// Caller-supplied associated data, authenticated but not encrypted
+    pub aad: Vec<u8>,
+    pub body: Vec<u8>,
+}
+
+impl std::fmt::Debug for Envelope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Envelope")
+            .field("version", &self.version)
+            .field("kem", &self.kem.map(|kem| kem.suite()))
+            .field("aead", &self.aead)
+            .field("kdf", &self.kdf)
+            .field("kem_ciphertext_len", &self.kem_ciphertext.len())
+            .field("aad_len", &self.aad.len())
+            .field("body_len", &self.body.len())
+            .finish()
+    }
+}
+
+impl Envelope {
+    /// Returns true if the bytes claim to be an envelope; `parse` decides whether they are valid
+    pub fn is_envelope(bytes: &[u8]) -> bool {
+        bytes.starts_with(ENVELOPE_MAGIC)
+    }
+
+    /// Serializes the header: every field except the body
+    pub fn header_bytes(&self) -> Vec<u8> {
+        let mut header = Vec::with_capacity(
+            ENVELOPE_MAGIC.len() + 14 + self.kem_ciphertext.len() + NONCE_LEN + self.aad.len(),
+        );
+        header.extend_from_slice(ENVELOPE_MAGIC);
+        header.push(self.version);
+        header.extend_from_slice(&self.kem.map_or(NO_KEM_ID, |kem| kem.id()).to_be_bytes());
+        header.push(self.aead.id());
+        header.push(self.kdf.id());
+        header.extend_from_slice(&(self.kem_ciphertext.len() as u32).to_be_bytes());
+        header.extend_from_slice(&self.kem_ciphertext);
+        header.push(NONCE_LEN as u8);
+        header.extend_from_slice(&self.nonce);
+        header.extend_from_slice(&(self.aad.len() as u32).to_be_bytes());
+        header.extend_from_slice(&self.aad);
+        header
+    }
+
+    /// Serializes the whole envelope
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = self.header_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    /// Parses and validates an envelope
+    pub fn parse(bytes: &[u8]) -> Result<Self, EnvelopeError> {
+        let mut reader = Reader { bytes, offset: 0 };
+
+        if reader.take(ENVELOPE_MAGIC.len(), "magic")? != ENVELOPE_MAGIC {
+            return Err(EnvelopeError::BadMagic);
+        }
+        let version = reader.u8("version")?;
+        if version != ENVELOPE_VERSION {
+            return Err(EnvelopeError::UnsupportedVersion(version));
+        }
+
+        let kem_id = reader.u16("KEM identifier")?;
+        let kem = match kem_id {
+            NO_KEM_ID => None,
+            id => Some(kem_for_id(id).ok_or(EnvelopeError::UnknownKem(id))?),
+        };
+        let aead_id = reader.u8("AEAD identifier")?;
+        let aead = AeadAlgorithm::from_id(aead_id).ok_or(EnvelopeError::UnknownAead(aead_id))?;
+        let kdf_id = reader.u8("KDF identifier")?;
+        let kdf = KdfAlgorithm::from_id(kdf_id).ok_or(EnvelopeError::UnknownKdf(kdf_id))?;
+
+        let kem_ciphertext_len = reader.u32("KEM ciphertext length")? as usize;
+        // A KEM ciphertext is present exactly when a KEM is named
+        if (kem_ciphertext_len == 0) == kem.is_some() || kem_ciphertext_len > MAX_KEM_CIPHERTEXT_LEN {
+            return Err(EnvelopeError::InvalidLength { field: "KEM ciphertext", len: kem_ciphertext_len });
+        }
+        let kem_ciphertext = reader.take(kem_ciphertext_len, "KEM ciphertext")?.to_vec();
+
+        let nonce_len = reader.u8("nonce length")? as usize;
+        if nonce_len != NONCE_LEN {
+            return Err(EnvelopeError::InvalidLength { field: "nonce", len: nonce_len });
+        }
+        let nonce = reader.take(NONCE_LEN, "nonce")?.try_into().expect("nonce length checked");
+
+        let aad_len = reader.u32("AAD length")? as usize;
+        if aad_len > MAX_AAD_LEN {
+            return Err(EnvelopeError::InvalidLength { field: "AAD", len: aad_len });
+        }
+        let aad = reader.take(aad_len, "AAD")?.to_vec();
+
+        let body = reader.rest();
+        if body.len() < TAG_LEN {
+            return Err(EnvelopeError::InvalidLength { field: "body", len: body.len() });
+        }
+
+        Ok(Self {
+            version,
+            kem,
+            aead,
+            kdf,
+            kem_ciphertext,
+            nonce,
+            aad,
+            body: body.to_vec(),
+        })
+    }
+}
+
+/// Bounds-checked cursor over the envelope bytes
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize, field: &'static str) -> Result<&'a [u8], EnvelopeError> {
+        let end = self.offset.checked_add(len).filter(|end| *end <= self.bytes.len())
+            .ok_or(EnvelopeError::Truncated(field))?;
+        let slice = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self, field: &'static str) -> Result<u8, EnvelopeError> {
+        Ok(self.take(1, field)?[0])
+    }
+
+    fn u16(&mut self, field: &'static str) -> Result<u16, EnvelopeError> {
+        Ok(u16::from_be_bytes(self.take(2, field)?.try_into().expect("length checked")))
+    }
+
+    fn u32(&mut self, field: &'static str) -> Result<u32, EnvelopeError> {
+        Ok(u32::from_be_bytes(self.take(4, field)?.try_into().expect("length checked")))
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.bytes[self.offset..];
+        self.offset = self.bytes.len();
+        rest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quantum_encryption::kem::Kyber768;
+
+    fn envelope() -> Envelope {
+        Envelope {
+            version: ENVELOPE_VERSION,
+            kem: Some(&Kyber768),
+            aead: AeadAlgorithm::Aes256Gcm,
+            kdf: KdfAlgorithm::HkdfSha3,
+            kem_ciphertext: vec![1; 1088],
+            nonce: [2; NONCE_LEN],
+            aad: b"metadata".to_vec(),
+            body: vec![3; 40],
+        }
+    }
+
+    #[test]
+    fn test_serialize_parse_roundtrip() {
+        let envelope = envelope();
+        let bytes = envelope.serialize();
+        assert!(Envelope::is_envelope(&bytes));
+        assert!(bytes.starts_with(&envelope.header_bytes()));
+
+        let parsed = Envelope::parse(&bytes).unwrap();
+        assert_eq!(parsed, envelope);
+        assert_eq!(parsed.kem.map(|kem| kem.suite()), Some("kyber768"));
+
+        let shared = Envelope { kem: None, kem_ciphertext: Vec::new(), ..envelope };
+        assert_eq!(Envelope::parse(&shared.serialize()).unwrap(), shared);
+    }
+
+    #[test]
+    fn test_strict_validation() {
+        let bytes = envelope().serialize();
+
+        assert_eq!(Envelope::parse(b"nonce and ciphertext").unwrap_err(), EnvelopeError::BadMagic);
+
+        let mut version = bytes.clone();
+        version[4] = 9;
+        assert_eq!(Envelope::parse(&version).unwrap_err(), EnvelopeError::UnsupportedVersion(9));
+
+        let mut kem = bytes.clone();
+        kem[5..7].copy_from_slice(&0x7777u16.to_be_bytes());
+        assert_eq!(Envelope::parse(&kem).unwrap_err(), EnvelopeError::UnknownKem(0x7777));
+
+        let mut aead = bytes.clone();
+        aead[7] = 42;
+        assert_eq!(Envelope::parse(&aead).unwrap_err(), EnvelopeError::UnknownAead(42));
+
+        let mut kdf = bytes.clone();
+        kdf[8] = 42;
+        assert_eq!(Envelope::parse(&kdf).unwrap_err(), EnvelopeError::UnknownKdf(42));
+
+        let mut huge = bytes.clone();
+        huge[9..13].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(Envelope::parse(&huge), Err(EnvelopeError::InvalidLength { field: "KEM ciphertext", .. })));
+
+        let mut missing_kem = bytes.clone();
+        missing_kem[5..7].copy_from_slice(&0u16.to_be_bytes());
+        assert!(matches!(Envelope::parse(&missing_kem), Err(EnvelopeError::InvalidLength { field: "KEM ciphertext", .. })));
+
+        assert_eq!(Envelope::parse(&bytes[..20]).unwrap_err(), EnvelopeError::Truncated("KEM ciphertext"));
+        assert!(matches!(
+            Envelope::parse(&bytes[..bytes.len() - 30]),
+            Err(EnvelopeError::InvalidLength { field: "body", .. })
+        ));
+    }
+}
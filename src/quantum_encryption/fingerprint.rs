@@ -0,0 +1,73 @@
+// src/quantum_encryption/fingerprint.rs
+use std::fmt;
+
+use sha3::{Digest, Sha3_256};
+
+/// Domain separation for key fingerprints
+const FINGERPRINT_LABEL: &[u8] = b"quantum-email/key-fingerprint/v1";
+
+/// Bytes of the digest shown to users: 160 bits in ten groups of four hex digits
+const DISPLAYED_BYTES: usize = 20;
+
+/// Human-comparable fingerprint of a public key
+///
+/// The SHA3-256 digest covers the suite name as well as the key bytes, so the same bytes
+/// published under another algorithm get a different fingerprint. It is displayed as
+/// `1A2B 3C4D ...` for reading aloud or comparing side by side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    pub fn of(algorithm: &str, public_key: &[u8]) -> Self {
+        let mut hasher = Sha3_256::new();
+        hasher.update(FINGERPRINT_LABEL);
+        hasher.update((algorithm.len() as u32).to_be_bytes());
+        hasher.update(algorithm.as_bytes());
+        hasher.update(public_key);
+        Self(hasher.finalize().into())
+    }
+
+    /// Checks a fingerprint typed or pasted by a user, ignoring case, spaces and separators
+    pub fn matches(&self, candidate: &str) -> bool {
+        let normalized: String = candidate
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+        normalized == self.to_string().replace(' ', "")
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, pair) in self.0[..DISPLAYED_BYTES].chunks(2).enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{:02X}{:02X}", pair[0], pair[1])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_format_and_matching() {
+        let fingerprint = Fingerprint::of("kyber1024", b"public key");
+        let shown = fingerprint.to_string();
+        assert_eq!(shown.len(), 10 * 4 + 9);
+        assert_eq!(shown.split(' ').count(), 10);
+        assert_eq!(fingerprint, Fingerprint::of("kyber1024", b"public key"));
+
+        assert!(fingerprint.matches(&shown));
+        assert!(fingerprint.matches(&shown.to_lowercase().replace(' ', ":")));
+        assert!(!fingerprint.matches(&shown[..shown.len() - 1]));
+
+        // The suite is part of the fingerprint
+        assert_ne!(fingerprint, Fingerprint::of("kyber768", b"public key"));
+        assert_ne!(fingerprint, Fingerprint::of("kyber1024", b"other key"));
+    }
+}
@@ -0,0 +1,176 @@
+// src/quantum_encryption/hybrid_kem.rs
+use anyhow::Result;
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use sha3::Sha3_256;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+use crate::quantum_encryption::kem::{Kem, Kyber1024, Kyber768};
+use crate::quantum_encryption::secret::SecretBytes;
+
+/// Length of X25519 public keys, private keys and shared secrets
+const X25519_LEN: usize = 32;
+
+/// HKDF info prefix for the combined shared secret
+const COMBINER_LABEL: &[u8] = b"quantum-email-hybrid-kem-v1";
+
+/// Hybrid KEM combining classical X25519 with a Kyber parameter set
+///
+/// The session stays secure as long as either X25519 or Kyber holds. Serialization is
+/// fixed-length concatenation with the X25519 part first, so any implementation that
+/// knows the suite can split the fields without framing:
+///
+/// - public key: `x25519_public (32) | kyber_public`
+/// - private key: `x25519_private (32) | kyber_private`
+/// - ciphertext: `x25519_ephemeral_public (32) | kyber_ciphertext`
+///
+/// The shared secret is `HKDF-SHA3-256(ikm = ss_x25519 | ss_kyber, info = label | suite |
+/// x25519_ephemeral_public | x25519_public)`, 32 bytes. Binding the X25519 public values
+/// keeps the classical half from being replayed against another recipient.
+pub struct HybridKem {
+    suite: &'static str,
+    id: u16,
+    kyber: &'static dyn Kem,
+}
+
+/// X25519 combined with Kyber-768
+pub static X25519_KYBER768: HybridKem = HybridKem { suite: "x25519-kyber768", id: 0x0102, kyber: &Kyber768 };
+
+/// X25519 combined with Kyber-1024
+pub static X25519_KYBER1024: HybridKem = HybridKem { suite: "x25519-kyber1024", id: 0x0103, kyber: &Kyber1024 };
+
+impl HybridKem {
+    fn split<'a>(bytes: &'a [u8], what: &str) -> Result<([u8; X25519_LEN], &'a [u8])> {
+        if bytes.len() <= X25519_LEN {
+            return Err(anyhow::anyhow!("Invalid hybrid {} format: too short", what));
+        }
+        let (classical, kyber) = bytes.split_at(X25519_LEN);
+        Ok((classical.try_into()?, kyber))
+    }
+
+    fn combine(
+        &self,
+        classical_secret: &[u8],
+        kyber_secret: &[u8],
+        ephemeral_public: &[u8],
+        recipient_public: &[u8],
+    ) -> Result<SecretBytes> {
+        let mut ikm = SecretBytes::new(Vec::with_capacity(classical_secret.len() + kyber_secret.len()));
+        ikm.expose_secret_mut().extend_from_slice(classical_secret);
+        ikm.expose_secret_mut().extend_from_slice(kyber_secret);
+
+        let mut info = Vec::with_capacity(COMBINER_LABEL.len() + self.suite.len() + 2 * X25519_LEN);
+        info.extend_from_slice(COMBINER_LABEL);
+        info.extend_from_slice(self.suite.as_bytes());
+        info.extend_from_slice(ephemeral_public);
+        info.extend_from_slice(recipient_public);
+
+        let mut shared_secret = SecretBytes::new(vec![0u8; 32]);
+        Hkdf::<Sha3_256>::new(None, ikm.expose_secret())
+            .expand(&info, shared_secret.expose_secret_mut())
+            .map_err(|e| anyhow::anyhow!("Failed to combine hybrid shared secrets: {}", e))?;
+        Ok(shared_secret)
+    }
+}
+
+impl Kem for HybridKem {
+    fn suite(&self) -> &'static str {
+        self.suite
+    }
+
+    fn id(&self) -> u16 {
+        self.id
+    }
+
+    fn generate_key_pair(&self) -> (Vec<u8>, SecretBytes) {
+        let classical_private = StaticSecret::random_from_rng(OsRng);
+        let classical_public = PublicKey::from(&classical_private);
+        let (kyber_public, kyber_private) = self.kyber.generate_key_pair();
+
+        let mut public_key = classical_public.as_bytes().to_vec();
+        public_key.extend_from_slice(&kyber_public);
+        let mut private_key = SecretBytes::new(classical_private.to_bytes().to_vec());
+        private_key.expose_secret_mut().extend_from_slice(kyber_private.expose_secret());
+        (public_key, private_key)
+    }
+
+    fn encapsulate(&self, public_key: &[u8]) -> Result<(Vec<u8>, SecretBytes)> {
+        let (classical_public, kyber_public) = Self::split(public_key, "public key")?;
+        let (kyber_ciphertext, kyber_secret) = self.kyber.encapsulate(kyber_public)?;
+
+        let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+        let ephemeral_public = PublicKey::from(&ephemeral);
+        let classical_secret = ephemeral.diffie_hellman(&PublicKey::from(classical_public));
+        if !classical_secret.was_contributory() {
+            return Err(anyhow::anyhow!("Invalid hybrid public key: low-order X25519 point"));
+        }
+
+        let shared_secret = self.combine(
+            classical_secret.as_bytes(),
+            kyber_secret.expose_secret(),
+            ephemeral_public.as_bytes(),
+            &classical_public,
+        )?;
+
+        let mut ciphertext = ephemeral_public.as_bytes().to_vec();
+        ciphertext.extend_from_slice(&kyber_ciphertext);
+        Ok((ciphertext, shared_secret))
+    }
+
+    fn decapsulate(&self, private_key: &[u8], ciphertext: &[u8]) -> Result<SecretBytes> {
+        let (classical_private, kyber_private) = Self::split(private_key, "private key")?;
+        let (ephemeral_public, kyber_ciphertext) = Self::split(ciphertext, "ciphertext")?;
+        let kyber_secret = self.kyber.decapsulate(kyber_private, kyber_ciphertext)?;
+
+        let classical_private = StaticSecret::from(classical_private);
+        let classical_public = PublicKey::from(&classical_private);
+        let classical_secret = classical_private.diffie_hellman(&PublicKey::from(ephemeral_public));
+        if !classical_secret.was_contributory() {
+            return Err(anyhow::anyhow!("Invalid hybrid ciphertext: low-order X25519 point"));
+        }
+
+        self.combine(
+            classical_secret.as_bytes(),
+            kyber_secret.expose_secret(),
+            &ephemeral_public,
+            classical_public.as_bytes(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hybrid_roundtrip() -> Result<()> {
+        for kem in [&X25519_KYBER768, &X25519_KYBER1024] {
+            let (public_key, private_key) = kem.generate_key_pair();
+            let (ciphertext, shared_secret) = kem.encapsulate(&public_key)?;
+            assert_eq!(shared_secret.expose_secret().len(), 32);
+            assert_eq!(kem.decapsulate(private_key.expose_secret(), &ciphertext)?, shared_secret);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_both_halves_are_bound() -> Result<()> {
+        let kem = &X25519_KYBER768;
+        let (public_key, private_key) = kem.generate_key_pair();
+        let (ciphertext, shared_secret) = kem.encapsulate(&public_key)?;
+
+        // Swapping in another ephemeral key changes the classical half
+        let (other_ciphertext, _) = kem.encapsulate(&public_key)?;
+        let mut mixed = other_ciphertext[..X25519_LEN].to_vec();
+        mixed.extend_from_slice(&ciphertext[X25519_LEN..]);
+        assert_ne!(kem.decapsulate(private_key.expose_secret(), &mixed)?, shared_secret);
+
+        // A low-order X25519 point is rejected outright
+        let mut low_order = vec![0u8; X25519_LEN];
+        low_order.extend_from_slice(&ciphertext[X25519_LEN..]);
+        assert!(kem.decapsulate(private_key.expose_secret(), &low_order).is_err());
+
+        assert!(kem.encapsulate(&public_key[..X25519_LEN]).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+// src/quantum_encryption/kdf.rs
+use anyhow::Result;
+use hkdf::Hkdf;
+use sha3::{Digest, Sha3_256};
+use uuid::Uuid;
+
+use crate::quantum_encryption::secret::SecretKey;
+
+/// HKDF salt separating email keys from every other use of a KEM shared secret
+const KDF_SALT: &[u8] = b"quantum-email-kdf-v1";
+
+/// Length of every derived key (AES-256)
+pub const KEY_LEN: usize = 32;
+
+/// Algorithm label for keys derived from a random content key shared by all recipients
+pub const SHARED_CONTENT_KEY: &str = "shared-content-key";
+
+/// The email a key is derived for; every field is bound into the HKDF info
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyContext<'a> {
+    pub email_id: Uuid,
+    pub sender_id: Uuid,
+    pub recipient_id: Uuid,
+    /// Canonical KEM suite the shared secret came from
+    pub algorithm: &'a str,
+}
+
+/// What a derived key will encrypt; each purpose gets an independent key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyPurpose {
+    Body,
+    Subject,
+    Attachment,
+    /// Wraps a random content key for one recipient of a multi-recipient email
+    KeyWrap,
+}
+
+impl KeyPurpose {
+    fn label(self) -> &'static [u8] {
+        match self {
+            KeyPurpose::Body => b"body",
+            KeyPurpose::Subject => b"subject",
+            KeyPurpose::Attachment => b"attachment",
+            KeyPurpose::KeyWrap => b"key-wrap",
+        }
+    }
+}
+
+/// The per-purpose keys derived from one encapsulation
+pub struct MessageKeys {
+    pub body: SecretKey,
+    pub subject: SecretKey,
+    pub attachment: SecretKey,
+}
+
+/// Derives the key for one purpose with HKDF-SHA3-256
+///
+/// The info string is the purpose label followed by the email ID, sender ID, recipient ID
+/// and algorithm, each length-prefixed, so a key never decrypts content that was moved to
+/// another email, re-addressed, or relabelled with a different suite.
+pub fn derive_key(shared_secret: &[u8], context: &KeyContext, purpose: KeyPurpose) -> Result<SecretKey> {
+    let fields: [&[u8]; 5] = [
+        purpose.label(),
+        context.email_id.as_bytes(),
+        context.sender_id.as_bytes(),
+        context.recipient_id.as_bytes(),
+        context.algorithm.as_bytes(),
+    ];
+    let mut info = Vec::with_capacity(fields.iter().map(|f| f.len() + 2).sum());
+    for field in fields {
+        info.extend_from_slice(&(field.len() as u16).to_be_bytes());
+        info.extend_from_slice(field);
+    }
+
+    let mut key = SecretKey::new([0u8; KEY_LEN]);
+    Hkdf::<Sha3_256>::new(Some(KDF_SALT), shared_secret)
+        .expand(&info, key.expose_secret_mut())
+        .map_err(|e| anyhow::anyhow!("Failed to derive {:?} key: {}", purpose, e))?;
+    Ok(key)
+}
+
+/// Derives the body, subject and attachment keys for an email from one shared secret
+pub fn derive_message_keys(shared_secret: &[u8], context: &KeyContext) -> Result<MessageKeys> {
+    Ok(MessageKeys {
+        body: derive_key(shared_secret, context, KeyPurpose::Body)?,
+        subject: derive_key(shared_secret, context, KeyPurpose::Subject)?,
+        attachment: derive_key(shared_secret, context, KeyPurpose::Attachment)?,
+    })
+}
+
+/// Key derivation used before context binding: a bare SHA3-256 of the shared secret
+///
+/// Only used to read mail stored before HKDF was introduced.
+pub fn legacy_key(shared_secret: &[u8]) -> SecretKey {
+    SecretKey::new(Sha3_256::digest(shared_secret).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> KeyContext<'static> {
+        KeyContext {
+            email_id: Uuid::new_v4(),
+            sender_id: Uuid::new_v4(),
+            recipient_id: Uuid::new_v4(),
+            algorithm: "kyber1024",
+        }
+    }
+
+    #[test]
+    fn test_keys_are_deterministic_and_separated() -> Result<()> {
+        let context = context();
+        let keys = derive_message_keys(b"shared secret", &context)?;
+        assert_eq!(keys.body, derive_key(b"shared secret", &context, KeyPurpose::Body)?);
+        assert_ne!(keys.body, keys.subject);
+        assert_ne!(keys.body, keys.attachment);
+        assert_ne!(keys.subject, keys.attachment);
+        assert_ne!(keys.body, legacy_key(b"shared secret"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_every_context_field_is_bound() -> Result<()> {
+        let context = context();
+        let body = derive_key(b"shared secret", &context, KeyPurpose::Body)?;
+
+        let variants = [
+            KeyContext { email_id: Uuid::new_v4(), ..context },
+            KeyContext { sender_id: Uuid::new_v4(), ..context },
+            KeyContext { recipient_id: Uuid::new_v4(), ..context },
+            KeyContext { algorithm: "kyber768", ..context },
+        ];
+        for variant in variants {
+            assert_ne!(derive_key(b"shared secret", &variant, KeyPurpose::Body)?, body);
+        }
+        assert_ne!(derive_key(b"other secret", &context, KeyPurpose::Body)?, body);
+        Ok(())
+    }
+}
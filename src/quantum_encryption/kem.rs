@@ -0,0 +1,180 @@
+// src/quantum_encryption/kem.rs
+use anyhow::Result;
+use pqcrypto_kyber::{kyber1024, kyber512, kyber768};
+use pqcrypto_traits::kem::{Ciphertext, PublicKey, SecretKey, SharedSecret};
+
+use crate::config::EncryptionConfig;
+use crate::quantum_encryption::hybrid_kem::{X25519_KYBER1024, X25519_KYBER768};
+use crate::quantum_encryption::secret::SecretBytes;
+
+/// Suite name recorded before algorithm agility; all such keys are Kyber-1024
+const LEGACY_KYBER_SUITE: &str = "kyber";
+
+/// Algorithm family selecting the X25519 + Kyber hybrid, sized by `key_size`
+const HYBRID_FAMILY: &str = "x25519kyber";
+
+/// A key encapsulation mechanism identified by the suite name stored with keys and emails
+pub trait Kem: Send + Sync {
+    /// Exact suite name, e.g. `kyber768`, recorded as `KeyPair.algorithm` and `Email.encryption_method`
+    fn suite(&self) -> &'static str;
+
+    /// Stable numeric identifier written into ciphertext envelopes
+    fn id(&self) -> u16;
+
+    /// Generates a key pair, returning (public_key, private_key)
+    fn generate_key_pair(&self) -> (Vec<u8>, SecretBytes);
+
+    /// Encapsulates a fresh shared secret to a public key, returning (ciphertext, shared_secret)
+    fn encapsulate(&self, public_key: &[u8]) -> Result<(Vec<u8>, SecretBytes)>;
+
+    /// Recovers the shared secret from a ciphertext with the matching private key
+    fn decapsulate(&self, private_key: &[u8], ciphertext: &[u8]) -> Result<SecretBytes>;
+}
+
+/// KEMs are equal when they are the same suite
+impl PartialEq for dyn Kem {
+    fn eq(&self, other: &Self) -> bool {
+        self.id() == other.id()
+    }
+}
+
+impl Eq for dyn Kem {}
+
+/// Implements `Kem` for one of the pqcrypto Kyber parameter sets
+macro_rules! kyber_kem {
+    ($name:ident, $module:ident, $suite:literal, $id:literal) => {
+        #[doc = concat!("Kyber KEM with the `", $suite, "` parameter set")]
+        pub struct $name;
+
+        impl Kem for $name {
+            fn suite(&self) -> &'static str {
+                $suite
+            }
+
+            fn id(&self) -> u16 {
+                $id
+            }
+
+            fn generate_key_pair(&self) -> (Vec<u8>, SecretBytes) {
+                let (public_key, private_key) = $module::keypair();
+                (public_key.as_bytes().to_vec(), private_key.as_bytes().to_vec().into())
+            }
+
+            fn encapsulate(&self, public_key: &[u8]) -> Result<(Vec<u8>, SecretBytes)> {
+                let public_key = $module::PublicKey::from_bytes(public_key).map_err(|_| {
+                    anyhow::anyhow!("Invalid {} public key format: incorrect length or data", $suite)
+                })?;
+                let (shared_secret, ciphertext) = $module::encapsulate(&public_key);
+                Ok((ciphertext.as_bytes().to_vec(), shared_secret.as_bytes().to_vec().into()))
+            }
+
+            fn decapsulate(&self, private_key: &[u8], ciphertext: &[u8]) -> Result<SecretBytes> {
+                let private_key = $module::SecretKey::from_bytes(private_key).map_err(|_| {
+                    anyhow::anyhow!("Invalid {} private key format: incorrect length or data", $suite)
+                })?;
+                let ciphertext = $module::Ciphertext::from_bytes(ciphertext).map_err(|_| {
+                    anyhow::anyhow!("Invalid {} ciphertext format: incorrect length or data", $suite)
+                })?;
+                Ok($module::decapsulate(&ciphertext, &private_key).as_bytes().to_vec().into())
+            }
+        }
+    };
+}
+
+kyber_kem!(Kyber512, kyber512, "kyber512", 0x0001);
+kyber_kem!(Kyber768, kyber768, "kyber768", 0x0002);
+kyber_kem!(Kyber1024, kyber1024, "kyber1024", 0x0003);
+
+/// Every supported KEM, for lookups by suite name or envelope ID
+const ALL_KEMS: [&dyn Kem; 5] = [&Kyber512, &Kyber768, &Kyber1024, &X25519_KYBER768, &X25519_KYBER1024];
+
+/// Looks up the KEM for a recorded suite name
+///
+/// Keys and emails written before suites were recorded carry the bare `kyber` label and
+/// always used Kyber-1024, so that label keeps resolving to it.
+pub fn kem_for_suite(suite: &str) -> Result<&'static dyn Kem> {
+    match normalize(suite).as_str() {
+        "kyber512" => Ok(&Kyber512),
+        "kyber768" => Ok(&Kyber768),
+        "kyber1024" | LEGACY_KYBER_SUITE => Ok(&Kyber1024),
+        "x25519kyber768" => Ok(&X25519_KYBER768),
+        "x25519kyber1024" => Ok(&X25519_KYBER1024),
+        _ => Err(anyhow::anyhow!("Unsupported encryption method: {}", suite)),
+    }
+}
+
+/// Looks up the KEM for an envelope identifier
+pub fn kem_for_id(id: u16) -> Option<&'static dyn Kem> {
+    ALL_KEMS.into_iter().find(|kem| kem.id() == id)
+}
+
+/// Selects the KEM for new keys from `EncryptionConfig.algorithm` and `key_size`
+///
+/// `algorithm` may name a full suite (`kyber768`, `x25519-kyber768`) or just the family
+/// (`kyber`, `x25519-kyber`), in which case `key_size` picks the Kyber parameter set.
+pub fn kem_from_config(config: &EncryptionConfig) -> Result<&'static dyn Kem> {
+    match (normalize(&config.algorithm).as_str(), config.key_size) {
+        (LEGACY_KYBER_SUITE, 512) => Ok(&Kyber512),
+        (LEGACY_KYBER_SUITE, 768) => Ok(&Kyber768),
+        (LEGACY_KYBER_SUITE, 1024) => Ok(&Kyber1024),
+        (HYBRID_FAMILY, 768) => Ok(&X25519_KYBER768),
+        (HYBRID_FAMILY, 1024) => Ok(&X25519_KYBER1024),
+        (LEGACY_KYBER_SUITE, other) | (HYBRID_FAMILY, other) => Err(anyhow::anyhow!(
+            "Unsupported key size {} for {}", other, config.algorithm
+        )),
+        _ => kem_for_suite(&config.algorithm),
+    }
+}
+
+/// Lower-cases a suite name and drops separators so `Kyber-768` and `kyber768` match
+fn normalize(suite: &str) -> String {
+    suite.to_ascii_lowercase().replace(['-', '_'], "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(algorithm: &str, key_size: usize) -> EncryptionConfig {
+        EncryptionConfig {
+            key_rotation_days: 30,
+            algorithm: algorithm.to_string(),
+            key_size,
+            key_encryption_secret: "test-secret".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_every_suite_roundtrips() -> Result<()> {
+        for kem in ALL_KEMS {
+            let (public_key, private_key) = kem.generate_key_pair();
+            let (ciphertext, shared_secret) = kem.encapsulate(&public_key)?;
+            assert_eq!(kem.decapsulate(private_key.expose_secret(), &ciphertext)?, shared_secret);
+            assert_eq!(kem_for_suite(kem.suite())?.suite(), kem.suite());
+            assert_eq!(kem_for_id(kem.id()).map(|found| found.suite()), Some(kem.suite()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_suites_do_not_mix() {
+        let (public_key, _) = Kyber512.generate_key_pair();
+        assert!(Kyber1024.encapsulate(&public_key).is_err());
+    }
+
+    #[test]
+    fn test_selection_from_config() -> Result<()> {
+        assert_eq!(kem_from_config(&config("kyber", 512))?.suite(), "kyber512");
+        assert_eq!(kem_from_config(&config("kyber", 768))?.suite(), "kyber768");
+        assert_eq!(kem_from_config(&config("kyber", 1024))?.suite(), "kyber1024");
+        assert_eq!(kem_from_config(&config("Kyber-768", 1024))?.suite(), "kyber768");
+        assert!(kem_from_config(&config("kyber", 2048)).is_err());
+        assert!(kem_from_config(&config("rsa", 1024)).is_err());
+        assert_eq!(kem_from_config(&config("x25519-kyber", 768))?.suite(), "x25519-kyber768");
+        assert_eq!(kem_from_config(&config("x25519-kyber1024", 512))?.suite(), "x25519-kyber1024");
+        assert!(kem_from_config(&config("x25519-kyber", 512)).is_err());
+
+        assert_eq!(kem_for_suite("kyber")?.suite(), "kyber1024");
+        Ok(())
+    }
+}
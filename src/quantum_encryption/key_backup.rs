@@ -0,0 +1,348 @@
+// src/quantum_encryption/key_backup.rs
+use std::collections::HashSet;
+
+use anyhow::Result;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use crate::database::key_store::KeyStore;
+use crate::database::models::QuantumKey;
+use crate::quantum_encryption::kem::kem_for_suite;
+use crate::quantum_encryption::key_wrapping::KeyWrapper;
+use crate::quantum_encryption::secret::{serialize_exposed, Secret, SecretBytes};
+
+/// Marks a key backup archive
+pub const BACKUP_MAGIC: &[u8; 4] = b"QKBK";
+
+/// Current version of the backup archive format
+pub const BACKUP_VERSION: u8 = 1;
+
+/// Shortest passphrase a backup may be sealed with
+pub const MIN_PASSPHRASE_LEN: usize = 12;
+
+/// Length of the header preceding the sealed payload: magic, version, archive ID and user ID
+const HEADER_LEN: usize = 4 + 1 + 16 + 16;
+
+/// All of a user's quantum keys, active and historical, with their private halves
+///
+/// A sealed archive is laid out as
+///
+/// `"QKBK" | version u8 | archive_id (16) | user_id (16) | wrapped payload`
+///
+/// where the payload is the JSON encoded key list wrapped by `KeyWrapper` under the
+/// passphrase, with the archive and user IDs as its key and user IDs. AES-GCM therefore
+/// authenticates the payload and both IDs, and every key is checked against its suite
+/// when the archive is opened.
+#[derive(Debug, Clone)]
+pub struct KeyBackup {
+    pub user_id: Uuid,
+    pub exported_at: OffsetDateTime,
+    pub keys: Vec<QuantumKey>,
+}
+
+/// Outcome of merging a backup into the key store
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RestoreReport {
+    /// Keys that were missing and have been added
+    pub restored: Vec<Uuid>,
+    /// Keys that are already stored and were left untouched
+    pub skipped: Vec<Uuid>,
+    /// The archived active key, if it became the user's active key again
+    pub activated: Option<Uuid>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BackupPayload {
+    exported_at: OffsetDateTime,
+    keys: Vec<BackupKey>,
+}
+
+/// One archived key; the base64 private key is zeroed when the payload is dropped
+#[derive(Serialize, Deserialize)]
+struct BackupKey {
+    key_id: Uuid,
+    user_id: Uuid,
+    algorithm: String,
+    public_key: String,
+    #[serde(serialize_with = "serialize_exposed")]
+    private_key: Secret<String>,
+    created_at: OffsetDateTime,
+    expires_at: OffsetDateTime,
+    is_active: bool,
+}
+
+impl KeyBackup {
+    /// Collects the user's long-term keys, private keys unwrapped
+    ///
+    /// One-time prekeys are left out, so a restore cannot bring back a prekey deleted for forward secrecy.
+    pub async fn export(key_store: &dyn KeyStore, user_id: Uuid) -> Result<Self> {
+        let keys = key_store.long_term_keys(user_id).await?;
+        debug!("Exporting {} quantum keys of user {}", keys.len(), user_id);
+        Ok(Self { user_id, exported_at: OffsetDateTime::now_utc(), keys })
+    }
+
+    /// Seals the backup under a key derived from the passphrase
+    pub fn seal(&self, passphrase: &str) -> Result<Vec<u8>> {
+        if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+            return Err(anyhow::anyhow!("Backup passphrase must be at least {} characters", MIN_PASSPHRASE_LEN));
+        }
+        self.seal_with(&KeyWrapper::new(passphrase.as_bytes()))
+    }
+
+    fn seal_with(&self, wrapper: &KeyWrapper) -> Result<Vec<u8>> {
+        self.validate()?;
+        let payload = BackupPayload {
+            exported_at: self.exported_at,
+            keys: self.keys.iter().map(|key| BackupKey {
+                key_id: key.key_id,
+                user_id: key.user_id,
+                algorithm: key.encryption_method.clone(),
+                public_key: BASE64.encode(&key.public_key),
+                private_key: Secret::new(BASE64.encode(key.private_key.expose_secret())),
+                created_at: key.key_generation_timestamp,
+                expires_at: key.expiration_timestamp,
+                is_active: key.is_active,
+            }).collect(),
+        };
+
+        let archive_id = Uuid::new_v4();
+        let mut archive = Vec::with_capacity(HEADER_LEN);
+        archive.extend_from_slice(BACKUP_MAGIC);
+        archive.push(BACKUP_VERSION);
+        archive.extend_from_slice(archive_id.as_bytes());
+        archive.extend_from_slice(self.user_id.as_bytes());
+        let payload = SecretBytes::new(serde_json::to_vec(&payload)?);
+        archive.extend(wrapper.wrap_private_key(archive_id, self.user_id, payload.expose_secret())?);
+        Ok(archive)
+    }
+
+    /// Reads the user an archive claims to belong to, without deriving any key
+    ///
+    /// The claim is only authenticated by `open`, but checking it first spares the
+    /// passphrase derivation for archives of other users.
+    pub fn owner(archive: &[u8]) -> Result<Uuid> {
+        Self::header(archive).map(|(_, user_id)| user_id)
+    }
+
+    /// Opens and validates an archive sealed with `seal`
+    ///
+    /// The passphrase derivation is deliberately slow, so call this off the async runtime.
+    pub fn open(archive: &[u8], passphrase: &str) -> Result<Self> {
+        let (archive_id, user_id) = Self::header(archive)?;
+
+        let payload = KeyWrapper::new(passphrase.as_bytes())
+            .unwrap_private_key(archive_id, user_id, &archive[HEADER_LEN..])
+            .map_err(|_| anyhow::anyhow!("Key backup could not be opened: wrong passphrase or corrupted archive"))?;
+        let payload: BackupPayload = serde_json::from_slice(payload.expose_secret())?;
+
+        let keys = payload.keys.into_iter()
+            .map(|key| Ok(QuantumKey {
+                key_id: key.key_id,
+                user_id: key.user_id,
+                public_key: BASE64.decode(key.public_key)?,
+                private_key: SecretBytes::new(BASE64.decode(key.private_key.expose_secret())?),
+                encryption_method: key.algorithm,
+                key_generation_timestamp: key.created_at,
+                expiration_timestamp: key.expires_at,
+                is_active: key.is_active,
+            }))
+            .collect::<Result<Vec<_>>>()?;
+        let backup = Self { user_id, exported_at: payload.exported_at, keys };
+        backup.validate()?;
+        Ok(backup)
+    }
+
+    /// Checks the magic and version and returns the archive and user IDs
+    fn header(archive: &[u8]) -> Result<(Uuid, Uuid)> {
+        if archive.len() < HEADER_LEN || !archive.starts_with(BACKUP_MAGIC) {
+            return Err(anyhow::anyhow!("Not a key backup archive"));
+        }
+        if archive[4] != BACKUP_VERSION {
+            return Err(anyhow::anyhow!("Unsupported key backup version: {}", archive[4]));
+        }
+        Ok((Uuid::from_slice(&archive[5..21])?, Uuid::from_slice(&archive[21..HEADER_LEN])?))
+    }
+
+    /// Checks that every key belongs to the user and that its private half opens its public half
+    pub fn validate(&self) -> Result<()> {
+        let mut seen = HashSet::new();
+        for key in &self.keys {
+            if key.user_id != self.user_id {
+                return Err(anyhow::anyhow!("Key {} belongs to another user", key.key_id));
+            }
+            if !seen.insert(key.key_id) {
+                return Err(anyhow::anyhow!("Key {} is listed more than once", key.key_id));
+            }
+            let kem = kem_for_suite(&key.encryption_method)?;
+            let (ciphertext, shared_secret) = kem.encapsulate(&key.public_key)
+                .map_err(|e| anyhow::anyhow!("Key {} has an invalid public key: {}", key.key_id, e))?;
+            let matches = kem.decapsulate(key.private_key.expose_secret(), &ciphertext)
+                .is_ok_and(|decapsulated| decapsulated == shared_secret);
+            if !matches {
+                return Err(anyhow::anyhow!("Private key {} does not match its public key", key.key_id));
+            }
+        }
+        if self.keys.iter().filter(|key| key.is_active).count() > 1 {
+            return Err(anyhow::anyhow!("Key backup has more than one active key"));
+        }
+        Ok(())
+    }
+
+    /// Adds the archived keys the store is missing, never replacing a stored key
+    ///
+    /// Keys already in the store are skipped. Restored keys are inactive, except that the
+    /// archived active key becomes active again when it has not expired and the user has no
+    /// active key generated after it.
+    pub async fn restore(&self, key_store: &dyn KeyStore) -> Result<RestoreReport> {
+        self.validate()?;
+        let stored: HashSet<Uuid> = key_store.key_history(self.user_id).await?
+            .into_iter()
+            .map(|key| key.key_id)
+            .collect();
+        let current = key_store.active_key(self.user_id).await?;
+
+        let mut report = RestoreReport::default();
+        let mut keys: Vec<&QuantumKey> = self.keys.iter().collect();
+        // The key to activate goes last, so restoring older keys cannot deactivate it
+        keys.sort_by_key(|key| key.is_active);
+        for key in keys {
+            if stored.contains(&key.key_id) {
+                report.skipped.push(key.key_id);
+                continue;
+            }
+            let activate = key.is_active
+                && key.expiration_timestamp > OffsetDateTime::now_utc()
+                && current.as_ref().is_none_or(|current| current.key_generation_timestamp < key.key_generation_timestamp);
+            key_store.insert(&QuantumKey { is_active: activate, ..key.clone() }).await?;
+            if activate {
+                report.activated = Some(key.key_id);
+            }
+            report.restored.push(key.key_id);
+        }
+
+        info!(
+            "Restored {} quantum keys of user {} from backup, {} already stored",
+            report.restored.len(), self.user_id, report.skipped.len()
+        );
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EncryptionConfig;
+    use crate::database::key_store::InMemoryKeyStore;
+    use crate::database::models::OneTimePrekey;
+    use crate::quantum_encryption::key_exchange::QuantumKeyExchange;
+    use crate::quantum_encryption::key_wrapping::KdfParams;
+
+    const PASSPHRASE: &str = "correct horse battery staple";
+
+    fn test_wrapper() -> KeyWrapper {
+        KeyWrapper::with_params(PASSPHRASE.as_bytes(), KdfParams { memory_kib: 64, iterations: 1, parallelism: 1 })
+    }
+
+    fn key_exchange() -> QuantumKeyExchange {
+        QuantumKeyExchange::new(&EncryptionConfig {
+            key_rotation_days: 30,
+            algorithm: "kyber".to_string(),
+            key_size: 512,
+            key_encryption_secret: "test-secret".to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_backup_roundtrip_and_merge() -> Result<()> {
+        let user_id = Uuid::new_v4();
+        let key_exchange = key_exchange();
+        let source = InMemoryKeyStore::new();
+        let mut old = key_exchange.generate_key_pair()?;
+        old.created_at -= time::Duration::days(1);
+        let active = key_exchange.generate_key_pair()?;
+        source.insert(&QuantumKeyExchange::to_db_model(user_id, &old)).await?;
+        source.insert(&QuantumKeyExchange::to_db_model(user_id, &active)).await?;
+        for claimed_at in [None, Some(OffsetDateTime::now_utc())] {
+            let mut prekey = QuantumKeyExchange::to_db_model(user_id, &key_exchange.generate_key_pair()?);
+            prekey.is_active = false;
+            source.insert_prekey(&prekey, &OneTimePrekey {
+                key_id: prekey.key_id,
+                user_id,
+                public_key: prekey.public_key.clone(),
+                encryption_method: prekey.encryption_method.clone(),
+                signature: vec![7],
+                signing_key_id: Uuid::new_v4(),
+                created_at: prekey.key_generation_timestamp,
+                claimed_at,
+                claimed_by: None,
+            }).await?;
+        }
+
+        // One-time prekeys, claimed or not, stay out of the archive
+        let archive = KeyBackup::export(&source, user_id).await?.seal_with(&test_wrapper())?;
+        assert!(KeyBackup::open(&archive, "wrong passphrase!").is_err());
+        let backup = KeyBackup::open(&archive, PASSPHRASE)?;
+        assert_eq!(backup.user_id, user_id);
+        assert_eq!(backup.keys.len(), 2);
+
+        // Restoring on an empty store brings back both keys and the active one
+        let target = InMemoryKeyStore::new();
+        let report = backup.restore(&target).await?;
+        assert_eq!(report.restored.len(), 2);
+        assert_eq!(report.activated, Some(active.id));
+        assert_eq!(target.active_key_pair(user_id).await?.unwrap().private_key, active.private_key);
+
+        // A key generated after the backup stays active and nothing is restored twice
+        let newer = key_exchange.generate_key_pair()?;
+        target.insert(&QuantumKeyExchange::to_db_model(user_id, &newer)).await?;
+        let report = backup.restore(&target).await?;
+        assert!(report.restored.is_empty());
+        assert_eq!(report.skipped.len(), 2);
+        assert_eq!(target.active_key(user_id).await?.unwrap().key_id, newer.id);
+
+        let fresh = InMemoryKeyStore::new();
+        fresh.insert(&QuantumKeyExchange::to_db_model(user_id, &newer)).await?;
+        let report = backup.restore(&fresh).await?;
+        assert_eq!(report.restored.len(), 2);
+        assert_eq!(report.activated, None);
+        assert_eq!(fresh.active_key(user_id).await?.unwrap().key_id, newer.id);
+        assert_eq!(fresh.key_history(user_id).await?.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tampered_or_invalid_archives_are_rejected() -> Result<()> {
+        let user_id = Uuid::new_v4();
+        let key_pair = key_exchange().generate_key_pair()?;
+        let backup = KeyBackup {
+            user_id,
+            exported_at: OffsetDateTime::now_utc(),
+            keys: vec![QuantumKeyExchange::to_db_model(user_id, &key_pair)],
+        };
+        assert!(backup.seal("too short").is_err());
+
+        let archive = backup.seal_with(&test_wrapper())?;
+        assert_eq!(KeyBackup::owner(&archive)?, user_id);
+        let mut moved = archive.clone();
+        moved[21..HEADER_LEN].copy_from_slice(Uuid::new_v4().as_bytes());
+        assert!(KeyBackup::open(&moved, PASSPHRASE).is_err());
+        let mut flipped = archive.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert!(KeyBackup::open(&flipped, PASSPHRASE).is_err());
+        assert!(KeyBackup::open(&archive[..HEADER_LEN - 1], PASSPHRASE).is_err());
+
+        // A private key that does not belong to its public key fails the integrity check
+        let other = key_exchange().generate_key_pair()?;
+        let mut mismatched = backup.clone();
+        mismatched.keys[0].private_key = other.private_key;
+        assert!(mismatched.seal_with(&test_wrapper()).is_err());
+        let mut foreign = backup;
+        foreign.keys[0].user_id = Uuid::new_v4();
+        assert!(foreign.validate().is_err());
+        Ok(())
+    }
+}
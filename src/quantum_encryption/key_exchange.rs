@@ -1,13 +1,11 @@
 // src/quantum_encryption/key_exchange.rs
 use anyhow::Result;
-use pqcrypto_kyber::kyber1024::{keypair, encapsulate, decapsulate};
-use pqcrypto_traits::kem::*;
-use pqcrypto_traits::kem::{Ciphertext, PublicKey, SecretKey};
-// Import all KEM traits
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use uuid::Uuid;
 use crate::config::EncryptionConfig;
 use crate::database::models::QuantumKey;
+use crate::quantum_encryption::kem::{kem_for_suite, kem_from_config, Kem};
+use crate::quantum_encryption::secret::SecretBytes;
 use tracing::debug;
 use time::{OffsetDateTime, Duration};
 
@@ -15,11 +13,12 @@ pub struct QuantumKeyExchange {
     config: EncryptionConfig,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A KEM key pair; the private key is zeroed on drop and redacted from `Debug`
+#[derive(Debug, Clone, Deserialize)]
 pub struct KeyPair {
     pub id: Uuid,
     pub public_key: Vec<u8>,
-    pub private_key: Vec<u8>,
+    pub private_key: SecretBytes,
     pub algorithm: String,
     pub created_at: OffsetDateTime,
     pub expires_at: OffsetDateTime,
@@ -32,45 +31,40 @@ impl QuantumKeyExchange {
         }
     }
 
+    /// The KEM new keys are generated with, as selected by the configuration
+    pub fn kem(&self) -> Result<&'static dyn Kem> {
+        kem_from_config(&self.config)
+    }
+
     pub fn generate_key_pair(&self) -> Result<KeyPair> {
-        debug!("Generating new Kyber-1024 key pair");
-        let (public_key, private_key) = keypair();
+        let kem = self.kem()?;
+        debug!("Generating new {} key pair", kem.suite());
+        let (public_key, private_key) = kem.generate_key_pair();
 
         let now = OffsetDateTime::now_utc();
         let expires_at = now + Duration::days(self.config.key_rotation_days as i64);
 
         Ok(KeyPair {
             id: Uuid::new_v4(),
-            public_key: public_key.as_bytes().to_vec(),
-            private_key: private_key.as_bytes().to_vec(),
-            algorithm: self.config.algorithm.clone(),
+            public_key,
+            private_key,
+            algorithm: kem.suite().to_string(),
             created_at: now,
             expires_at,
         })
     }
 
-    pub fn encapsulate(&self, public_key_bytes: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
-        debug!("Encapsulating shared secret with public key of length: {}", public_key_bytes.len());
-        let public_key = PublicKey::from_bytes(public_key_bytes)
-            .map_err(|_| anyhow::anyhow!("Invalid public key format: incorrect length or data"))?;
-
-        let (ciphertext, shared_secret) = encapsulate(&public_key);
-
-        Ok((ciphertext.as_bytes().to_vec(), shared_secret.as_bytes().to_vec()))
+    /// Encapsulates a shared secret to a public key of the given suite, returning (ciphertext, shared_secret)
+    pub fn encapsulate(&self, algorithm: &str, public_key_bytes: &[u8]) -> Result<(Vec<u8>, SecretBytes)> {
+        debug!("Encapsulating {} shared secret with public key of length: {}", algorithm, public_key_bytes.len());
+        kem_for_suite(algorithm)?.encapsulate(public_key_bytes)
     }
 
-    pub fn decapsulate(&self, private_key_bytes: &[u8], ciphertext_bytes: &[u8]) -> Result<Vec<u8>> {
-        debug!("Decapsulating shared secret with private key length: {} and ciphertext length: {}",
-            private_key_bytes.len(), ciphertext_bytes.len());
-        let private_key = SecretKey::from_bytes(private_key_bytes)
-            .map_err(|_| anyhow::anyhow!("Invalid private key format: incorrect length or data"))?;
-
-        let ciphertext = Ciphertext::from_bytes(ciphertext_bytes)
-            .map_err(|_| anyhow::anyhow!("Invalid ciphertext format: incorrect length or data"))?;
-
-        let shared_secret = decapsulate(&ciphertext, &private_key);
-
-        Ok(shared_secret.as_bytes().to_vec())
+    /// Decapsulates a ciphertext with a private key of the given suite
+    pub fn decapsulate(&self, algorithm: &str, private_key: &SecretBytes, ciphertext_bytes: &[u8]) -> Result<SecretBytes> {
+        debug!("Decapsulating {} shared secret with private key length: {} and ciphertext length: {}",
+            algorithm, private_key.expose_secret().len(), ciphertext_bytes.len());
+        kem_for_suite(algorithm)?.decapsulate(private_key.expose_secret(), ciphertext_bytes)
     }
 
     pub fn from_db_model(key: QuantumKey) -> KeyPair {
@@ -84,6 +78,19 @@ impl QuantumKeyExchange {
         }
     }
 
+    pub fn to_db_model(user_id: Uuid, key_pair: &KeyPair) -> QuantumKey {
+        QuantumKey {
+            key_id: key_pair.id,
+            user_id,
+            public_key: key_pair.public_key.clone(),
+            private_key: key_pair.private_key.clone(),
+            encryption_method: key_pair.algorithm.clone(),
+            key_generation_timestamp: key_pair.created_at,
+            expiration_timestamp: key_pair.expires_at,
+            is_active: true,
+        }
+    }
+
     pub fn is_expired(key_pair: &KeyPair) -> bool {
         key_pair.expires_at < OffsetDateTime::now_utc()
     }
@@ -99,13 +106,15 @@ mod tests {
             key_rotation_days: 30,
             algorithm: "kyber".to_string(),
             key_size: 1024,
+            key_encryption_secret: "test-secret".to_string(),
         };
         let key_exchange = QuantumKeyExchange::new(&config);
         let key_pair = key_exchange.generate_key_pair()?;
 
-        assert_eq!(key_pair.algorithm, "kyber");
+        assert_eq!(key_pair.algorithm, "kyber1024");
         assert!(!key_pair.public_key.is_empty());
-        assert!(!key_pair.private_key.is_empty());
+        assert!(!key_pair.private_key.expose_secret().is_empty());
+        assert!(!format!("{:?}", key_pair).contains(&format!("{:?}", key_pair.private_key.expose_secret())));
         assert!(!QuantumKeyExchange::is_expired(&key_pair));
         Ok(())
     }
@@ -116,23 +125,65 @@ mod tests {
             key_rotation_days: 30,
             algorithm: "kyber".to_string(),
             key_size: 1024,
+            key_encryption_secret: "test-secret".to_string(),
         };
         let key_exchange = QuantumKeyExchange::new(&config);
 
         let key_pair = key_exchange.generate_key_pair()?;
-        let (ciphertext, shared_secret_enc) = key_exchange.encapsulate(&key_pair.public_key)?;
-        let shared_secret_dec = key_exchange.decapsulate(&key_pair.private_key, &ciphertext)?;
+        let (ciphertext, shared_secret_enc) = key_exchange.encapsulate(&key_pair.algorithm, &key_pair.public_key)?;
+        let shared_secret_dec = key_exchange.decapsulate(&key_pair.algorithm, &key_pair.private_key, &ciphertext)?;
 
         assert_eq!(shared_secret_enc, shared_secret_dec);
         Ok(())
     }
 
+    #[test]
+    fn test_hybrid_encryption_method() -> Result<()> {
+        let config = EncryptionConfig {
+            key_rotation_days: 30,
+            algorithm: "x25519-kyber".to_string(),
+            key_size: 768,
+            key_encryption_secret: "test-secret".to_string(),
+        };
+        let key_exchange = QuantumKeyExchange::new(&config);
+        let key_pair = key_exchange.generate_key_pair()?;
+        assert_eq!(key_pair.algorithm, "x25519-kyber768");
+
+        let (ciphertext, shared_secret) = key_exchange.encapsulate(&key_pair.algorithm, &key_pair.public_key)?;
+        assert_eq!(key_exchange.decapsulate(&key_pair.algorithm, &key_pair.private_key, &ciphertext)?, shared_secret);
+
+        // A hybrid key cannot be used as a plain Kyber key
+        assert!(key_exchange.encapsulate("kyber768", &key_pair.public_key).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_old_suite_stays_usable_after_config_change() -> Result<()> {
+        let mut config = EncryptionConfig {
+            key_rotation_days: 30,
+            algorithm: "kyber".to_string(),
+            key_size: 512,
+            key_encryption_secret: "test-secret".to_string(),
+        };
+        let old_key = QuantumKeyExchange::new(&config).generate_key_pair()?;
+        assert_eq!(old_key.algorithm, "kyber512");
+
+        config.key_size = 1024;
+        let key_exchange = QuantumKeyExchange::new(&config);
+        assert_eq!(key_exchange.generate_key_pair()?.algorithm, "kyber1024");
+
+        let (ciphertext, shared_secret) = key_exchange.encapsulate(&old_key.algorithm, &old_key.public_key)?;
+        assert_eq!(key_exchange.decapsulate(&old_key.algorithm, &old_key.private_key, &ciphertext)?, shared_secret);
+        Ok(())
+    }
+
     #[test]
     fn test_key_expiration() -> Result<()> {
         let config = EncryptionConfig {
             key_rotation_days: 0, // Negative to force expiration
             algorithm: "kyber".to_string(),
             key_size: 1024,
+            key_encryption_secret: "test-secret".to_string(),
         };
         let key_exchange = QuantumKeyExchange::new(&config);
         let key_pair = key_exchange.generate_key_pair()?;
@@ -0,0 +1,182 @@
+// src/quantum_encryption/key_rotation.rs
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use time::OffsetDateTime;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::config::EncryptionConfig;
+use crate::database::key_store::KeyStore;
+use crate::quantum_encryption::key_exchange::{KeyPair, QuantumKeyExchange};
+use crate::websocket::hub::Hub;
+use crate::websocket::server::{WebSocketMessage, WebSocketMessageType};
+
+/// How often the scheduler looks for expiring keys
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A key that was replaced by the scheduler
+#[derive(Debug, Clone)]
+pub struct RotatedKey {
+    pub user_id: Uuid,
+    pub old_key_id: Uuid,
+    pub new_key: KeyPair,
+}
+
+/// Background task replacing quantum keys before they expire
+///
+/// Keys are rotated when they would expire before the next check, so a user always has
+/// a valid active key. The replaced key is kept inactive and only used to decrypt older
+/// mail, and the user's connections are sent a `KeyRotation` event with the new public key.
+/// Each check also deletes one-time prekeys that expired unclaimed, along with their private halves.
+pub struct KeyRotationScheduler {
+    key_store: Arc<dyn KeyStore>,
+    config: EncryptionConfig,
+    hub: Arc<Hub>,
+    interval: Duration,
+}
+
+impl KeyRotationScheduler {
+    pub fn new(key_store: Arc<dyn KeyStore>, config: &EncryptionConfig, hub: Arc<Hub>) -> Self {
+        Self {
+            key_store,
+            config: config.clone(),
+            hub,
+            interval: CHECK_INTERVAL,
+        }
+    }
+
+    /// Runs the scheduler on its own task, checking once at startup and then every interval
+    pub fn spawn(self) -> JoinHandle<()> {
+        info!("Key rotation scheduler started: keys are rotated every {} days", self.config.key_rotation_days);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(self.interval);
+            loop {
+                ticker.tick().await;
+                match self.rotate_due_keys().await {
+                    Ok(rotated) if !rotated.is_empty() => info!("Rotated {} expiring quantum keys", rotated.len()),
+                    Ok(_) => debug!("No quantum keys due for rotation"),
+                    Err(e) => error!("Key rotation failed: {}", e),
+                }
+                match self.key_store.delete_expired_prekeys(OffsetDateTime::now_utc()).await {
+                    Ok(0) => {}
+                    Ok(deleted) => info!("Deleted {} expired one-time prekeys", deleted),
+                    Err(e) => error!("Deleting expired prekeys failed: {}", e),
+                }
+            }
+        })
+    }
+
+    /// Replaces every active key that expires before the next check
+    ///
+    /// A key the user already replaced since the lookup is skipped. A failure for one user
+    /// is logged and does not stop the others from being rotated.
+    pub async fn rotate_due_keys(&self) -> Result<Vec<RotatedKey>> {
+        let due_before = OffsetDateTime::now_utc() + self.interval;
+        let key_exchange = QuantumKeyExchange::new(&self.config);
+
+        let mut rotated = Vec::new();
+        for key in self.key_store.expiring_keys(due_before).await? {
+            let still_active = self.key_store.active_key(key.user_id).await?
+                .is_some_and(|active| active.key_id == key.key_id);
+            if !still_active {
+                debug!("Key {} of user {} was already replaced", key.key_id, key.user_id);
+                continue;
+            }
+
+            let new_key = key_exchange.generate_key_pair()?;
+            // Inserting the new active key deactivates the old one, which stays decrypt-only
+            if let Err(e) = self.key_store.insert(&QuantumKeyExchange::to_db_model(key.user_id, &new_key)).await {
+                warn!("Could not rotate key {} of user {}: {}", key.key_id, key.user_id, e);
+                continue;
+            }
+            info!("Rotated quantum key {} of user {} to {}", key.key_id, key.user_id, new_key.id);
+
+            let rotation = RotatedKey { user_id: key.user_id, old_key_id: key.key_id, new_key };
+            self.hub.send_to_user(rotation.user_id, &rotation_message(&rotation)).await;
+            rotated.push(rotation);
+        }
+        Ok(rotated)
+    }
+}
+
+/// The `KeyRotation` event sent to the owner of a rotated key
+fn rotation_message(rotation: &RotatedKey) -> WebSocketMessage {
+    WebSocketMessage {
+        message_type: WebSocketMessageType::KeyRotation,
+        sender_id: None,
+        recipient_id: Some(rotation.user_id),
+        payload: serde_json::json!({
+            "old_key_id": rotation.old_key_id,
+            "key_id": rotation.new_key.id,
+            "algorithm": rotation.new_key.algorithm,
+            "public_key": BASE64.encode(&rotation.new_key.public_key),
+            "expires_at": rotation.new_key.expires_at,
+        }),
+        timestamp: OffsetDateTime::now_utc(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::key_store::InMemoryKeyStore;
+    use tokio_tungstenite::tungstenite::Message;
+
+    fn test_config() -> EncryptionConfig {
+        EncryptionConfig {
+            key_rotation_days: 30,
+            algorithm: "kyber".to_string(),
+            key_size: 512,
+            key_encryption_secret: "test-secret".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rotates_expiring_keys() -> Result<()> {
+        let config = test_config();
+        let key_store: Arc<dyn KeyStore> = Arc::new(InMemoryKeyStore::new());
+        let hub = Arc::new(Hub::new());
+        let key_exchange = QuantumKeyExchange::new(&config);
+
+        let expiring_user = Uuid::new_v4();
+        let mut expiring = key_exchange.generate_key_pair()?;
+        expiring.expires_at = OffsetDateTime::now_utc() + time::Duration::minutes(5);
+        key_store.insert(&QuantumKeyExchange::to_db_model(expiring_user, &expiring)).await?;
+
+        let fresh_user = Uuid::new_v4();
+        let fresh = key_exchange.generate_key_pair()?;
+        key_store.insert(&QuantumKeyExchange::to_db_model(fresh_user, &fresh)).await?;
+
+        let mut client = hub.connect_test_client(expiring_user).await;
+        let mut other_client = hub.connect_test_client(fresh_user).await;
+
+        let scheduler = KeyRotationScheduler::new(key_store.clone(), &config, hub);
+        let rotated = scheduler.rotate_due_keys().await?;
+        assert_eq!(rotated.len(), 1);
+        assert_eq!(rotated[0].old_key_id, expiring.id);
+
+        // The old key stays available for older mail but is no longer active
+        let history = key_store.key_pair_history(expiring_user).await?;
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].id, rotated[0].new_key.id);
+        assert_eq!(history[1].id, expiring.id);
+        assert!(!key_store.key_history(expiring_user).await?[1].is_active);
+        assert_eq!(key_store.active_key(fresh_user).await?.unwrap().key_id, fresh.id);
+
+        let Some(Message::Text(text)) = client.recv().await else { panic!("expected a text message") };
+        let message: WebSocketMessage = serde_json::from_str(&text)?;
+        assert_eq!(message.message_type, WebSocketMessageType::KeyRotation);
+        assert_eq!(message.payload["old_key_id"], expiring.id.to_string());
+        assert_eq!(message.payload["key_id"], rotated[0].new_key.id.to_string());
+        assert!(other_client.try_recv().is_err());
+
+        // Nothing is due once the new key is in place
+        assert!(scheduler.rotate_due_keys().await?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,313 @@
+// src/quantum_encryption/key_wrapping.rs
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use anyhow::Result;
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::quantum_encryption::kdf::KEY_LEN;
+use crate::quantum_encryption::secret::{SecretBytes, SecretKey};
+
+/// Marks a private key blob as wrapped by this module
+pub const WRAPPED_KEY_MAGIC: &[u8; 4] = b"QKWR";
+
+/// Current version of the wrapped key format
+pub const WRAPPED_KEY_VERSION: u8 = 1;
+
+/// KDF identifier for Argon2id
+const KDF_ARGON2ID: u8 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Length of the header preceding the salt: magic, version, kdf id and three u32 cost params
+const FIXED_HEADER_LEN: usize = 4 + 1 + 1 + 12 + 1;
+
+/// Most derived wrapping keys kept in memory at once
+const MAX_CACHED_KEYS: usize = 1024;
+
+/// Cache of derived wrapping keys by (salt, params)
+type DerivedKeyCache = HashMap<(Vec<u8>, KdfParams), SecretKey>;
+
+/// Argon2id cost parameters stored with every wrapped key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl KdfParams {
+    /// Highest cost a wrapped key may ask for: four times the defaults
+    ///
+    /// The parameters are read from the blob before it is authenticated, so without a
+    /// ceiling an uploaded blob could demand gigabytes of memory or hours of hashing.
+    pub const MAX: Self = Self {
+        memory_kib: 4 * 19 * 1024,
+        iterations: 8,
+        parallelism: 4,
+    };
+
+    /// Returns true if no parameter exceeds the corresponding one of `limit`
+    pub fn within(&self, limit: &Self) -> bool {
+        self.memory_kib <= limit.memory_kib && self.iterations <= limit.iterations && self.parallelism <= limit.parallelism
+    }
+}
+
+impl Default for KdfParams {
+    /// OWASP recommended Argon2id settings: 19 MiB, 2 iterations, 1 lane
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+/// Wraps private keys with AES-256-GCM under a key derived from a secret with Argon2id
+///
+/// The secret is either a user's passphrase or the server master key. Each wrapped key
+/// carries its own salt and KDF parameters, so parameters can be raised without
+/// breaking older keys. Wrapped layout (integers big-endian):
+///
+/// `"QKWR" | version u8 | kdf u8 | memory_kib u32 | iterations u32 | parallelism u32 |
+///  salt_len u8 | salt | nonce (12) | AES-GCM ciphertext + tag`
+///
+/// The header, key ID and user ID are authenticated as associated data, so a wrapped
+/// key cannot be moved to another row or have its parameters altered.
+pub struct KeyWrapper {
+    secret: SecretBytes,
+    params: KdfParams,
+    // Argon2id is deliberately slow, so recently used salts are not derived again
+    derived_keys: Mutex<DerivedKeyCache>,
+}
+
+impl KeyWrapper {
+    /// Creates a wrapper using the default KDF parameters
+    pub fn new(secret: &[u8]) -> Self {
+        Self::with_params(secret, KdfParams::default())
+    }
+
+    /// Creates a wrapper that wraps new keys with the given KDF parameters
+    pub fn with_params(secret: &[u8], params: KdfParams) -> Self {
+        Self {
+            secret: SecretBytes::new(secret.to_vec()),
+            params,
+            derived_keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns true if the blob uses the wrapped key format rather than raw key bytes
+    pub fn is_wrapped(blob: &[u8]) -> bool {
+        blob.starts_with(WRAPPED_KEY_MAGIC)
+    }
+
+    /// Wraps a private key belonging to the given key and user IDs
+    pub fn wrap_private_key(&self, key_id: Uuid, user_id: Uuid, private_key: &[u8]) -> Result<Vec<u8>> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let mut blob = Vec::with_capacity(FIXED_HEADER_LEN + SALT_LEN + NONCE_LEN + private_key.len() + 16);
+        blob.extend_from_slice(WRAPPED_KEY_MAGIC);
+        blob.push(WRAPPED_KEY_VERSION);
+        blob.push(KDF_ARGON2ID);
+        blob.extend_from_slice(&self.params.memory_kib.to_be_bytes());
+        blob.extend_from_slice(&self.params.iterations.to_be_bytes());
+        blob.extend_from_slice(&self.params.parallelism.to_be_bytes());
+        blob.push(SALT_LEN as u8);
+        blob.extend_from_slice(&salt);
+        blob.extend_from_slice(&nonce);
+
+        let wrapping_key = self.derive_wrapping_key(&salt, self.params)?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(wrapping_key.expose_secret()));
+        let aad = Self::associated_data(&blob, key_id, user_id);
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: private_key, aad: &aad })
+            .map_err(|e| anyhow::anyhow!("Failed to wrap private key: {}", e))?;
+        blob.extend_from_slice(&ciphertext);
+
+        debug!("Wrapped private key {} with Argon2id ({} KiB)", key_id, self.params.memory_kib);
+        Ok(blob)
+    }
+
+    /// Unwraps a private key previously wrapped for the same key and user IDs
+    pub fn unwrap_private_key(&self, key_id: Uuid, user_id: Uuid, blob: &[u8]) -> Result<SecretBytes> {
+        if !Self::is_wrapped(blob) {
+            return Err(anyhow::anyhow!("Private key {} is not in the wrapped key format", key_id));
+        }
+        if blob.len() < FIXED_HEADER_LEN {
+            return Err(anyhow::anyhow!("Wrapped private key {} is truncated", key_id));
+        }
+
+        let version = blob[4];
+        if version != WRAPPED_KEY_VERSION {
+            return Err(anyhow::anyhow!("Unsupported wrapped key version: {}", version));
+        }
+        let kdf = blob[5];
+        if kdf != KDF_ARGON2ID {
+            return Err(anyhow::anyhow!("Unsupported wrapped key KDF: {}", kdf));
+        }
+
+        let read_u32 = |offset: usize| u32::from_be_bytes(blob[offset..offset + 4].try_into().unwrap());
+        let params = KdfParams {
+            memory_kib: read_u32(6),
+            iterations: read_u32(10),
+            parallelism: read_u32(14),
+        };
+        if !params.within(&KdfParams::MAX) {
+            return Err(anyhow::anyhow!("Wrapped private key {} asks for KDF parameters above the allowed maximum", key_id));
+        }
+        let salt_len = blob[18] as usize;
+        let nonce_start = FIXED_HEADER_LEN + salt_len;
+        let body_start = nonce_start + NONCE_LEN;
+        if salt_len == 0 || blob.len() < body_start + 16 {
+            return Err(anyhow::anyhow!("Wrapped private key {} is truncated", key_id));
+        }
+
+        let salt = &blob[FIXED_HEADER_LEN..nonce_start];
+        let nonce = &blob[nonce_start..body_start];
+        let wrapping_key = self.derive_wrapping_key(salt, params)?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(wrapping_key.expose_secret()));
+        let aad = Self::associated_data(&blob[..body_start], key_id, user_id);
+
+        cipher
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: &blob[body_start..], aad: &aad })
+            .map(SecretBytes::new)
+            .map_err(|_| anyhow::anyhow!("Failed to unwrap private key {}: wrong secret or tampered data", key_id))
+    }
+
+    /// Derives (or fetches from cache) the 32-byte wrapping key for a salt and parameter set
+    fn derive_wrapping_key(&self, salt: &[u8], params: KdfParams) -> Result<SecretKey> {
+        let cache_key = (salt.to_vec(), params);
+        if let Some(key) = self.derived_keys.lock().unwrap().get(&cache_key) {
+            return Ok(key.clone());
+        }
+
+        let argon2_params = Params::new(params.memory_kib, params.iterations, params.parallelism, Some(KEY_LEN))
+            .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {}", e))?;
+        let mut key = SecretKey::new([0u8; KEY_LEN]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, argon2_params)
+            .hash_password_into(self.secret.expose_secret(), salt, key.expose_secret_mut())
+            .map_err(|e| anyhow::anyhow!("Failed to derive wrapping key: {}", e))?;
+
+        let mut derived_keys = self.derived_keys.lock().unwrap();
+        if derived_keys.len() >= MAX_CACHED_KEYS {
+            // Every key has its own salt, so make room rather than grow with the key count;
+            // the evicted key is zeroed as it is dropped
+            if let Some(evicted) = derived_keys.keys().next().cloned() {
+                derived_keys.remove(&evicted);
+            }
+        }
+        derived_keys.insert(cache_key, key.clone());
+        Ok(key)
+    }
+
+    fn associated_data(header: &[u8], key_id: Uuid, user_id: Uuid) -> Vec<u8> {
+        let mut aad = Vec::with_capacity(header.len() + 32);
+        aad.extend_from_slice(header);
+        aad.extend_from_slice(key_id.as_bytes());
+        aad.extend_from_slice(user_id.as_bytes());
+        aad
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Small parameters keep the tests fast; the format is identical
+    fn test_params() -> KdfParams {
+        KdfParams { memory_kib: 64, iterations: 1, parallelism: 1 }
+    }
+
+    #[test]
+    fn test_wrap_unwrap_roundtrip() -> Result<()> {
+        let wrapper = KeyWrapper::with_params(b"master secret", test_params());
+        let (key_id, user_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let private_key = vec![7u8; 3168];
+
+        let wrapped = wrapper.wrap_private_key(key_id, user_id, &private_key)?;
+        assert!(KeyWrapper::is_wrapped(&wrapped));
+        assert!(!wrapped.windows(32).any(|w| w == &private_key[..32]));
+
+        let unwrapped = wrapper.unwrap_private_key(key_id, user_id, &wrapped)?;
+        assert_eq!(unwrapped.expose_secret(), &private_key);
+
+        // A fresh wrapper reads the params from the blob instead of its own settings
+        let other = KeyWrapper::new(b"master secret");
+        assert_eq!(other.unwrap_private_key(key_id, user_id, &wrapped)?.expose_secret(), &private_key);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unwrap_rejects_wrong_context() -> Result<()> {
+        let wrapper = KeyWrapper::with_params(b"master secret", test_params());
+        let (key_id, user_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let wrapped = wrapper.wrap_private_key(key_id, user_id, b"secret key bytes")?;
+
+        assert!(wrapper.unwrap_private_key(Uuid::new_v4(), user_id, &wrapped).is_err());
+        assert!(wrapper.unwrap_private_key(key_id, Uuid::new_v4(), &wrapped).is_err());
+
+        let wrong_secret = KeyWrapper::with_params(b"other secret", test_params());
+        assert!(wrong_secret.unwrap_private_key(key_id, user_id, &wrapped).is_err());
+
+        let mut tampered = wrapped.clone();
+        tampered[9] ^= 1; // memory_kib field
+        assert!(wrapper.unwrap_private_key(key_id, user_id, &tampered).is_err());
+
+        let mut bad_version = wrapped.clone();
+        bad_version[4] = 99;
+        assert!(wrapper.unwrap_private_key(key_id, user_id, &bad_version).is_err());
+
+        assert!(wrapper.unwrap_private_key(key_id, user_id, &wrapped[..30]).is_err());
+        assert!(wrapper.unwrap_private_key(key_id, user_id, b"raw key").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_derived_key_cache_is_bounded() -> Result<()> {
+        let wrapper = KeyWrapper::with_params(b"master secret", test_params());
+        let user_id = Uuid::new_v4();
+        let wrapped: Vec<_> = (0..MAX_CACHED_KEYS + 10)
+            .map(|_| {
+                let key_id = Uuid::new_v4();
+                wrapper.wrap_private_key(key_id, user_id, b"secret key bytes").map(|blob| (key_id, blob))
+            })
+            .collect::<Result<_>>()?;
+        assert_eq!(wrapper.derived_keys.lock().unwrap().len(), MAX_CACHED_KEYS);
+
+        // Evicted salts are derived again when needed
+        for (key_id, blob) in &wrapped {
+            assert_eq!(wrapper.unwrap_private_key(*key_id, user_id, blob)?.expose_secret(), b"secret key bytes");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_unwrap_rejects_excessive_params_before_deriving() -> Result<()> {
+        let wrapper = KeyWrapper::with_params(b"master secret", test_params());
+        let (key_id, user_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let wrapped = wrapper.wrap_private_key(key_id, user_id, b"secret key bytes")?;
+
+        // Deriving with these would allocate 4 TiB; the blob is refused first
+        let mut huge_memory = wrapped.clone();
+        huge_memory[6..10].copy_from_slice(&u32::MAX.to_be_bytes());
+        let error = wrapper.unwrap_private_key(key_id, user_id, &huge_memory).unwrap_err();
+        assert!(error.to_string().contains("allowed maximum"));
+
+        let mut many_iterations = wrapped;
+        many_iterations[10..14].copy_from_slice(&(KdfParams::MAX.iterations + 1).to_be_bytes());
+        assert!(wrapper.unwrap_private_key(key_id, user_id, &many_iterations).is_err());
+        assert!(KdfParams::default().within(&KdfParams::MAX));
+        Ok(())
+    }
+}
@@ -1,3 +1,14 @@
 pub mod key_exchange;
+pub mod kem;
+pub mod hybrid_kem;
+pub mod kdf;
+pub mod secret;
+pub mod envelope;
+pub mod fingerprint;
+pub mod stream;
 pub mod encryption;
 pub mod decryption;
+pub mod key_wrapping;
+pub mod key_rotation;
+pub mod key_backup;
+pub mod signing;
@@ -0,0 +1,163 @@
+// src/quantum_encryption/secret.rs
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgArgumentBuffer, PgTypeInfo, PgValueRef};
+use sqlx::{Decode, Encode, Postgres, Type};
+use subtle::ConstantTimeEq;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::quantum_encryption::kdf::KEY_LEN;
+
+/// Secret material that is zeroed when dropped
+///
+/// `Debug` never prints the value and there is no `Serialize` impl; a field that really
+/// must be written out opts in with `#[serde(serialize_with = "serialize_exposed")]`.
+/// Reading the value requires an explicit `expose_secret()`, and equality is constant-time.
+pub struct Secret<T: Zeroize>(T);
+
+/// Secret bytes of any length, such as private keys and KEM shared secrets
+pub type SecretBytes = Secret<Vec<u8>>;
+
+/// A derived 256-bit AES key
+pub type SecretKey = Secret<[u8; KEY_LEN]>;
+
+impl<T: Zeroize> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// Borrows the secret value; keep the borrow as short as possible
+    pub fn expose_secret(&self) -> &T {
+        &self.0
+    }
+
+    /// Mutably borrows the secret value, e.g. to fill it in place
+    pub fn expose_secret_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Zeroize> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<T: Zeroize> ZeroizeOnDrop for Secret<T> {}
+
+/// Every copy is zeroed on drop in turn
+impl<T: Zeroize + Clone> Clone for Secret<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: Zeroize> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]>> PartialEq for Secret<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_ref().ct_eq(other.0.as_ref()).into()
+    }
+}
+
+impl<T: Zeroize + AsRef<[u8]>> Eq for Secret<T> {}
+
+impl<'de, T: Zeroize + Deserialize<'de>> Deserialize<'de> for Secret<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Self)
+    }
+}
+
+/// Serializes a secret in the clear, for fields that explicitly opt in
+pub fn serialize_exposed<T, S>(secret: &Secret<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Zeroize + Serialize,
+    S: Serializer,
+{
+    secret.0.serialize(serializer)
+}
+
+// Private keys are stored as BYTEA, wrapped by `KeyWrapper` before they reach the database
+impl Type<Postgres> for SecretBytes {
+    fn type_info() -> PgTypeInfo {
+        <Vec<u8> as Type<Postgres>>::type_info()
+    }
+
+    fn compatible(ty: &PgTypeInfo) -> bool {
+        <Vec<u8> as Type<Postgres>>::compatible(ty)
+    }
+}
+
+impl Encode<'_, Postgres> for SecretBytes {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+        <Vec<u8> as Encode<Postgres>>::encode_by_ref(&self.0, buf)
+    }
+}
+
+impl<'r> Decode<'r, Postgres> for SecretBytes {
+    fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+        <Vec<u8> as Decode<Postgres>>::decode(value).map(Self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Key bytes that stay observable after the secret holding them is dropped
+    struct Observed(Rc<RefCell<[u8; KEY_LEN]>>);
+
+    impl Zeroize for Observed {
+        fn zeroize(&mut self) {
+            self.0.borrow_mut().zeroize();
+        }
+    }
+
+    #[test]
+    fn test_secret_is_redacted() {
+        let secret = SecretBytes::new(vec![0x42; 32]);
+        assert_eq!(format!("{:?}", secret), "Secret([REDACTED])");
+        assert!(!format!("{:?}", Some(&secret)).contains("66"));
+        assert_eq!(secret, secret.clone());
+        assert_ne!(secret, SecretBytes::new(vec![0x42; 31]));
+    }
+
+    #[test]
+    fn test_secret_is_zeroed_on_drop() {
+        let bytes = Rc::new(RefCell::new([7; KEY_LEN]));
+        let secret = Secret::new(Observed(bytes.clone()));
+        assert_eq!(*bytes.borrow(), [7; KEY_LEN]);
+
+        drop(secret);
+        assert_eq!(*bytes.borrow(), [0; KEY_LEN]);
+    }
+
+    #[test]
+    fn test_serialization_requires_opt_in() -> serde_json::Result<()> {
+        #[derive(Serialize)]
+        struct Exported {
+            #[serde(serialize_with = "serialize_exposed")]
+            key: SecretBytes,
+        }
+
+        let secret: SecretBytes = serde_json::from_str("[1,2,3]")?;
+        assert_eq!(secret.expose_secret(), &vec![1, 2, 3]);
+        assert_eq!(serde_json::to_string(&Exported { key: secret })?, r#"{"key":[1,2,3]}"#);
+        Ok(())
+    }
+}
@@ -0,0 +1,368 @@
+// src/quantum_encryption/signing.rs
+use anyhow::Result;
+use pqcrypto_dilithium::dilithium3::{detached_sign, keypair, verify_detached_signature};
+use pqcrypto_dilithium::dilithium3::{DetachedSignature, PublicKey, SecretKey};
+use pqcrypto_traits::sign::{DetachedSignature as _, PublicKey as _, SecretKey as _};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::database::models::SigningKey;
+use crate::quantum_encryption::secret::SecretBytes;
+
+/// Signature algorithm used for sender authentication
+pub const SIGNATURE_ALGORITHM: &str = "dilithium3";
+
+/// Domain separation tag prefixed to every signed email
+const SIGNATURE_CONTEXT: &[u8] = b"quantum-email-signature-v1";
+
+/// Domain separation tag prefixed to every signed key revocation
+const REVOCATION_CONTEXT: &[u8] = b"quantum-email-revocation-v1";
+
+/// Domain separation tag prefixed to every signed one-time prekey
+const PREKEY_CONTEXT: &[u8] = b"quantum-email-prekey-v1";
+
+/// A user's Dilithium signing key pair; the private key is zeroed on drop and redacted from `Debug`
+#[derive(Debug, Clone, Deserialize)]
+pub struct SigningKeyPair {
+    pub id: Uuid,
+    pub public_key: Vec<u8>,
+    pub private_key: SecretBytes,
+    pub algorithm: String,
+    pub created_at: OffsetDateTime,
+}
+
+/// Email header fields covered by the sender's signature and bound into the content keys
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailHeader {
+    pub email_id: Uuid,
+    pub sender_id: Uuid,
+    pub recipient_id: Uuid,
+    pub subject: String,
+}
+
+/// Key revocation fields covered by the owner's signature
+///
+/// `revoked_at` is signed with second precision, so the record still verifies after the
+/// timestamp has been stored and read back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RevocationRecord<'a> {
+    pub key_id: Uuid,
+    pub user_id: Uuid,
+    pub reason: &'a str,
+    pub revoked_at: OffsetDateTime,
+}
+
+/// One-time prekey fields covered by the owner's signature
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrekeyRecord<'a> {
+    pub key_id: Uuid,
+    pub user_id: Uuid,
+    pub algorithm: &'a str,
+    pub public_key: &'a [u8],
+}
+
+/// Outcome of checking the sender's signature on an email
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureStatus {
+    /// The signature is valid and was made by a key belonging to the claimed sender
+    Verified,
+    /// The email carries no signature, or the signing key is unknown
+    Unverified,
+    /// The signature does not match the email or belongs to someone else
+    Invalid,
+}
+
+/// Service for signing and verifying emails with Dilithium
+#[derive(Default)]
+pub struct SignatureService;
+
+impl SignatureService {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn generate_key_pair(&self) -> SigningKeyPair {
+        debug!("Generating new Dilithium3 signing key pair");
+        let (public_key, private_key) = keypair();
+
+        SigningKeyPair {
+            id: Uuid::new_v4(),
+            public_key: public_key.as_bytes().to_vec(),
+            private_key: private_key.as_bytes().to_vec().into(),
+            algorithm: SIGNATURE_ALGORITHM.to_string(),
+            created_at: OffsetDateTime::now_utc(),
+        }
+    }
+
+    /// Signs the header and ciphertexts of an email
+    pub fn sign_email(
+        &self,
+        signing_key: &SigningKeyPair,
+        header: &EmailHeader,
+        encapsulated_secret: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>> {
+        let private_key = SecretKey::from_bytes(signing_key.private_key.expose_secret())
+            .map_err(|_| anyhow::anyhow!("Invalid signing key format: incorrect length or data"))?;
+        let message = Self::signed_message(header, encapsulated_secret, ciphertext);
+
+        Ok(detached_sign(&message, &private_key).as_bytes().to_vec())
+    }
+
+    /// Verifies an email signature against the sender's public signing key
+    pub fn verify_email(
+        &self,
+        public_key: &[u8],
+        signature: &[u8],
+        header: &EmailHeader,
+        encapsulated_secret: &[u8],
+        ciphertext: &[u8],
+    ) -> SignatureStatus {
+        let (Ok(public_key), Ok(signature)) = (
+            PublicKey::from_bytes(public_key),
+            DetachedSignature::from_bytes(signature),
+        ) else {
+            debug!("Malformed signature or signing key for email from {}", header.sender_id);
+            return SignatureStatus::Invalid;
+        };
+
+        let message = Self::signed_message(header, encapsulated_secret, ciphertext);
+        match verify_detached_signature(&signature, &message, &public_key) {
+            Ok(()) => SignatureStatus::Verified,
+            Err(_) => SignatureStatus::Invalid,
+        }
+    }
+
+    /// Signs a key revocation as the key's owner
+    pub fn sign_revocation(&self, signing_key: &SigningKeyPair, record: &RevocationRecord) -> Result<Vec<u8>> {
+        let private_key = SecretKey::from_bytes(signing_key.private_key.expose_secret())
+            .map_err(|_| anyhow::anyhow!("Invalid signing key format: incorrect length or data"))?;
+        let message = Self::revocation_message(record);
+
+        Ok(detached_sign(&message, &private_key).as_bytes().to_vec())
+    }
+
+    /// Verifies a key revocation against the owner's public signing key
+    pub fn verify_revocation(&self, public_key: &[u8], signature: &[u8], record: &RevocationRecord) -> SignatureStatus {
+        let (Ok(public_key), Ok(signature)) = (
+            PublicKey::from_bytes(public_key),
+            DetachedSignature::from_bytes(signature),
+        ) else {
+            debug!("Malformed signature or signing key for revocation of key {}", record.key_id);
+            return SignatureStatus::Invalid;
+        };
+
+        match verify_detached_signature(&signature, &Self::revocation_message(record), &public_key) {
+            Ok(()) => SignatureStatus::Verified,
+            Err(_) => SignatureStatus::Invalid,
+        }
+    }
+
+    /// Signs a one-time prekey with its owner's identity key
+    pub fn sign_prekey(&self, signing_key: &SigningKeyPair, record: &PrekeyRecord) -> Result<Vec<u8>> {
+        let private_key = SecretKey::from_bytes(signing_key.private_key.expose_secret())
+            .map_err(|_| anyhow::anyhow!("Invalid signing key format: incorrect length or data"))?;
+        let message = Self::prekey_message(record);
+
+        Ok(detached_sign(&message, &private_key).as_bytes().to_vec())
+    }
+
+    /// Verifies a one-time prekey against its owner's public signing key
+    pub fn verify_prekey(&self, public_key: &[u8], signature: &[u8], record: &PrekeyRecord) -> SignatureStatus {
+        let (Ok(public_key), Ok(signature)) = (
+            PublicKey::from_bytes(public_key),
+            DetachedSignature::from_bytes(signature),
+        ) else {
+            debug!("Malformed signature or signing key for prekey {}", record.key_id);
+            return SignatureStatus::Invalid;
+        };
+
+        match verify_detached_signature(&signature, &Self::prekey_message(record), &public_key) {
+            Ok(()) => SignatureStatus::Verified,
+            Err(_) => SignatureStatus::Invalid,
+        }
+    }
+
+    pub fn from_db_model(key: SigningKey) -> SigningKeyPair {
+        SigningKeyPair {
+            id: key.key_id,
+            public_key: key.public_key,
+            private_key: key.private_key,
+            algorithm: key.algorithm,
+            created_at: key.created_at,
+        }
+    }
+
+    pub fn to_db_model(user_id: Uuid, key_pair: &SigningKeyPair) -> SigningKey {
+        SigningKey {
+            key_id: key_pair.id,
+            user_id,
+            public_key: key_pair.public_key.clone(),
+            private_key: key_pair.private_key.clone(),
+            algorithm: key_pair.algorithm.clone(),
+            created_at: key_pair.created_at,
+            is_active: true,
+        }
+    }
+
+    /// Builds the byte string that gets signed for an email
+    fn signed_message(header: &EmailHeader, encapsulated_secret: &[u8], ciphertext: &[u8]) -> Vec<u8> {
+        Self::length_prefixed(SIGNATURE_CONTEXT, &[
+            header.email_id.as_bytes(),
+            header.sender_id.as_bytes(),
+            header.recipient_id.as_bytes(),
+            header.subject.as_bytes(),
+            encapsulated_secret,
+            ciphertext,
+        ])
+    }
+
+    /// Builds the byte string that gets signed for a key revocation
+    fn revocation_message(record: &RevocationRecord) -> Vec<u8> {
+        Self::length_prefixed(REVOCATION_CONTEXT, &[
+            record.key_id.as_bytes(),
+            record.user_id.as_bytes(),
+            record.reason.as_bytes(),
+            &record.revoked_at.unix_timestamp().to_be_bytes(),
+        ])
+    }
+
+    /// Builds the byte string that gets signed for a one-time prekey
+    fn prekey_message(record: &PrekeyRecord) -> Vec<u8> {
+        Self::length_prefixed(PREKEY_CONTEXT, &[
+            record.key_id.as_bytes(),
+            record.user_id.as_bytes(),
+            record.algorithm.as_bytes(),
+            record.public_key,
+        ])
+    }
+
+    /// Prefixes the context and every field with its length
+    fn length_prefixed(context: &[u8], fields: &[&[u8]]) -> Vec<u8> {
+        let mut message = Vec::with_capacity(
+            context.len() + fields.iter().map(|f| f.len() + 8).sum::<usize>(),
+        );
+        message.extend_from_slice(context);
+        for field in fields {
+            message.extend_from_slice(&(field.len() as u64).to_be_bytes());
+            message.extend_from_slice(field);
+        }
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> EmailHeader {
+        EmailHeader {
+            email_id: Uuid::new_v4(),
+            sender_id: Uuid::new_v4(),
+            recipient_id: Uuid::new_v4(),
+            subject: "Quarterly report".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_sign_verify_roundtrip() -> Result<()> {
+        let service = SignatureService::new();
+        let key_pair = service.generate_key_pair();
+        let header = header();
+
+        let signature = service.sign_email(&key_pair, &header, b"kem", b"body")?;
+        let status = service.verify_email(&key_pair.public_key, &signature, &header, b"kem", b"body");
+        assert_eq!(status, SignatureStatus::Verified);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tampering_is_detected() -> Result<()> {
+        let service = SignatureService::new();
+        let key_pair = service.generate_key_pair();
+        let header = header();
+        let signature = service.sign_email(&key_pair, &header, b"kem", b"body")?;
+
+        let mut forged_header = header.clone();
+        forged_header.sender_id = Uuid::new_v4();
+        assert_eq!(
+            service.verify_email(&key_pair.public_key, &signature, &forged_header, b"kem", b"body"),
+            SignatureStatus::Invalid
+        );
+
+        let mut forged_subject = header.clone();
+        forged_subject.subject = "Urgent: wire transfer".to_string();
+        assert_eq!(
+            service.verify_email(&key_pair.public_key, &signature, &forged_subject, b"kem", b"body"),
+            SignatureStatus::Invalid
+        );
+
+        assert_eq!(
+            service.verify_email(&key_pair.public_key, &signature, &header, b"kem", b"b0dy"),
+            SignatureStatus::Invalid
+        );
+
+        let other_key = service.generate_key_pair();
+        assert_eq!(
+            service.verify_email(&other_key.public_key, &signature, &header, b"kem", b"body"),
+            SignatureStatus::Invalid
+        );
+
+        assert_eq!(
+            service.verify_email(&key_pair.public_key, b"short", &header, b"kem", b"body"),
+            SignatureStatus::Invalid
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_revocation_signature() -> Result<()> {
+        let service = SignatureService::new();
+        let key_pair = service.generate_key_pair();
+        let record = RevocationRecord {
+            key_id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            reason: "compromised",
+            revoked_at: OffsetDateTime::now_utc(),
+        };
+        let signature = service.sign_revocation(&key_pair, &record)?;
+        assert_eq!(service.verify_revocation(&key_pair.public_key, &signature, &record), SignatureStatus::Verified);
+
+        // Only whole seconds are signed
+        let stored = RevocationRecord { revoked_at: record.revoked_at.replace_nanosecond(0)?, ..record.clone() };
+        assert_eq!(service.verify_revocation(&key_pair.public_key, &signature, &stored), SignatureStatus::Verified);
+
+        let other_reason = RevocationRecord { reason: "superseded", ..record.clone() };
+        assert_eq!(service.verify_revocation(&key_pair.public_key, &signature, &other_reason), SignatureStatus::Invalid);
+        let other_key = RevocationRecord { key_id: Uuid::new_v4(), ..record };
+        assert_eq!(service.verify_revocation(&key_pair.public_key, &signature, &other_key), SignatureStatus::Invalid);
+
+        // An email signature is never accepted as a revocation
+        let email_signature = service.sign_email(&key_pair, &header(), b"kem", b"body")?;
+        assert_eq!(service.verify_revocation(&key_pair.public_key, &email_signature, &stored), SignatureStatus::Invalid);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prekey_signature() -> Result<()> {
+        let service = SignatureService::new();
+        let key_pair = service.generate_key_pair();
+        let record = PrekeyRecord {
+            key_id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            algorithm: "kyber768",
+            public_key: b"prekey",
+        };
+        let signature = service.sign_prekey(&key_pair, &record)?;
+        assert_eq!(service.verify_prekey(&key_pair.public_key, &signature, &record), SignatureStatus::Verified);
+
+        let swapped = PrekeyRecord { public_key: b"attacker key", ..record.clone() };
+        assert_eq!(service.verify_prekey(&key_pair.public_key, &signature, &swapped), SignatureStatus::Invalid);
+        let other_owner = PrekeyRecord { user_id: Uuid::new_v4(), ..record };
+        assert_eq!(service.verify_prekey(&key_pair.public_key, &signature, &other_owner), SignatureStatus::Invalid);
+        Ok(())
+    }
+}
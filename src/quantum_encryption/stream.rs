@@ -0,0 +1,230 @@
+// src/quantum_encryption/stream.rs
+use anyhow::Result;
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use crate::quantum_encryption::envelope::{NONCE_LEN, TAG_LEN};
+use crate::quantum_encryption::kdf::KEY_LEN;
+
+/// Plaintext bytes in every chunk but the last
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Random per-stream part of each chunk nonce
+pub const NONCE_PREFIX_LEN: usize = 7;
+
+/// Current stream format version
+pub const STREAM_VERSION: u8 = 1;
+
+/// Length of a serialized StreamHeader
+pub const STREAM_HEADER_LEN: usize = 1 + 4 + NONCE_PREFIX_LEN;
+
+/// Parameters shared by every chunk of one encrypted stream
+///
+/// Serialized as `version (1) | chunk_size (u32 BE) | nonce_prefix (7)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamHeader {
+    pub chunk_size: u32,
+    pub nonce_prefix: [u8; NONCE_PREFIX_LEN],
+}
+
+impl StreamHeader {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(STREAM_HEADER_LEN);
+        bytes.push(STREAM_VERSION);
+        bytes.extend_from_slice(&self.chunk_size.to_be_bytes());
+        bytes.extend_from_slice(&self.nonce_prefix);
+        bytes
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != STREAM_HEADER_LEN {
+            return Err(anyhow::anyhow!("Invalid stream header: expected {} bytes, got {}", STREAM_HEADER_LEN, bytes.len()));
+        }
+        if bytes[0] != STREAM_VERSION {
+            return Err(anyhow::anyhow!("Unsupported stream version: {}", bytes[0]));
+        }
+        let chunk_size = u32::from_be_bytes(bytes[1..5].try_into()?);
+        if chunk_size == 0 {
+            return Err(anyhow::anyhow!("Invalid stream header: chunk size is zero"));
+        }
+        Ok(Self { chunk_size, nonce_prefix: bytes[5..].try_into()? })
+    }
+
+    /// Length of a sealed chunk holding `chunk_size` plaintext bytes
+    pub fn sealed_chunk_len(&self) -> usize {
+        self.chunk_size as usize + TAG_LEN
+    }
+}
+
+/// AES-256-GCM with the nonce built from the stream prefix, chunk counter and last-chunk flag
+struct ChunkCipher {
+    cipher: Aes256Gcm,
+    header: StreamHeader,
+    aad: Vec<u8>,
+    counter: u32,
+}
+
+impl ChunkCipher {
+    fn new(key: &[u8; KEY_LEN], header: StreamHeader, aad: &[u8]) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+            header,
+            aad: aad.to_vec(),
+            counter: 0,
+        }
+    }
+
+    /// Nonce for the next chunk: `nonce_prefix (7) | counter (u32 BE) | last (1)`
+    fn next_nonce(&mut self, last: bool) -> Result<[u8; NONCE_LEN]> {
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[..NONCE_PREFIX_LEN].copy_from_slice(&self.header.nonce_prefix);
+        nonce[NONCE_PREFIX_LEN..NONCE_LEN - 1].copy_from_slice(&self.counter.to_be_bytes());
+        nonce[NONCE_LEN - 1] = u8::from(last);
+        self.counter = self.counter
+            .checked_add(1)
+            .ok_or_else(|| anyhow::anyhow!("Stream is too long: chunk counter exhausted"))?;
+        Ok(nonce)
+    }
+}
+
+/// Encrypts a stream chunk by chunk with the STREAM construction
+///
+/// Every chunk is sealed under a nonce carrying its position and whether it is the last
+/// one, so dropping, reordering or truncating chunks makes decryption fail.
+pub struct StreamEncryptor(ChunkCipher);
+
+impl StreamEncryptor {
+    /// Starts a stream under a fresh random nonce prefix, authenticating `aad` with every chunk
+    pub fn new(key: &[u8; KEY_LEN], aad: &[u8]) -> Self {
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LEN];
+        OsRng.fill_bytes(&mut nonce_prefix);
+        let header = StreamHeader { chunk_size: CHUNK_SIZE as u32, nonce_prefix };
+        Self(ChunkCipher::new(key, header, aad))
+    }
+
+    /// The header to store alongside the chunks
+    pub fn header(&self) -> StreamHeader {
+        self.0.header
+    }
+
+    /// Seals a chunk that is not the last; it must be exactly `chunk_size` bytes
+    pub fn encrypt_next(&mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        if chunk.len() != self.0.header.chunk_size as usize {
+            return Err(anyhow::anyhow!("Only the last chunk may be shorter than {} bytes", self.0.header.chunk_size));
+        }
+        self.seal(chunk, false)
+    }
+
+    /// Seals the last chunk, which may be empty, and ends the stream
+    pub fn encrypt_last(mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        if chunk.len() > self.0.header.chunk_size as usize {
+            return Err(anyhow::anyhow!("Chunk is longer than {} bytes", self.0.header.chunk_size));
+        }
+        self.seal(chunk, true)
+    }
+
+    fn seal(&mut self, chunk: &[u8], last: bool) -> Result<Vec<u8>> {
+        let nonce = self.0.next_nonce(last)?;
+        self.0.cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: chunk, aad: &self.0.aad })
+            .map_err(|e| anyhow::anyhow!("Chunk encryption failed: {}", e))
+    }
+}
+
+/// Decrypts a stream sealed by StreamEncryptor, chunk by chunk and in order
+pub struct StreamDecryptor(ChunkCipher);
+
+impl StreamDecryptor {
+    pub fn new(key: &[u8; KEY_LEN], header: StreamHeader, aad: &[u8]) -> Self {
+        Self(ChunkCipher::new(key, header, aad))
+    }
+
+    /// Opens the next chunk; fails if it was moved or was sealed as the last one
+    pub fn decrypt_next(&mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        self.open(chunk, false)
+    }
+
+    /// Opens the final chunk; fails if the stream was truncated before it
+    pub fn decrypt_last(mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        self.open(chunk, true)
+    }
+
+    fn open(&mut self, chunk: &[u8], last: bool) -> Result<Vec<u8>> {
+        let index = self.0.counter;
+        let nonce = self.0.next_nonce(last)?;
+        self.0.cipher
+            .decrypt(Nonce::from_slice(&nonce), Payload { msg: chunk, aad: &self.0.aad })
+            .map_err(|_| anyhow::anyhow!("Chunk {} failed to authenticate: the stream was altered, reordered or truncated", index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seal(key: &[u8; KEY_LEN], plaintext: &[u8]) -> Result<(StreamHeader, Vec<Vec<u8>>)> {
+        let mut encryptor = StreamEncryptor::new(key, b"attachment");
+        let mut chunks = Vec::new();
+        let mut rest = plaintext;
+        while rest.len() > CHUNK_SIZE {
+            let (chunk, tail) = rest.split_at(CHUNK_SIZE);
+            chunks.push(encryptor.encrypt_next(chunk)?);
+            rest = tail;
+        }
+        let header = encryptor.header();
+        chunks.push(encryptor.encrypt_last(rest)?);
+        Ok((header, chunks))
+    }
+
+    fn open(key: &[u8; KEY_LEN], header: StreamHeader, chunks: &[Vec<u8>]) -> Result<Vec<u8>> {
+        let mut decryptor = StreamDecryptor::new(key, header, b"attachment");
+        let mut plaintext = Vec::new();
+        let (last, rest) = chunks.split_last().expect("at least one chunk");
+        for chunk in rest {
+            plaintext.extend(decryptor.decrypt_next(chunk)?);
+        }
+        plaintext.extend(decryptor.decrypt_last(last)?);
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn test_stream_roundtrip() -> Result<()> {
+        let key = [3u8; KEY_LEN];
+        for len in [0, 1, CHUNK_SIZE, CHUNK_SIZE + 1, 3 * CHUNK_SIZE - 7] {
+            let plaintext: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let (header, chunks) = seal(&key, &plaintext)?;
+            assert_eq!(chunks.len(), len.saturating_sub(1) / CHUNK_SIZE + 1);
+            assert_eq!(chunks[0].len(), len.min(CHUNK_SIZE) + TAG_LEN);
+            assert_eq!(StreamHeader::parse(&header.to_bytes())?, header);
+            assert_eq!(open(&key, header, &chunks)?, plaintext);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncation_and_reordering_are_detected() -> Result<()> {
+        let key = [5u8; KEY_LEN];
+        let plaintext = vec![9u8; 3 * CHUNK_SIZE];
+        let (header, chunks) = seal(&key, &plaintext)?;
+        assert_eq!(chunks.len(), 3);
+
+        // Dropping the last chunk leaves a stream whose final chunk was not sealed as last
+        assert!(open(&key, header, &chunks[..2]).is_err());
+
+        let mut reordered = chunks.clone();
+        reordered.swap(0, 1);
+        assert!(open(&key, header, &reordered).is_err());
+
+        // Another stream's chunks, a different key, or different associated data do not open
+        let (other_header, _) = seal(&key, &plaintext)?;
+        assert!(open(&key, other_header, &chunks).is_err());
+        assert!(open(&[6u8; KEY_LEN], header, &chunks).is_err());
+        let mut decryptor = StreamDecryptor::new(&key, header, b"other");
+        assert!(decryptor.decrypt_next(&chunks[0]).is_err());
+
+        assert!(StreamHeader::parse(&[STREAM_VERSION + 1; STREAM_HEADER_LEN]).is_err());
+        Ok(())
+    }
+}
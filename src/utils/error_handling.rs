@@ -1,7 +1,12 @@
 // src/utils/error_handling.rs
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
 use thiserror::Error;
 use serde::Serialize;
 
+use crate::quantum_encryption::envelope::EnvelopeError;
+
 /// Application-specific error types
 #[derive(Error, Debug)]
 pub enum AppError {
@@ -32,6 +37,9 @@ pub enum AppError {
     #[error("Not found: {0}")]
     NotFoundError(String),
 
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
+
     #[error("Internal server error: {0}")]
     InternalServerError(String),
 
@@ -49,6 +57,13 @@ impl From<anyhow::Error> for AppError {
     }
 }
 
+/// Malformed ciphertext envelopes are reported as decryption failures
+impl From<EnvelopeError> for AppError {
+    fn from(error: EnvelopeError) -> Self {
+        AppError::DecryptionError(error.to_string())
+    }
+}
+
 /// HTTP response representation of an AppError
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
@@ -89,6 +104,9 @@ impl From<AppError> for ErrorResponse {
             AppError::NotFoundError(_) => {
                 ErrorResponse::new("NOT_FOUND", &error.to_string(), 404)
             }
+            AppError::PayloadTooLarge(_) => {
+                ErrorResponse::new("PAYLOAD_TOO_LARGE", &error.to_string(), 413)
+            }
             AppError::DatabaseError(_) => {
                 ErrorResponse::new("DATABASE_ERROR", &error.to_string(), 500)
             }
@@ -117,6 +135,19 @@ impl From<AppError> for ErrorResponse {
     }
 }
 
+/// Renders an AppError as a JSON ErrorResponse so handlers can return it directly
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let body = ErrorResponse::from(self);
+        let status = StatusCode::from_u16(body.status_code)
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        if status.is_server_error() {
+            tracing::error!("Request failed: {}", body.message);
+        }
+        (status, Json(body)).into_response()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -143,11 +174,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_envelope_error_conversion() {
+        let app_error: AppError = EnvelopeError::UnsupportedVersion(7).into();
+        assert!(matches!(app_error, AppError::DecryptionError(ref msg) if msg.contains("version: 7")));
+    }
+
     #[test]
     fn test_specific_error_types() {
         let errors = vec![
             (AppError::NotFoundError("User not found".to_string()), "NOT_FOUND", 404),
             (AppError::ValidationError("Invalid email".to_string()), "VALIDATION_ERROR", 400),
+            (AppError::PayloadTooLarge("Attachment too big".to_string()), "PAYLOAD_TOO_LARGE", 413),
             (AppError::EncryptionError("Key failure".to_string()), "ENCRYPTION_ERROR", 500),
         ];
 
@@ -157,4 +195,13 @@ mod tests {
             assert_eq!(response.status_code, expected_code);
         }
     }
+
+    #[test]
+    fn test_into_response_status() {
+        let response = AppError::NotFoundError("Email not found".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = AppError::ValidationError("Empty subject".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
 }
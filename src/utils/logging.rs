@@ -17,8 +17,10 @@ pub fn init_logging() {
         .with(env_filter)
         .with(fmt_layer);
 
-    set_global_default(subscriber)
-        .expect("Failed to set global default subscriber");
+    // A subscriber may already be installed (e.g. by another test), keep it
+    if set_global_default(subscriber).is_err() {
+        return;
+    }
 
     tracing::info!("Logging system initialized successfully");
 }
@@ -0,0 +1,177 @@
+// src/websocket/fan_out.rs
+use std::sync::Arc;
+
+use sqlx::PgPool;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+use crate::websocket::hub::Hub;
+use crate::websocket::presence;
+use crate::websocket::server::{WebSocketMessage, WebSocketMessageType};
+
+/// Delivers the events published on the WebSocket server's broadcast channel to their audience
+///
+/// Presence changes go to the users who have the subject in their contacts, unless the
+/// subject hides their presence, and events about a user's keys go to that user's own
+/// connections only. The sender of every published event is the verified user it is about.
+pub struct FanOut {
+    hub: Arc<Hub>,
+    db_pool: PgPool,
+}
+
+impl FanOut {
+    pub fn new(hub: Arc<Hub>, db_pool: PgPool) -> Self {
+        Self { hub, db_pool }
+    }
+
+    /// Delivers events from `receiver` on its own task until every sender is dropped
+    pub fn spawn(self, receiver: broadcast::Receiver<WebSocketMessage>) -> JoinHandle<()> {
+        tokio::spawn(async move { self.run(receiver).await })
+    }
+
+    async fn run(&self, mut receiver: broadcast::Receiver<WebSocketMessage>) {
+        loop {
+            match receiver.recv().await {
+                Ok(message) => self.deliver(&message).await,
+                // The channel keeps the newest events, so carry on with those; clients
+                // that missed a presence change see the current state when they reconnect
+                Err(RecvError::Lagged(skipped)) => warn!("WebSocket fan-out fell behind, {} events were dropped", skipped),
+                Err(RecvError::Closed) => break,
+            }
+        }
+        debug!("WebSocket fan-out stopped");
+    }
+
+    async fn deliver(&self, message: &WebSocketMessage) {
+        let audience = match self.audience(message).await {
+            Ok(audience) => audience,
+            Err(e) => {
+                error!("Failed to resolve the audience of {:?}: {}", message.message_type, e);
+                return;
+            }
+        };
+
+        let mut delivered = 0;
+        for user_id in &audience {
+            delivered += self.hub.send_to_user(*user_id, message).await;
+        }
+        debug!("Delivered {:?} to {} connections of {} users", message.message_type, delivered, audience.len());
+    }
+
+    /// The users an event is delivered to
+    pub async fn audience(&self, message: &WebSocketMessage) -> Result<Vec<Uuid>, sqlx::Error> {
+        let Some(subject) = message.sender_id else {
+            return Ok(message.recipient_id.into_iter().collect());
+        };
+
+        match message.message_type {
+            WebSocketMessageType::UserOnline | WebSocketMessageType::UserOffline => {
+                if !presence::is_shared(&self.db_pool, subject).await? {
+                    return Ok(Vec::new());
+                }
+                presence::watchers(&self.db_pool, subject).await
+            }
+            WebSocketMessageType::KeyRotation |
+            WebSocketMessageType::KeyRevocation |
+            WebSocketMessageType::EncryptionStatus => Ok(vec![subject]),
+            _ => Ok(message.recipient_id.into_iter().collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_support::{add_contact, insert_user, login, test_state};
+    use crate::websocket::test_support::{frame, listen, next_message};
+    use futures_util::SinkExt;
+    use time::OffsetDateTime;
+    use tokio_tungstenite::connect_async;
+    use tokio_tungstenite::tungstenite::Message;
+
+    fn event(message_type: WebSocketMessageType, sender_id: Uuid) -> WebSocketMessage {
+        WebSocketMessage {
+            message_type,
+            sender_id: Some(sender_id),
+            recipient_id: None,
+            payload: serde_json::Value::Null,
+            timestamp: OffsetDateTime::now_utc(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_events_reach_their_audience() -> anyhow::Result<()> {
+        let state = test_state().await;
+        let alice = insert_user(&state).await;
+        let bob = insert_user(&state).await;
+        let carol = insert_user(&state).await;
+        add_contact(&state, &alice, &bob).await;
+        add_contact(&state, &bob, &alice).await;
+        add_contact(&state, &carol, &bob).await;
+        let url = listen(state.websocket.clone()).await;
+
+        let (mut alice_client, _) = connect_async(format!("{}?token={}", url, login(&state, &alice).await)).await?;
+        let (mut carol_client, _) = connect_async(format!("{}?token={}", url, login(&state, &carol).await)).await?;
+        next_message(&mut alice_client).await.unwrap();
+        next_message(&mut carol_client).await.unwrap();
+
+        // Bob coming online is only announced to alice, his mutual contact, not to carol
+        let bob_token = login(&state, &bob).await;
+        let (mut bob_client, _) = connect_async(format!("{}?token={}", url, bob_token)).await?;
+        assert_eq!(next_message(&mut bob_client).await.unwrap().message_type, WebSocketMessageType::Authenticated);
+        let online = next_message(&mut alice_client).await.unwrap();
+        assert_eq!((online.message_type, online.sender_id), (WebSocketMessageType::UserOnline, Some(bob.user_id)));
+
+        // Key events stay with their owner, even when addressed to someone else
+        bob_client.send(frame(WebSocketMessageType::KeyRotation, None, Some(alice.user_id), serde_json::Value::Null)).await?;
+        let rotation = next_message(&mut bob_client).await.unwrap();
+        assert_eq!((rotation.message_type, rotation.sender_id), (WebSocketMessageType::KeyRotation, Some(bob.user_id)));
+
+        // Bob's second connection does not change his presence, closing the last one does
+        let (mut bob_tablet, _) = connect_async(format!("{}?token={}", url, bob_token)).await?;
+        next_message(&mut bob_tablet).await.unwrap();
+        bob_tablet.close(None).await?;
+        bob_client.close(None).await?;
+        let offline = next_message(&mut alice_client).await.unwrap();
+        assert_eq!((offline.message_type, offline.sender_id), (WebSocketMessageType::UserOffline, Some(bob.user_id)));
+        let last_seen_at: Option<OffsetDateTime> = sqlx::query_scalar("SELECT last_seen_at FROM users WHERE user_id = $1")
+            .bind(bob.user_id)
+            .fetch_one(&state.db_pool)
+            .await?;
+        assert!(last_seen_at.is_some());
+        assert!(!offline.payload["last_seen_at"].is_null());
+
+        // Carol saw none of it: the marker is the first thing she receives
+        state.hub.send_to_user(carol.user_id, &event(WebSocketMessageType::EncryptionStatus, carol.user_id)).await;
+        assert_eq!(next_message(&mut carol_client).await.unwrap().message_type, WebSocketMessageType::EncryptionStatus);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lagged_fan_out_keeps_delivering() -> anyhow::Result<()> {
+        let hub = Arc::new(Hub::new());
+        let owner = Uuid::new_v4();
+        let mut client = hub.connect_test_client(owner).await;
+        let (sender, receiver) = broadcast::channel(2);
+        for _ in 0..5 {
+            sender.send(event(WebSocketMessageType::EncryptionStatus, owner))?;
+        }
+        sender.send(event(WebSocketMessageType::KeyRotation, owner))?;
+        drop(sender);
+
+        FanOut::new(hub.clone(), PgPool::connect_lazy("postgres://localhost/unused")?).spawn(receiver).await?;
+
+        // The receiver skips what it missed and delivers the two newest events
+        let mut received = Vec::new();
+        while let Ok(Message::Text(text)) = client.try_recv() {
+            received.push(serde_json::from_str::<WebSocketMessage>(&text)?.message_type);
+        }
+        assert_eq!(received, vec![
+            WebSocketMessageType::EncryptionStatus,
+            WebSocketMessageType::KeyRotation,
+        ]);
+        Ok(())
+    }
+}
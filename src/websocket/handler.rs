@@ -0,0 +1,135 @@
+// src/websocket/handler.rs
+use std::io;
+use std::sync::Arc;
+
+use axum::extract::ws::{self, WebSocket, WebSocketUpgrade};
+use axum::extract::Query;
+use axum::http::HeaderMap;
+use axum::response::Response;
+use axum::routing::get;
+use axum::{Extension, Router};
+use futures_util::{future, Sink, SinkExt, Stream, StreamExt};
+use serde::Deserialize;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tracing::error;
+
+use crate::api::auth::bearer_token;
+use crate::AppState;
+
+/// Route for `/ws`, served by the HTTP server next to `/api`
+pub fn routes() -> Router {
+    Router::new().route("/ws", get(upgrade))
+}
+
+/// Query parameters of the upgrade request
+#[derive(Debug, Deserialize)]
+pub struct UpgradeQuery {
+    pub token: Option<String>,
+}
+
+/// Upgrades the request and hands the socket to the state's WebSocket server
+///
+/// The session token is taken from the `token` query parameter or the `Authorization`
+/// header; browsers can set neither, so without one the client sends an `authenticate` frame.
+async fn upgrade(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(query): Query<UpgradeQuery>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let token = query.token
+        .filter(|token| !token.is_empty())
+        .or_else(|| bearer_token(&headers).map(str::to_string));
+
+    upgrade.on_upgrade(move |socket| async move {
+        if let Err(e) = state.websocket.serve_socket(into_tungstenite(socket), token).await {
+            error!("Error handling WebSocket connection: {}", e);
+        }
+    })
+}
+
+/// Adapts an axum socket to the tungstenite messages the WebSocket server works with
+// tungstenite's error type is large, but it is what the server's streams carry
+#[allow(clippy::result_large_err)]
+fn into_tungstenite(
+    socket: WebSocket,
+) -> impl Stream<Item = Result<Message, tungstenite::Error>> + Sink<Message, Error = tungstenite::Error> {
+    socket
+        .map(|result| result.map(from_axum_message).map_err(io_error))
+        .sink_map_err(io_error)
+        .with(|message| future::ready(Ok(into_axum_message(message))))
+}
+
+fn from_axum_message(message: ws::Message) -> Message {
+    match message {
+        ws::Message::Text(text) => Message::Text(text),
+        ws::Message::Binary(data) => Message::Binary(data),
+        ws::Message::Ping(data) => Message::Ping(data),
+        ws::Message::Pong(data) => Message::Pong(data),
+        ws::Message::Close(frame) => Message::Close(frame.map(|frame| CloseFrame {
+            code: frame.code.into(),
+            reason: frame.reason,
+        })),
+    }
+}
+
+fn into_axum_message(message: Message) -> ws::Message {
+    match message {
+        Message::Text(text) => ws::Message::Text(text),
+        Message::Binary(data) => ws::Message::Binary(data),
+        Message::Ping(data) => ws::Message::Ping(data),
+        Message::Pong(data) => ws::Message::Pong(data),
+        Message::Close(frame) => ws::Message::Close(frame.map(|frame| ws::CloseFrame {
+            code: frame.code.into(),
+            reason: frame.reason,
+        })),
+        // Raw frames are never produced by the server
+        Message::Frame(frame) => ws::Message::Binary(frame.into_data()),
+    }
+}
+
+fn io_error(error: axum::Error) -> tungstenite::Error {
+    tungstenite::Error::Io(io::Error::other(error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_support::{insert_user, login, test_state};
+    use crate::websocket::server::WebSocketMessageType;
+    use crate::websocket::test_support::{frame, next_message};
+    use tokio_tungstenite::connect_async;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::http::{header, HeaderValue};
+
+    #[tokio::test]
+    async fn test_ws_route_authenticates_and_shuts_down() -> anyhow::Result<()> {
+        let state = test_state().await;
+        let alice = insert_user(&state).await;
+        let token = login(&state, &alice).await;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("ws://{}/ws", listener.local_addr()?);
+        let app = routes().layer(Extension(state.clone()));
+        tokio::spawn(axum::Server::from_tcp(listener)?.serve(app.into_make_service()));
+
+        let (mut query_client, _) = connect_async(format!("{}?token={}", url, token)).await?;
+        assert_eq!(next_message(&mut query_client).await.unwrap().recipient_id, Some(alice.user_id));
+
+        let mut request = url.as_str().into_client_request()?;
+        request.headers_mut().insert(header::AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", token))?);
+        let (mut header_client, _) = connect_async(request).await?;
+        assert_eq!(next_message(&mut header_client).await.unwrap().message_type, WebSocketMessageType::Authenticated);
+
+        let (mut frame_client, _) = connect_async(&url).await?;
+        frame_client.send(frame(WebSocketMessageType::Authenticate, None, None, serde_json::json!({ "token": "wrong" }))).await?;
+        assert_eq!(next_message(&mut frame_client).await.unwrap().message_type, WebSocketMessageType::Error);
+        assert!(next_message(&mut frame_client).await.is_none());
+
+        state.websocket.shutdown();
+        assert!(next_message(&mut query_client).await.is_none());
+        assert!(next_message(&mut header_client).await.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+// src/websocket/hub.rs
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, error};
+use uuid::Uuid;
+
+use crate::websocket::connection::{ClientConnection, ConnectionRegistry};
+use crate::websocket::server::WebSocketMessage;
+
+/// Pushes messages to connected clients, from the WebSocket server and the HTTP handlers alike
+///
+/// Messages are serialized once and queued on each target connection; a user with
+/// several tabs or devices open gets the message on every one of them.
+#[derive(Default)]
+pub struct Hub {
+    registry: ConnectionRegistry,
+}
+
+impl Hub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The connections the hub delivers to
+    pub fn registry(&self) -> &ConnectionRegistry {
+        &self.registry
+    }
+
+    /// Sends a message to every connection of the user and returns how many it was queued on
+    pub async fn send_to_user(&self, user_id: Uuid, message: &WebSocketMessage) -> usize {
+        let connections = self.registry.get_by_user(user_id).await;
+        debug!("Sending {:?} to {} connections of user {}", message.message_type, connections.len(), user_id);
+        Self::deliver(&connections, message)
+    }
+
+    /// Sends a message to every open connection and returns how many it was queued on
+    pub async fn broadcast(&self, message: &WebSocketMessage) -> usize {
+        let connections = self.registry.all().await;
+        debug!("Broadcasting {:?} to {} connections", message.message_type, connections.len());
+        Self::deliver(&connections, message)
+    }
+
+    /// Checks whether the user has at least one open connection
+    pub async fn is_online(&self, user_id: Uuid) -> bool {
+        self.registry.is_online(user_id).await
+    }
+
+    /// Returns which of the given users are online
+    pub async fn presence(&self, user_ids: &[Uuid]) -> Vec<Uuid> {
+        let mut online = Vec::new();
+        for &user_id in user_ids {
+            if self.registry.is_online(user_id).await {
+                online.push(user_id);
+            }
+        }
+        online
+    }
+
+    /// Registers a connection for the user without a socket and returns what it is sent
+    #[cfg(test)]
+    pub(crate) async fn connect_test_client(&self, user_id: Uuid) -> tokio::sync::mpsc::UnboundedReceiver<Message> {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        self.registry.add(ClientConnection::new(user_id, sender)).await;
+        receiver
+    }
+
+    fn deliver(connections: &[ClientConnection], message: &WebSocketMessage) -> usize {
+        let message_json = match serde_json::to_string(message) {
+            Ok(json) => json,
+            Err(e) => {
+                error!("Failed to serialize message: {}", e);
+                return 0;
+            }
+        };
+        let ws_message = Message::Text(message_json);
+
+        connections.iter()
+            .filter(|connection| match connection.send(ws_message.clone()) {
+                Ok(()) => true,
+                // The connection is closing; its task removes it from the registry
+                Err(e) => {
+                    error!("{}", e);
+                    false
+                }
+            })
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::websocket::server::WebSocketMessageType;
+    use time::OffsetDateTime;
+
+    #[tokio::test]
+    async fn test_send_to_user_and_broadcast() {
+        let hub = Hub::new();
+        let alice = Uuid::new_v4();
+        let bob = Uuid::new_v4();
+        let mut alice_phone = hub.connect_test_client(alice).await;
+        let mut alice_laptop = hub.connect_test_client(alice).await;
+        let mut bob_client = hub.connect_test_client(bob).await;
+
+        let message = WebSocketMessage {
+            message_type: WebSocketMessageType::NewEmail,
+            sender_id: Some(bob),
+            recipient_id: Some(alice),
+            payload: serde_json::Value::Null,
+            timestamp: OffsetDateTime::now_utc(),
+        };
+        assert_eq!(hub.send_to_user(alice, &message).await, 2);
+        assert!(alice_phone.try_recv().is_ok());
+        assert!(alice_laptop.try_recv().is_ok());
+        assert!(bob_client.try_recv().is_err());
+
+        drop(alice_laptop);
+        assert_eq!(hub.broadcast(&message).await, 2);
+        assert!(bob_client.try_recv().is_ok());
+
+        let carol = Uuid::new_v4();
+        assert_eq!(hub.presence(&[alice, carol, bob]).await, vec![alice, bob]);
+        assert_eq!(hub.send_to_user(carol, &message).await, 0);
+    }
+}
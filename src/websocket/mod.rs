@@ -1,2 +1,54 @@
 pub mod server;
 pub mod connection;
+pub mod fan_out;
+pub mod handler;
+pub mod hub;
+pub mod presence;
+
+#[cfg(test)]
+pub(crate) mod test_support {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use futures_util::{Stream, StreamExt};
+    use time::OffsetDateTime;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::{self, Message};
+    use uuid::Uuid;
+
+    use super::server::{WebSocketMessage, WebSocketMessageType, WebSocketServer};
+
+    /// Serves the WebSocket server on an ephemeral port and returns its URL
+    pub async fn listen(server: Arc<WebSocketServer>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}/ws", listener.local_addr().unwrap());
+        tokio::spawn(async move { server.serve(listener).await });
+        url
+    }
+
+    /// Waits for the next message, or `None` once the server closed the connection
+    pub async fn next_message<S>(client: &mut S) -> Option<WebSocketMessage>
+    where
+        S: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+    {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(Ok(message)) = client.next().await {
+                if let Message::Text(text) = message {
+                    return Some(serde_json::from_str(&text).unwrap());
+                }
+            }
+            None
+        }).await.expect("Timed out waiting for a WebSocket message")
+    }
+
+    /// A client frame as the frontend sends it
+    pub fn frame(message_type: WebSocketMessageType, sender_id: Option<Uuid>, recipient_id: Option<Uuid>, payload: serde_json::Value) -> Message {
+        Message::Text(serde_json::to_string(&WebSocketMessage {
+            message_type,
+            sender_id,
+            recipient_id,
+            payload,
+            timestamp: OffsetDateTime::now_utc(),
+        }).unwrap())
+    }
+}
@@ -0,0 +1,77 @@
+// src/websocket/presence.rs
+use sqlx::PgPool;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::websocket::server::{WebSocketMessage, WebSocketMessageType};
+
+/// The `notification_settings` type that controls whether a user shares their presence
+pub const PRESENCE_SETTING: &str = "presence";
+
+/// Checks whether the user shares their presence; users without the setting do
+pub async fn is_shared(db_pool: &PgPool, user_id: Uuid) -> Result<bool, sqlx::Error> {
+    let enabled: Option<bool> = sqlx::query_scalar(
+        "SELECT is_enabled FROM notification_settings WHERE user_id = $1 AND notification_type = $2",
+    )
+        .bind(user_id)
+        .bind(PRESENCE_SETTING)
+        .fetch_optional(db_pool)
+        .await?;
+    Ok(enabled.unwrap_or(true))
+}
+
+/// Stores whether the user shares their presence
+pub async fn set_shared(db_pool: &PgPool, user_id: Uuid, shared: bool) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO notification_settings (setting_id, user_id, notification_type, is_enabled, updated_at)
+         VALUES ($1, $2, $3, $4, $5)
+         ON CONFLICT (user_id, notification_type) DO UPDATE SET is_enabled = $4, updated_at = $5",
+    )
+        .bind(Uuid::new_v4())
+        .bind(user_id)
+        .bind(PRESENCE_SETTING)
+        .bind(shared)
+        .bind(OffsetDateTime::now_utc())
+        .execute(db_pool)
+        .await?;
+    Ok(())
+}
+
+/// The users who may be told about the presence of `user_id`
+///
+/// Only mutual contacts qualify: a watcher must have `user_id` in their contacts, and
+/// `user_id` must have the watcher in theirs.
+pub async fn watchers(db_pool: &PgPool, user_id: Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+    sqlx::query_scalar(
+        "SELECT DISTINCT c.user_id FROM contacts c
+         WHERE c.contact_user_id = $1 AND c.user_id <> $1
+           AND EXISTS (SELECT 1 FROM contacts m WHERE m.user_id = $1 AND m.contact_user_id = c.user_id)",
+    )
+        .bind(user_id)
+        .fetch_all(db_pool)
+        .await
+}
+
+/// Records when the user's last connection closed
+pub async fn record_last_seen(db_pool: &PgPool, user_id: Uuid, last_seen_at: OffsetDateTime) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET last_seen_at = $2 WHERE user_id = $1")
+        .bind(user_id)
+        .bind(last_seen_at)
+        .execute(db_pool)
+        .await?;
+    Ok(())
+}
+
+/// A presence change of `user_id`, carrying when they were last seen once they go offline
+pub fn presence_message(user_id: Uuid, online: bool, last_seen_at: Option<OffsetDateTime>) -> WebSocketMessage {
+    WebSocketMessage {
+        message_type: if online { WebSocketMessageType::UserOnline } else { WebSocketMessageType::UserOffline },
+        sender_id: Some(user_id),
+        recipient_id: None,
+        payload: match last_seen_at {
+            Some(last_seen_at) => serde_json::json!({ "last_seen_at": last_seen_at }),
+            None => serde_json::Value::Null,
+        },
+        timestamp: OffsetDateTime::now_utc(),
+    }
+}
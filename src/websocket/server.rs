@@ -1,36 +1,42 @@
 // src/websocket/server.rs
-use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
 use anyhow::Result;
-use futures_util::{SinkExt, StreamExt};
+use futures_util::{Sink, SinkExt, Stream, StreamExt};
 use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{broadcast, mpsc};
-use tokio_tungstenite::tungstenite::Message;
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::{self, Message};
 use uuid::Uuid;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
+use crate::api::auth::authenticate_session;
 use crate::config::ServerConfig;
+use crate::database::models::User;
+use crate::utils::error_handling::AppError;
+use crate::websocket::connection::ClientConnection;
+use crate::websocket::fan_out::FanOut;
+use crate::websocket::hub::Hub;
+use crate::websocket::presence;
 use time::OffsetDateTime;
 
+/// How long a connection upgraded without a session token has to send its `authenticate` frame
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Events buffered for the fan-out task before it starts dropping the oldest
+const EVENT_BUFFER_SIZE: usize = 100;
+
 type ConnectionId = Uuid;
 type UserId = Uuid;
-type Connections = Arc<Mutex<HashMap<ConnectionId, WebSocketConnection>>>;
-type UserConnections = Arc<Mutex<HashMap<UserId, Vec<ConnectionId>>>>;
 
 pub struct WebSocketServer {
     config: ServerConfig,
-    connections: Connections,
-    user_connections: UserConnections,
+    db_pool: PgPool,
+    hub: Arc<Hub>,
     message_tx: broadcast::Sender<WebSocketMessage>,
-}
-
-#[derive(Clone)]
-pub struct WebSocketConnection {
-    id: ConnectionId,
-    user_id: Option<UserId>,
-    sender: mpsc::UnboundedSender<Message>,
+    shutdown_tx: watch::Sender<bool>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,34 +58,63 @@ pub enum WebSocketMessageType {
     UserOffline,
     EncryptionStatus,
     KeyRotation,
+    KeyRevocation,
+    /// First frame of a connection upgraded without a session token, `{"token": "..."}`
+    Authenticate,
+    /// Sent once the session token was verified, with the verified user as recipient
+    Authenticated,
     Error,
 }
 
 impl WebSocketServer {
-    pub fn new(config: &ServerConfig) -> Self {
-        let (tx, _) = broadcast::channel(100);
-        info!("Initialized WebSocket server with buffer size 100");
+    /// Creates the server; `db_pool` is used to verify the session token of each connection,
+    /// and authenticated connections are registered with `hub`
+    ///
+    /// Also spawns the task fanning published events out to the hub, so it must be called
+    /// from within a Tokio runtime.
+    pub fn new(config: &ServerConfig, db_pool: PgPool, hub: Arc<Hub>) -> Self {
+        let (tx, rx) = broadcast::channel(EVENT_BUFFER_SIZE);
+        FanOut::new(Arc::clone(&hub), db_pool.clone()).spawn(rx);
+        info!("Initialized WebSocket server with buffer size {}", EVENT_BUFFER_SIZE);
         Self {
             config: config.clone(),
-            connections: Arc::new(Mutex::new(HashMap::new())),
-            user_connections: Arc::new(Mutex::new(HashMap::new())),
+            db_pool,
+            hub,
             message_tx: tx,
+            shutdown_tx: watch::channel(false).0,
         }
     }
 
+    /// Runs the standalone listener on `websocket_port`, for deployments that keep
+    /// WebSockets off the HTTP port; `/ws` on the HTTP server does not need it
     pub async fn start(&self) -> Result<()> {
-        let addr = format!("{}:{}", self.config.host, self.config.websocket_port);
+        let port = self.config.websocket_port
+            .ok_or_else(|| anyhow::anyhow!("WEBSOCKET_PORT is not set"))?;
+        let addr = format!("{}:{}", self.config.host, port);
         let listener = TcpListener::bind(&addr).await?;
         info!("WebSocket server listening on: {}", addr);
+        self.serve(listener).await
+    }
 
-        while let Ok((stream, addr)) = listener.accept().await {
+    /// Accepts WebSocket connections on an already bound listener until `shutdown` is called
+    pub async fn serve(&self, listener: TcpListener) -> Result<()> {
+        let mut shutdown = self.shutdown_tx.subscribe();
+        loop {
+            let (stream, addr) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(_) => break,
+                },
+                _ = stopped(&mut shutdown) => break,
+            };
             info!("New WebSocket connection from: {}", addr);
-            let connections = Arc::clone(&self.connections);
-            let user_connections = Arc::clone(&self.user_connections);
+            let db_pool = self.db_pool.clone();
+            let hub = Arc::clone(&self.hub);
             let message_tx = self.message_tx.clone();
+            let shutdown = self.shutdown_tx.subscribe();
 
             tokio::spawn(async move {
-                if let Err(e) = Self::handle_connection(stream, connections, user_connections, message_tx).await {
+                if let Err(e) = Self::handle_connection(stream, db_pool, hub, message_tx, shutdown).await {
                     error!("Error handling WebSocket connection from {}: {}", addr, e);
                 }
             });
@@ -88,31 +123,109 @@ impl WebSocketServer {
         Ok(())
     }
 
+    /// Runs a connection upgraded elsewhere, such as by the `/ws` route, until it closes
+    ///
+    /// `token` is the session token sent with the upgrade request; without one the
+    /// client has to send an `authenticate` frame first.
+    pub async fn serve_socket<S>(&self, socket: S, token: Option<String>) -> Result<()>
+    where
+        S: Stream<Item = Result<Message, tungstenite::Error>> + Sink<Message, Error = tungstenite::Error> + Send + 'static,
+    {
+        Self::run_connection(
+            socket,
+            token,
+            self.db_pool.clone(),
+            Arc::clone(&self.hub),
+            self.message_tx.clone(),
+            self.shutdown_tx.subscribe(),
+        ).await
+    }
+
+    /// Stops the standalone listener and closes every open connection
+    pub fn shutdown(&self) {
+        info!("Closing WebSocket connections");
+        self.shutdown_tx.send_replace(true);
+    }
+
     async fn handle_connection(
         stream: TcpStream,
-        connections: Connections,
-        user_connections: UserConnections,
+        db_pool: PgPool,
+        hub: Arc<Hub>,
         message_tx: broadcast::Sender<WebSocketMessage>,
+        shutdown: watch::Receiver<bool>,
     ) -> Result<()> {
-        let ws_stream = tokio_tungstenite::accept_async(stream).await?;
-        let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+        let mut query_token = None;
+        // The callback's error type is tungstenite's HTTP error response
+        #[allow(clippy::result_large_err)]
+        let ws_stream = tokio_tungstenite::accept_hdr_async(stream, |request: &Request, response: Response| {
+            query_token = token_from_query(request.uri().query());
+            Ok(response)
+        }).await?;
+        Self::run_connection(ws_stream, query_token, db_pool, hub, message_tx, shutdown).await
+    }
+
+    async fn run_connection<S>(
+        socket: S,
+        token: Option<String>,
+        db_pool: PgPool,
+        hub: Arc<Hub>,
+        message_tx: broadcast::Sender<WebSocketMessage>,
+        mut shutdown: watch::Receiver<bool>,
+    ) -> Result<()>
+    where
+        S: Stream<Item = Result<Message, tungstenite::Error>> + Sink<Message, Error = tungstenite::Error> + Send + 'static,
+    {
+        let (mut ws_sender, mut ws_receiver) = socket.split();
+
+        let user = match Self::authenticate(&db_pool, token, &mut ws_receiver).await {
+            Ok(user) => user,
+            Err(e) => {
+                warn!("Rejected WebSocket connection: {}", e);
+                let _ = ws_sender.send(error_frame(&e.to_string())).await;
+                let _ = ws_sender.close().await;
+                return Ok(());
+            }
+        };
+        let user_id = user.user_id;
 
-        let connection_id = Uuid::new_v4();
         let (sender, mut receiver) = mpsc::unbounded_channel();
+        let connection = ClientConnection::new(user_id, sender);
+        let connection_id = connection.id;
+        let authenticated = WebSocketMessage {
+            message_type: WebSocketMessageType::Authenticated,
+            sender_id: None,
+            recipient_id: Some(user_id),
+            payload: serde_json::json!({ "username": user.username }),
+            timestamp: OffsetDateTime::now_utc(),
+        };
+        // The receiver is alive until the send task below drops it
+        let _ = connection.send(Message::Text(serde_json::to_string(&authenticated)?));
 
-        // Add connection to the connections map
-        {
-            let mut connections_lock = connections.lock().await;
-            connections_lock.insert(connection_id, WebSocketConnection {
-                id: connection_id,
-                user_id: None,
-                sender,
+        // Only the user's first connection changes their presence
+        if hub.registry().add(connection).await {
+            // Ignore send error as it's not critical
+            let _ = message_tx.send(WebSocketMessage {
+                message_type: WebSocketMessageType::UserOnline,
+                sender_id: Some(user_id),
+                recipient_id: None,
+                payload: serde_json::json!({ "username": user.username }),
+                timestamp: OffsetDateTime::now_utc(),
             });
-            debug!("Added connection {} to connections", connection_id);
         }
 
         let send_task = tokio::spawn(async move {
-            while let Some(message) = receiver.recv().await {
+            loop {
+                let message = tokio::select! {
+                    message = receiver.recv() => match message {
+                        Some(message) => message,
+                        None => break,
+                    },
+                    _ = stopped(&mut shutdown) => {
+                        debug!("Closing connection {} for shutdown", connection_id);
+                        let _ = ws_sender.close().await;
+                        break;
+                    }
+                };
                 if let Err(e) = ws_sender.send(message).await {
                     error!("Error sending WebSocket message for connection {}: {}", connection_id, e);
                     break;
@@ -120,8 +233,6 @@ impl WebSocketServer {
             }
         });
 
-        let connections_clone = Arc::clone(&connections);
-        let user_connections_clone = Arc::clone(&user_connections);
         let message_tx_clone = message_tx.clone();
 
         let receive_task = tokio::spawn(async move {
@@ -134,9 +245,9 @@ impl WebSocketServer {
                                     debug!("Received message from connection {}: {:?}", connection_id, ws_message.message_type);
                                     Self::process_message(
                                         connection_id,
+                                        user_id,
                                         ws_message,
-                                        Arc::clone(&connections_clone),
-                                        Arc::clone(&user_connections_clone),
+                                        &hub,
                                         message_tx_clone.clone(),
                                     ).await;
                                 }
@@ -152,12 +263,7 @@ impl WebSocketServer {
             }
 
             debug!("Connection {} closed, cleaning up", connection_id);
-            Self::remove_connection(
-                connection_id,
-                Arc::clone(&connections_clone),
-                Arc::clone(&user_connections_clone),
-                message_tx_clone.clone()
-            ).await;
+            Self::remove_connection(connection_id, &db_pool, &hub, message_tx_clone.clone()).await;
         });
 
         tokio::select! {
@@ -168,39 +274,55 @@ impl WebSocketServer {
         Ok(())
     }
 
+    /// Resolves the connection's user from the token sent with the upgrade request or its first frame
+    async fn authenticate<S>(db_pool: &PgPool, token: Option<String>, ws_receiver: &mut S) -> Result<User, AppError>
+    where
+        S: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+    {
+        let token = match token {
+            Some(token) => token,
+            None => tokio::time::timeout(AUTH_TIMEOUT, Self::auth_frame_token(ws_receiver))
+                .await
+                .map_err(|_| AppError::AuthenticationError("Timed out waiting for the authenticate frame".to_string()))??,
+        };
+        authenticate_session(db_pool, &token).await
+    }
+
+    /// Reads the token of the `authenticate` frame, rejecting any other frame sent before it
+    async fn auth_frame_token<S>(ws_receiver: &mut S) -> Result<String, AppError>
+    where
+        S: Stream<Item = Result<Message, tungstenite::Error>> + Unpin,
+    {
+        while let Some(result) = ws_receiver.next().await {
+            let text = match result.map_err(|e| AppError::WebSocketError(e.to_string()))? {
+                Message::Text(text) => text,
+                Message::Ping(_) | Message::Pong(_) => continue,
+                _ => break,
+            };
+            let message = serde_json::from_str::<WebSocketMessage>(&text)
+                .map_err(|e| AppError::ValidationError(format!("Invalid WebSocket message: {}", e)))?;
+            if message.message_type != WebSocketMessageType::Authenticate {
+                return Err(AppError::AuthenticationError(format!(
+                    "Connection must authenticate before sending {:?}", message.message_type
+                )));
+            }
+            return message.payload.get("token")
+                .and_then(|token| token.as_str())
+                .map(str::to_string)
+                .ok_or_else(|| AppError::AuthenticationError("Authenticate frame has no token".to_string()));
+        }
+        Err(AppError::AuthenticationError("Connection closed before authenticating".to_string()))
+    }
+
     async fn process_message(
         connection_id: ConnectionId,
-        message: WebSocketMessage,
-        connections: Connections,
-        user_connections: UserConnections,
+        user_id: UserId,
+        mut message: WebSocketMessage,
+        hub: &Hub,
         message_tx: broadcast::Sender<WebSocketMessage>,
     ) {
-        if message.message_type == WebSocketMessageType::UserOnline && message.sender_id.is_some() {
-            let user_id = message.sender_id.unwrap();
-            debug!("User {} came online on connection {}", user_id, connection_id);
-
-            // Update connection with user_id
-            {
-                let mut connections_lock = connections.lock().await;
-                if let Some(connection) = connections_lock.get_mut(&connection_id) {
-                    connection.user_id = Some(user_id);
-                    debug!("Updated connection {} with user_id {}", connection.id, user_id);
-                }
-            }
-
-            // Add connection to user's connections
-            {
-                let mut user_connections_lock = user_connections.lock().await;
-                user_connections_lock
-                    .entry(user_id)
-                    .or_insert_with(Vec::new)
-                    .push(connection_id);
-            }
-
-            // Ignore send error as it's not critical
-            let _ = message_tx.send(message);
-            return;
-        }
+        // The sender is always the verified user, whatever the client claims
+        message.sender_id = Some(user_id);
 
         match message.message_type {
             WebSocketMessageType::NewEmail |
@@ -208,95 +330,131 @@ impl WebSocketServer {
             WebSocketMessageType::TypingIndicator => {
                 if let Some(recipient_id) = message.recipient_id {
                     debug!("Sending message {:?} to user {}", message.message_type, recipient_id);
-                    Self::send_to_user(recipient_id, &message, &connections, &user_connections).await;
+                    hub.send_to_user(recipient_id, &message).await;
                 }
             },
+            WebSocketMessageType::UserOnline |
+            WebSocketMessageType::UserOffline |
             WebSocketMessageType::KeyRotation |
+            WebSocketMessageType::KeyRevocation |
             WebSocketMessageType::EncryptionStatus => {
-                debug!("Broadcasting message {:?}", message.message_type);
+                // The fan-out task picks the audience
+                debug!("Publishing message {:?}", message.message_type);
                 let _ = message_tx.send(message);
             },
-            _ => {
-                debug!("Broadcasting default message {:?}", message.message_type);
-                let _ = message_tx.send(message);
-            }
+            WebSocketMessageType::Authenticate |
+            WebSocketMessageType::Authenticated |
+            WebSocketMessageType::Error => {
+                debug!("Ignoring {:?} from connection {}", message.message_type, connection_id);
+            },
         }
     }
 
-    async fn send_to_user(
-        user_id: UserId,
-        message: &WebSocketMessage,
-        connections: &Connections,
-        user_connections: &UserConnections,
+    async fn remove_connection(
+        connection_id: ConnectionId,
+        db_pool: &PgPool,
+        hub: &Hub,
+        message_tx: broadcast::Sender<WebSocketMessage>,
     ) {
-        // Get connection IDs for the user
-        let connection_ids = {
-            let user_connections_lock = user_connections.lock().await;
-            user_connections_lock.get(&user_id)
-                .cloned()
-                .unwrap_or_default()
-        };
+        // Handle user offline status if this was their last connection
+        if let Some((user_id, true)) = hub.registry().remove(connection_id).await {
+            debug!("User {} has no more connections, publishing offline status", user_id);
 
-        // Serialize message once to avoid repeated serialization
-        let message_json = match serde_json::to_string(message) {
-            Ok(json) => json,
-            Err(e) => {
-                error!("Failed to serialize message: {}", e);
-                return;
+            let last_seen_at = OffsetDateTime::now_utc();
+            if let Err(e) = presence::record_last_seen(db_pool, user_id, last_seen_at).await {
+                error!("Failed to record when user {} was last seen: {}", user_id, e);
             }
-        };
-        let ws_message = Message::Text(message_json);
-
-        // Send to all connections for this user
-        let mut connections_lock = connections.lock().await;
-        for &conn_id in &connection_ids {
-            if let Some(connection) = connections_lock.get_mut(&conn_id) {
-                if let Err(e) = connection.sender.send(ws_message.clone()) {
-                    error!("Failed to send to connection {} for user {}: {}", conn_id, user_id, e);
-                }
+
+            // Use a generic error handler instead of specific type
+            if let Err(e) = message_tx.send(presence::presence_message(user_id, false, Some(last_seen_at))) {
+                error!("Failed to broadcast offline status for user {}: {}", user_id, e);
             }
         }
     }
+}
 
-    async fn remove_connection(
-        connection_id: ConnectionId,
-        connections: Connections,
-        user_connections: UserConnections,
-        message_tx: broadcast::Sender<WebSocketMessage>,
-    ) {
-        // Retrieve and remove user_id for this connection
-        let user_id = {
-            let mut connections_lock = connections.lock().await;
-            let user_id = connections_lock.get(&connection_id).and_then(|conn| conn.user_id);
-            connections_lock.remove(&connection_id);
-            debug!("Removed connection {} from connections", connection_id);
-            user_id
-        };
+/// Resolves once `WebSocketServer::shutdown` was called
+async fn stopped(shutdown: &mut watch::Receiver<bool>) {
+    // An error means the server was dropped, which stops everything too
+    let _ = shutdown.wait_for(|&stopped| stopped).await;
+}
 
-        // Handle user offline status if this was their last connection
-        if let Some(user_id) = user_id {
-            let mut user_connections_lock = user_connections.lock().await;
-            if let Some(connections) = user_connections_lock.get_mut(&user_id) {
-                connections.retain(|&id| id != connection_id);
-
-                if connections.is_empty() {
-                    user_connections_lock.remove(&user_id);
-                    debug!("User {} has no more connections, broadcasting offline status", user_id);
-
-                    let offline_message = WebSocketMessage {
-                        message_type: WebSocketMessageType::UserOffline,
-                        sender_id: Some(user_id),
-                        recipient_id: None,
-                        payload: serde_json::Value::Null,
-                        timestamp: OffsetDateTime::now_utc(),
-                    };
-
-                    // Use a generic error handler instead of specific type
-                    if let Err(e) = message_tx.send(offline_message) {
-                        error!("Failed to broadcast offline status for user {}: {}", user_id, e);
-                    }
-                }
-            }
-        }
+/// Extracts the session token from a `?token=...` query string
+fn token_from_query(query: Option<&str>) -> Option<String> {
+    query?.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == "token")
+        .map(|(_, token)| token.to_string())
+        .filter(|token| !token.is_empty())
+}
+
+/// An `error` message telling the client why its frame was rejected
+fn error_frame(reason: &str) -> Message {
+    let message = WebSocketMessage {
+        message_type: WebSocketMessageType::Error,
+        sender_id: None,
+        recipient_id: None,
+        payload: serde_json::json!({ "message": reason }),
+        timestamp: OffsetDateTime::now_utc(),
+    };
+    Message::Text(serde_json::to_string(&message).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::test_support::{insert_user, login, test_state};
+    use crate::websocket::test_support::{frame, listen, next_message};
+    use tokio_tungstenite::connect_async;
+
+    #[tokio::test]
+    async fn test_handshake_requires_session_token() -> Result<()> {
+        let state = test_state().await;
+        let alice = insert_user(&state).await;
+        let bob = insert_user(&state).await;
+        let token = login(&state, &alice).await;
+        let mut events = state.websocket.message_tx.subscribe();
+        let url = listen(state.websocket.clone()).await;
+
+        // A token in the query string authenticates the connection
+        let (mut client, _) = connect_async(format!("{}?token={}", url, token)).await?;
+        let authenticated = next_message(&mut client).await.unwrap();
+        assert_eq!(authenticated.message_type, WebSocketMessageType::Authenticated);
+        assert_eq!(authenticated.recipient_id, Some(alice.user_id));
+        let online = events.recv().await?;
+        assert_eq!((online.message_type, online.sender_id), (WebSocketMessageType::UserOnline, Some(alice.user_id)));
+
+        // The claimed sender is replaced with the verified user
+        client.send(frame(WebSocketMessageType::UserOnline, Some(bob.user_id), None, serde_json::Value::Null)).await?;
+        assert_eq!(events.recv().await?.sender_id, Some(alice.user_id));
+        client.send(frame(WebSocketMessageType::TypingIndicator, Some(bob.user_id), Some(alice.user_id), serde_json::Value::Null)).await?;
+        assert_eq!(next_message(&mut client).await.unwrap().sender_id, Some(alice.user_id));
+
+        // Or the first frame carries it
+        let (mut client, _) = connect_async(&url).await?;
+        client.send(frame(WebSocketMessageType::Authenticate, None, None, serde_json::json!({ "token": token }))).await?;
+        assert_eq!(next_message(&mut client).await.unwrap().recipient_id, Some(alice.user_id));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unauthenticated_connections_are_closed() -> Result<()> {
+        let state = test_state().await;
+        let bob = insert_user(&state).await;
+        let url = listen(state.websocket.clone()).await;
+
+        let (mut client, _) = connect_async(format!("{}?token=not-a-session", url)).await?;
+        assert_eq!(next_message(&mut client).await.unwrap().message_type, WebSocketMessageType::Error);
+        assert!(next_message(&mut client).await.is_none());
+
+        // Claiming to be a user in the first frame is not enough
+        let (mut client, _) = connect_async(&url).await?;
+        client.send(frame(WebSocketMessageType::UserOnline, Some(bob.user_id), None, serde_json::Value::Null)).await?;
+        let rejected = next_message(&mut client).await.unwrap();
+        assert_eq!(rejected.message_type, WebSocketMessageType::Error);
+        assert!(rejected.payload["message"].as_str().unwrap().contains("must authenticate"));
+        assert!(next_message(&mut client).await.is_none());
+        assert!(!state.hub.is_online(bob.user_id).await);
+        Ok(())
     }
 }